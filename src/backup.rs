@@ -0,0 +1,925 @@
+// jkcoxson
+// Reads iTunes and Finder style backups from disk, no device required
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use log::warn;
+use plist_plus::{Plist, PlistType};
+
+use crate::{
+    bindings as unsafe_bindings,
+    error::BackupError,
+    plist_helpers::{
+        array_items, data_bytes, dict_bool, dict_date, dict_item, dict_string, dict_uint,
+    },
+    sqlite::Database,
+};
+
+/// What an entry in the backup is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupFileKind {
+    File,
+    Directory,
+    Symlink,
+    Unknown,
+}
+
+impl From<i64> for BackupFileKind {
+    fn from(flags: i64) -> Self {
+        match flags {
+            1 => BackupFileKind::File,
+            2 => BackupFileKind::Directory,
+            4 => BackupFileKind::Symlink,
+            _ => BackupFileKind::Unknown,
+        }
+    }
+}
+
+/// An entry of Manifest.db
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupFile {
+    /// The hash the file is stored under in the backup folder
+    pub file_id: String,
+    /// The domain the file belongs to, such as `HomeDomain`
+    pub domain: String,
+    /// The path of the file relative to the domain
+    pub relative_path: String,
+    pub kind: BackupFileKind,
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+    pub mode: Option<u64>,
+    /// Where a symlink points to
+    pub link_target: Option<String>,
+    protection_class: Option<u32>,
+    encryption_key: Option<Vec<u8>>,
+}
+
+impl BackupFile {
+    fn from_row(
+        file_id: String,
+        domain: String,
+        relative_path: String,
+        flags: i64,
+        metadata: Option<&[u8]>,
+    ) -> Self {
+        let mut file = BackupFile {
+            file_id,
+            domain,
+            relative_path,
+            kind: flags.into(),
+            size: None,
+            modified: None,
+            mode: None,
+            link_target: None,
+            protection_class: None,
+            encryption_key: None,
+        };
+        if let Some(metadata) = metadata {
+            file.read_metadata(metadata);
+        }
+        file
+    }
+
+    /// Reads the NSKeyedArchiver encoded MBFile stored alongside each entry
+    fn read_metadata(&mut self, metadata: &[u8]) {
+        let archive = match Plist::from_memory(metadata.to_vec()) {
+            Ok(archive) => archive,
+            Err(_) => {
+                warn!("Unable to parse the metadata of {}", self.file_id);
+                return;
+            }
+        };
+        let objects = match dict_item(&archive, "$objects") {
+            Some(objects) => array_items(&objects),
+            None => return,
+        };
+        let root = dict_item(&archive, "$top")
+            .and_then(|top| uid(&top, "root"))
+            .and_then(|root| objects.get(root));
+        let root = match root {
+            Some(root) if root.plist_type == PlistType::Dictionary => root,
+            _ => return,
+        };
+
+        self.size = dict_uint(root, "Size");
+        self.modified = dict_uint(root, "LastModified")
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        self.mode = dict_uint(root, "Mode");
+        self.protection_class = dict_uint(root, "ProtectionClass").map(|class| class as u32);
+        self.encryption_key = uid(root, "EncryptionKey")
+            .and_then(|key| objects.get(key))
+            .and_then(|key| dict_item(key, "NS.data"))
+            .and_then(|key| data_bytes(&key));
+        self.link_target = uid(root, "Target")
+            .and_then(|target| objects.get(target))
+            .filter(|target| target.plist_type == PlistType::String)
+            .and_then(|target| target.get_string_val().ok());
+    }
+}
+
+/// Reads a backup made by iTunes, Finder or the backup engine.
+/// Only backups with a Manifest.db (iOS 10 and later) are supported.
+pub struct BackupReader {
+    path: PathBuf,
+    info: Option<Plist>,
+    status: Option<Plist>,
+    manifest: Plist,
+    files: Vec<BackupFile>,
+    #[cfg(feature = "openssl")]
+    keybag: Option<crypto::Keybag>,
+}
+
+impl BackupReader {
+    /// Opens an unencrypted backup
+    /// # Arguments
+    /// * `path` - The backup folder, usually named after the device's UDID
+    /// # Returns
+    /// A reader for the backup
+    ///
+    /// ***Verified:*** False
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, BackupError> {
+        let path = path.into();
+        let manifest = read_plist(&path.join("Manifest.plist"))?;
+        if dict_bool(&manifest, "IsEncrypted").unwrap_or(false) {
+            return Err(BackupError::Encrypted);
+        }
+        let database = fs::read(path.join("Manifest.db")).map_err(io_error)?;
+        Self::load(path, manifest, database)
+    }
+
+    /// Opens an encrypted backup, unlocking its keybag with the backup password
+    /// # Arguments
+    /// * `path` - The backup folder, usually named after the device's UDID
+    /// * `password` - The password the backup was encrypted with
+    /// # Returns
+    /// A reader for the backup
+    ///
+    /// ***Verified:*** False
+    #[cfg(feature = "openssl")]
+    pub fn open_encrypted(
+        path: impl Into<PathBuf>,
+        password: impl AsRef<[u8]>,
+    ) -> Result<Self, BackupError> {
+        let path = path.into();
+        let manifest = read_plist(&path.join("Manifest.plist"))?;
+        if !dict_bool(&manifest, "IsEncrypted").unwrap_or(false) {
+            return Self::open(path);
+        }
+
+        let keybag = dict_item(&manifest, "BackupKeyBag")
+            .and_then(|keybag| data_bytes(&keybag))
+            .ok_or(BackupError::InvalidManifest)?;
+        let mut keybag = crypto::Keybag::parse(&keybag)?;
+        keybag.unlock(password.as_ref())?;
+
+        let manifest_key = dict_item(&manifest, "ManifestKey")
+            .and_then(|key| data_bytes(&key))
+            .filter(|key| key.len() > 4)
+            .ok_or(BackupError::InvalidManifest)?;
+        let class = u32::from_le_bytes([
+            manifest_key[0],
+            manifest_key[1],
+            manifest_key[2],
+            manifest_key[3],
+        ]);
+        let key = keybag.unwrap_key(class, &manifest_key[4..])?;
+        let database = fs::read(path.join("Manifest.db")).map_err(io_error)?;
+        let database = crypto::decrypt(&key, &database)?;
+
+        let mut reader = Self::load(path, manifest, database)?;
+        reader.keybag = Some(keybag);
+        Ok(reader)
+    }
+
+    fn load(path: PathBuf, manifest: Plist, database: Vec<u8>) -> Result<Self, BackupError> {
+        let table = Database::from_bytes(database)
+            .and_then(|database| database.table("Files"))
+            .map_err(|e| {
+                warn!("Unable to read Manifest.db: {}", e);
+                BackupError::DatabaseError
+            })?;
+        let column = |name| table.column(name).ok_or(BackupError::DatabaseError);
+        let (file_id, domain, relative_path, flags) = (
+            column("fileID")?,
+            column("domain")?,
+            column("relativePath")?,
+            column("flags")?,
+        );
+        let metadata = table.column("file");
+
+        let files = table
+            .rows
+            .iter()
+            .map(|row| {
+                let text = |index: usize| {
+                    row.get(index)
+                        .and_then(|value| value.as_str())
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                BackupFile::from_row(
+                    text(file_id),
+                    text(domain),
+                    text(relative_path),
+                    row.get(flags)
+                        .and_then(|value| value.as_integer())
+                        .unwrap_or_default(),
+                    metadata
+                        .and_then(|metadata| row.get(metadata))
+                        .and_then(|value| value.as_blob()),
+                )
+            })
+            .collect();
+
+        Ok(BackupReader {
+            info: read_plist(&path.join("Info.plist")).ok(),
+            status: read_plist(&path.join("Status.plist")).ok(),
+            path,
+            manifest,
+            files,
+            #[cfg(feature = "openssl")]
+            keybag: None,
+        })
+    }
+
+    /// Gets the folder the backup is stored in
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gets the contents of Info.plist, written by the host that made the backup
+    pub fn info(&self) -> Option<&Plist> {
+        self.info.as_ref()
+    }
+
+    /// Gets the contents of Status.plist, written by the device
+    pub fn status(&self) -> Option<&Plist> {
+        self.status.as_ref()
+    }
+
+    /// Gets the contents of Manifest.plist
+    pub fn manifest(&self) -> &Plist {
+        &self.manifest
+    }
+
+    /// Whether the backup's files are encrypted
+    pub fn is_encrypted(&self) -> bool {
+        dict_bool(&self.manifest, "IsEncrypted").unwrap_or(false)
+    }
+
+    /// Whether the last backup was a full backup
+    pub fn is_full_backup(&self) -> Option<bool> {
+        dict_bool(self.status.as_ref()?, "IsFullBackup")
+    }
+
+    /// Gets when the backup was made
+    pub fn date(&self) -> Option<SystemTime> {
+        self.status
+            .as_ref()
+            .and_then(|status| dict_date(status, "Date"))
+            .or_else(|| dict_date(&self.manifest, "Date"))
+    }
+
+    /// Gets the name of the device that was backed up
+    pub fn device_name(&self) -> Option<String> {
+        self.info
+            .as_ref()
+            .and_then(|info| dict_string(info, "Device Name"))
+            .or_else(|| self.lockdown_string("DeviceName"))
+    }
+
+    /// Gets the UDID of the device that was backed up
+    pub fn udid(&self) -> Option<String> {
+        self.info
+            .as_ref()
+            .and_then(|info| dict_string(info, "Target Identifier"))
+            .or_else(|| self.lockdown_string("UniqueDeviceID"))
+    }
+
+    /// Gets the iOS version of the device when it was backed up
+    pub fn product_version(&self) -> Option<String> {
+        self.info
+            .as_ref()
+            .and_then(|info| dict_string(info, "Product Version"))
+            .or_else(|| self.lockdown_string("ProductVersion"))
+    }
+
+    fn lockdown_string(&self, key: &str) -> Option<String> {
+        dict_string(&dict_item(&self.manifest, "Lockdown")?, key)
+    }
+
+    /// Gets every entry in the backup
+    pub fn files(&self) -> &[BackupFile] {
+        &self.files
+    }
+
+    /// Lists the domains in the backup, sorted by name
+    pub fn domains(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .map(|file| file.domain.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Lists the entries of a single domain
+    pub fn files_in_domain<'a>(
+        &'a self,
+        domain: &'a str,
+    ) -> impl Iterator<Item = &'a BackupFile> + 'a {
+        self.files.iter().filter(move |file| file.domain == domain)
+    }
+
+    /// Finds an entry by its domain and relative path
+    pub fn find(&self, domain: &str, relative_path: &str) -> Option<&BackupFile> {
+        self.files
+            .iter()
+            .find(|file| file.domain == domain && file.relative_path == relative_path)
+    }
+
+    /// Reads the contents of a file, decrypting it if needed
+    /// # Arguments
+    /// * `file` - The entry to read
+    /// # Returns
+    /// The contents of the file
+    ///
+    /// ***Verified:*** False
+    pub fn read_file(&self, file: &BackupFile) -> Result<Vec<u8>, BackupError> {
+        if file.kind != BackupFileKind::File {
+            return Err(BackupError::NotAFile);
+        }
+        let prefix = file.file_id.get(..2).ok_or(BackupError::FileNotFound)?;
+        let data = fs::read(self.path.join(prefix).join(&file.file_id)).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                BackupError::FileNotFound
+            } else {
+                io_error(e)
+            }
+        })?;
+        if !self.is_encrypted() {
+            return Ok(data);
+        }
+        self.decrypt_file(file, &data)
+    }
+
+    #[cfg(feature = "openssl")]
+    fn decrypt_file(&self, file: &BackupFile, data: &[u8]) -> Result<Vec<u8>, BackupError> {
+        let keybag = self.keybag.as_ref().ok_or(BackupError::Encrypted)?;
+        let (class, wrapped) = match (file.protection_class, &file.encryption_key) {
+            (Some(class), Some(key)) if key.len() > 4 => (class, &key[4..]),
+            _ => return Err(BackupError::DecryptionFailed),
+        };
+        let key = keybag.unwrap_key(class, wrapped)?;
+        let mut data = crypto::decrypt(&key, data)?;
+        if let Some(size) = file.size {
+            data.truncate(size as usize);
+        }
+        Ok(data)
+    }
+
+    #[cfg(not(feature = "openssl"))]
+    fn decrypt_file(&self, _file: &BackupFile, _data: &[u8]) -> Result<Vec<u8>, BackupError> {
+        Err(BackupError::Encrypted)
+    }
+
+    /// Reads a file by its domain and relative path
+    /// # Arguments
+    /// * `domain` - The domain of the file
+    /// * `relative_path` - The path of the file in the domain
+    /// # Returns
+    /// The contents of the file
+    ///
+    /// ***Verified:*** False
+    pub fn read(&self, domain: &str, relative_path: &str) -> Result<Vec<u8>, BackupError> {
+        let file = self
+            .find(domain, relative_path)
+            .ok_or(BackupError::FileNotFound)?;
+        self.read_file(file)
+    }
+
+    /// Copies a file out of the backup
+    /// # Arguments
+    /// * `domain` - The domain of the file
+    /// * `relative_path` - The path of the file in the domain
+    /// * `dest` - Where to write the file
+    /// # Returns
+    /// The number of bytes written
+    ///
+    /// ***Verified:*** False
+    pub fn extract(
+        &self,
+        domain: &str,
+        relative_path: &str,
+        dest: impl AsRef<Path>,
+    ) -> Result<u64, BackupError> {
+        let file = self
+            .find(domain, relative_path)
+            .ok_or(BackupError::FileNotFound)?;
+        let data = self.read_file(file)?;
+        let dest = dest.as_ref();
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        fs::write(dest, &data).map_err(io_error)?;
+        if let Some(modified) = file.modified {
+            let _ = fs::File::options()
+                .write(true)
+                .open(dest)
+                .and_then(|dest| dest.set_modified(modified));
+        }
+        Ok(data.len() as u64)
+    }
+}
+
+#[cfg(feature = "openssl")]
+mod crypto {
+    use std::collections::HashMap;
+
+    use log::warn;
+    use openssl::{
+        aes::{unwrap_key, AesKey},
+        hash::MessageDigest,
+        pkcs5::pbkdf2_hmac,
+        symm::{Cipher, Crypter, Mode},
+    };
+
+    use crate::error::BackupError;
+
+    /// Set on class keys that are wrapped with the passcode key
+    const WRAP_PASSCODE: u32 = 2;
+
+    struct ClassKey {
+        wrap: u32,
+        wrapped: Vec<u8>,
+        key: Option<Vec<u8>>,
+    }
+
+    /// The backup keybag, holding the keys for each protection class
+    pub(super) struct Keybag {
+        salt: Vec<u8>,
+        iterations: u32,
+        double_protection_salt: Option<Vec<u8>>,
+        double_protection_iterations: u32,
+        class_keys: HashMap<u32, ClassKey>,
+    }
+
+    impl Keybag {
+        /// Parses the tag, length, value list of a keybag
+        pub(super) fn parse(data: &[u8]) -> Result<Self, BackupError> {
+            let mut keybag = Keybag {
+                salt: Vec::new(),
+                iterations: 0,
+                double_protection_salt: None,
+                double_protection_iterations: 0,
+                class_keys: HashMap::new(),
+            };
+            let mut seen_uuid = false;
+            let mut current: Option<(Option<u32>, ClassKey)> = None;
+
+            let mut offset = 0;
+            while offset + 8 <= data.len() {
+                let tag = &data[offset..offset + 4];
+                let len = u32::from_be_bytes([
+                    data[offset + 4],
+                    data[offset + 5],
+                    data[offset + 6],
+                    data[offset + 7],
+                ]) as usize;
+                let value = data
+                    .get(offset + 8..offset + 8 + len)
+                    .ok_or(BackupError::InvalidKeybag)?;
+                offset += 8 + len;
+                let number = || {
+                    value
+                        .try_into()
+                        .map(u32::from_be_bytes)
+                        .map_err(|_| BackupError::InvalidKeybag)
+                };
+
+                match (tag, current.as_mut()) {
+                    // The first UUID belongs to the keybag, every following one starts a class key
+                    (b"UUID", _) if !seen_uuid => seen_uuid = true,
+                    (b"UUID", _) => {
+                        keybag.push(current.take());
+                        current = Some((
+                            None,
+                            ClassKey {
+                                wrap: 0,
+                                wrapped: Vec::new(),
+                                key: None,
+                            },
+                        ));
+                    }
+                    (b"CLAS", Some((class, _))) => *class = Some(number()?),
+                    (b"WRAP", Some((_, key))) => key.wrap = number()?,
+                    (b"WPKY", Some((_, key))) => key.wrapped = value.to_vec(),
+                    (b"SALT", None) => keybag.salt = value.to_vec(),
+                    (b"ITER", None) => keybag.iterations = number()?,
+                    (b"DPSL", None) => keybag.double_protection_salt = Some(value.to_vec()),
+                    (b"DPIC", None) => keybag.double_protection_iterations = number()?,
+                    _ => {}
+                }
+            }
+            keybag.push(current);
+
+            if keybag.salt.is_empty() || keybag.iterations == 0 || keybag.class_keys.is_empty() {
+                return Err(BackupError::InvalidKeybag);
+            }
+            Ok(keybag)
+        }
+
+        fn push(&mut self, class_key: Option<(Option<u32>, ClassKey)>) {
+            if let Some((Some(class), key)) = class_key {
+                self.class_keys.insert(class, key);
+            }
+        }
+
+        /// Derives the passcode key from the password and unwraps the class keys with it
+        pub(super) fn unlock(&mut self, password: &[u8]) -> Result<(), BackupError> {
+            let mut password = password.to_vec();
+            // Newer backups run the password through a second round of PBKDF2 first
+            if let Some(salt) = &self.double_protection_salt {
+                let mut derived = vec![0; 32];
+                pbkdf2_hmac(
+                    &password,
+                    salt,
+                    self.double_protection_iterations as usize,
+                    MessageDigest::sha256(),
+                    &mut derived,
+                )
+                .map_err(|_| BackupError::DecryptionFailed)?;
+                password = derived;
+            }
+            let mut passcode_key = vec![0; 32];
+            pbkdf2_hmac(
+                &password,
+                &self.salt,
+                self.iterations as usize,
+                MessageDigest::sha1(),
+                &mut passcode_key,
+            )
+            .map_err(|_| BackupError::DecryptionFailed)?;
+
+            for (class, class_key) in self.class_keys.iter_mut() {
+                if class_key.wrap & WRAP_PASSCODE == 0 {
+                    continue;
+                }
+                match aes_unwrap(&passcode_key, &class_key.wrapped) {
+                    Some(key) => class_key.key = Some(key),
+                    None => {
+                        warn!("Unable to unwrap the key for protection class {}", class);
+                        return Err(BackupError::WrongPassword);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Unwraps a key that was wrapped with a protection class key
+        pub(super) fn unwrap_key(
+            &self,
+            class: u32,
+            wrapped: &[u8],
+        ) -> Result<Vec<u8>, BackupError> {
+            let class_key = self
+                .class_keys
+                .get(&class)
+                .and_then(|class_key| class_key.key.as_ref())
+                .ok_or(BackupError::DecryptionFailed)?;
+            aes_unwrap(class_key, wrapped).ok_or(BackupError::DecryptionFailed)
+        }
+    }
+
+    /// RFC 3394 AES key unwrapping
+    fn aes_unwrap(key: &[u8], wrapped: &[u8]) -> Option<Vec<u8>> {
+        if wrapped.len() < 16 || !wrapped.len().is_multiple_of(8) {
+            return None;
+        }
+        let key = AesKey::new_decrypt(key).ok()?;
+        let mut unwrapped = vec![0; wrapped.len() - 8];
+        unwrap_key(&key, None, &mut unwrapped, wrapped).ok()?;
+        Some(unwrapped)
+    }
+
+    /// Decrypts AES-256-CBC data with a zero IV, as used for backup files
+    pub(super) fn decrypt(key: &[u8], data: &[u8]) -> Result<Vec<u8>, BackupError> {
+        let cipher = Cipher::aes_256_cbc();
+        let mut crypter = Crypter::new(cipher, Mode::Decrypt, key, Some(&[0; 16]))
+            .map_err(|_| BackupError::DecryptionFailed)?;
+        // Padding is removed by hand, some files are stored without it
+        crypter.pad(false);
+        let mut decrypted = vec![0; data.len() + cipher.block_size()];
+        let mut len = crypter
+            .update(data, &mut decrypted)
+            .map_err(|_| BackupError::DecryptionFailed)?;
+        len += crypter
+            .finalize(&mut decrypted[len..])
+            .map_err(|_| BackupError::DecryptionFailed)?;
+        decrypted.truncate(len);
+
+        if let Some(&pad) = decrypted.last() {
+            let pad = pad as usize;
+            if (1..=16).contains(&pad)
+                && pad <= decrypted.len()
+                && decrypted[decrypted.len() - pad..]
+                    .iter()
+                    .all(|byte| *byte as usize == pad)
+            {
+                decrypted.truncate(decrypted.len() - pad);
+            }
+        }
+        Ok(decrypted)
+    }
+}
+
+/// Gets the index a UID in a keyed archive points to
+fn uid(dict: &Plist, key: &str) -> Option<usize> {
+    let item = dict.dict_get_item(key).ok()?;
+    if item.plist_type != PlistType::Uid {
+        return None;
+    }
+    // plist_plus 0.2 ships `Plist::get_uid_val` in types/uid.rs but never declares the module,
+    // so the accessor isn't compiled in and libplist has to be asked directly
+    let mut uid = 0;
+    unsafe { unsafe_bindings::plist_get_uid_val(item.get_pointer(), &mut uid) };
+    usize::try_from(uid).ok()
+}
+
+fn read_plist(path: &Path) -> Result<Plist, BackupError> {
+    let data = fs::read(path).map_err(io_error)?;
+    Plist::from_memory(data).map_err(|_| BackupError::PlistError)
+}
+
+fn io_error(error: std::io::Error) -> BackupError {
+    warn!("Unable to read backup: {}", error);
+    BackupError::IoError
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTES: &[u8] = b"Hello from the backup\n";
+    const PHOTO: &[u8] = b"\xff\xd8\xff\xe0 not a photo";
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/backups")
+            .join(name)
+    }
+
+    fn assert_contents(reader: &BackupReader) {
+        assert_eq!(
+            reader.read("HomeDomain", "Library/Notes.txt").unwrap(),
+            NOTES
+        );
+        assert_eq!(
+            reader.read("HomeDomain", "Library/Block.bin").unwrap(),
+            (0..32).collect::<Vec<u8>>()
+        );
+        assert_eq!(
+            reader
+                .read("CameraRollDomain", "Media/DCIM/IMG_0001.JPG")
+                .unwrap(),
+            PHOTO
+        );
+    }
+
+    #[test]
+    fn reads_plain_backup() {
+        let reader = BackupReader::open(fixture("plain")).unwrap();
+        assert!(!reader.is_encrypted());
+        assert_eq!(reader.is_full_backup(), Some(true));
+        assert_eq!(reader.device_name().as_deref(), Some("Fixture iPhone"));
+        assert_eq!(reader.udid().as_deref(), Some("00008030-0000000000000000"));
+        assert_eq!(reader.product_version().as_deref(), Some("15.4"));
+        // 2022-05-01 12:00:00 UTC
+        assert_eq!(
+            reader.date(),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1651406400))
+        );
+        assert_eq!(reader.files().len(), 5);
+        assert_eq!(reader.domains(), ["CameraRollDomain", "HomeDomain"]);
+        assert_eq!(reader.files_in_domain("HomeDomain").count(), 4);
+        assert_contents(&reader);
+    }
+
+    #[test]
+    fn reads_file_metadata() {
+        let reader = BackupReader::open(fixture("plain")).unwrap();
+        let notes = reader.find("HomeDomain", "Library/Notes.txt").unwrap();
+        assert_eq!(notes.file_id, "0960a2d96f7126876eacfd89cdfcadb08e6113fb");
+        assert_eq!(notes.kind, BackupFileKind::File);
+        assert_eq!(notes.size, Some(NOTES.len() as u64));
+        assert_eq!(notes.mode, Some(0o100644));
+        assert_eq!(
+            notes.modified,
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1600000000))
+        );
+
+        let link = reader.find("HomeDomain", "Library/Link").unwrap();
+        assert_eq!(link.kind, BackupFileKind::Symlink);
+        assert_eq!(link.link_target.as_deref(), Some("Notes.txt"));
+
+        let directory = reader.find("HomeDomain", "Library").unwrap();
+        assert_eq!(directory.kind, BackupFileKind::Directory);
+        assert_eq!(reader.read_file(directory), Err(BackupError::NotAFile));
+        assert_eq!(
+            reader.read("HomeDomain", "Library/Missing"),
+            Err(BackupError::FileNotFound)
+        );
+    }
+
+    #[test]
+    fn extracts_files() {
+        let reader = BackupReader::open(fixture("plain")).unwrap();
+        let dest = std::env::temp_dir()
+            .join(format!("rusty_libimobiledevice-{}", std::process::id()))
+            .join("Library/Notes.txt");
+        let written = reader
+            .extract("HomeDomain", "Library/Notes.txt", &dest)
+            .unwrap();
+        assert_eq!(written, NOTES.len() as u64);
+        assert_eq!(fs::read(&dest).unwrap(), NOTES);
+        let _ = fs::remove_dir_all(dest.parent().unwrap().parent().unwrap());
+    }
+
+    #[test]
+    fn refuses_encrypted_backup_without_password() {
+        assert_eq!(
+            BackupReader::open(fixture("encrypted")).err(),
+            Some(BackupError::Encrypted)
+        );
+    }
+
+    #[test]
+    fn missing_backup() {
+        assert_eq!(
+            BackupReader::open(fixture("missing")).err(),
+            Some(BackupError::IoError)
+        );
+    }
+
+    #[cfg(feature = "openssl")]
+    #[test]
+    fn reads_encrypted_backup() {
+        let reader = BackupReader::open_encrypted(fixture("encrypted"), "password").unwrap();
+        assert!(reader.is_encrypted());
+        // There's no Info.plist, so these come from the manifest's Lockdown dictionary
+        assert_eq!(reader.device_name().as_deref(), Some("Fixture iPhone"));
+        assert_eq!(reader.product_version().as_deref(), Some("15.4"));
+        assert_eq!(reader.files().len(), 5);
+        assert_contents(&reader);
+    }
+
+    #[cfg(feature = "openssl")]
+    #[test]
+    fn rejects_wrong_password() {
+        assert_eq!(
+            BackupReader::open_encrypted(fixture("encrypted"), "hunter2").err(),
+            Some(BackupError::WrongPassword)
+        );
+    }
+
+    #[cfg(feature = "openssl")]
+    #[test]
+    fn opens_plain_backup_with_password() {
+        let reader = BackupReader::open_encrypted(fixture("plain"), "password").unwrap();
+        assert_contents(&reader);
+    }
+
+    #[cfg(feature = "openssl")]
+    mod crypto {
+        use openssl::{
+            aes::{wrap_key, AesKey},
+            symm::{encrypt, Cipher},
+        };
+
+        use super::super::crypto::*;
+        use crate::error::BackupError;
+
+        fn tlv(tag: &[u8], value: &[u8]) -> Vec<u8> {
+            let mut data = tag.to_vec();
+            data.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            data.extend_from_slice(value);
+            data
+        }
+
+        fn wrap(kek: &[u8], key: &[u8]) -> Vec<u8> {
+            let kek = AesKey::new_encrypt(kek).unwrap();
+            let mut wrapped = vec![0; key.len() + 8];
+            wrap_key(&kek, None, &mut wrapped, key).unwrap();
+            wrapped
+        }
+
+        /// A keybag without double protection, with class 3 wrapped by the passcode key
+        fn keybag(class_key: &[u8], password: &[u8]) -> Vec<u8> {
+            let salt = [7; 20];
+            let mut passcode_key = [0; 32];
+            openssl::pkcs5::pbkdf2_hmac(
+                password,
+                &salt,
+                1,
+                openssl::hash::MessageDigest::sha1(),
+                &mut passcode_key,
+            )
+            .unwrap();
+            [
+                tlv(b"VERS", &3u32.to_be_bytes()),
+                tlv(b"UUID", &[0; 16]),
+                tlv(b"SALT", &salt),
+                tlv(b"ITER", &1u32.to_be_bytes()),
+                tlv(b"UUID", &[3; 16]),
+                tlv(b"CLAS", &3u32.to_be_bytes()),
+                tlv(b"WRAP", &2u32.to_be_bytes()),
+                tlv(b"WPKY", &wrap(&passcode_key, class_key)),
+            ]
+            .concat()
+        }
+
+        #[test]
+        fn unwraps_keys() {
+            let class_key = [0x33; 32];
+            let file_key = [0x42; 32];
+            let mut keybag = Keybag::parse(&keybag(&class_key, b"secret")).unwrap();
+            // Nothing can be unwrapped before the keybag is unlocked
+            assert_eq!(
+                keybag.unwrap_key(3, &wrap(&class_key, &file_key)),
+                Err(BackupError::DecryptionFailed)
+            );
+            keybag.unlock(b"secret").unwrap();
+            assert_eq!(
+                keybag.unwrap_key(3, &wrap(&class_key, &file_key)).unwrap(),
+                file_key
+            );
+            assert_eq!(
+                keybag.unwrap_key(4, &wrap(&class_key, &file_key)),
+                Err(BackupError::DecryptionFailed)
+            );
+        }
+
+        #[test]
+        fn wrong_password() {
+            let mut keybag = Keybag::parse(&keybag(&[0x33; 32], b"secret")).unwrap();
+            assert_eq!(keybag.unlock(b"guess"), Err(BackupError::WrongPassword));
+        }
+
+        #[test]
+        fn rejects_invalid_keybags() {
+            assert_eq!(Keybag::parse(&[]).err(), Some(BackupError::InvalidKeybag));
+            // A length running past the end
+            let mut truncated = tlv(b"SALT", &[1; 20]);
+            truncated.truncate(16);
+            assert_eq!(
+                Keybag::parse(&truncated).err(),
+                Some(BackupError::InvalidKeybag)
+            );
+            // No class keys
+            let keybag = [tlv(b"SALT", &[1; 20]), tlv(b"ITER", &1u32.to_be_bytes())].concat();
+            assert_eq!(
+                Keybag::parse(&keybag).err(),
+                Some(BackupError::InvalidKeybag)
+            );
+        }
+
+        #[test]
+        fn decrypts_cbc() {
+            let key = [9; 32];
+            for len in [0, 5, 16, 33] {
+                let plain: Vec<u8> = (0..len as u8).collect();
+                let encrypted =
+                    encrypt(Cipher::aes_256_cbc(), &key, Some(&[0; 16]), &plain).unwrap();
+                assert_eq!(decrypt(&key, &encrypted).unwrap(), plain);
+            }
+        }
+
+        #[test]
+        fn decrypts_unpadded_cbc() {
+            // Ends in 0x20, which isn't valid padding, so nothing is stripped
+            let key = [9; 32];
+            let plain = [0x20; 32];
+            let mut encrypted =
+                encrypt(Cipher::aes_256_cbc(), &key, Some(&[0; 16]), &plain).unwrap();
+            encrypted.truncate(32);
+            assert_eq!(decrypt(&key, &encrypted).unwrap(), plain);
+        }
+    }
+
+    #[test]
+    fn reads_uids() {
+        let mut dict = Plist::new_dict();
+        let root = Plist::from(unsafe { unsafe_bindings::plist_new_uid(7) });
+        dict.dict_set_item("root", root).unwrap();
+        dict.dict_set_item("name", Plist::new_string("root"))
+            .unwrap();
+        assert_eq!(uid(&dict, "root"), Some(7));
+        assert_eq!(uid(&dict, "name"), None);
+        assert_eq!(uid(&dict, "missing"), None);
+    }
+}
@@ -46,6 +46,33 @@ impl DeviceConnection<'_> {
         })
     }
 
+    /// Create a connection to an iOS device, leaving the device usable for other connections
+    /// # Arguments
+    /// * `device` - The device to create a connection to
+    /// * `port` - The port to connect to
+    /// # Returns
+    /// A handle for the connection, which can't outlive the device
+    ///
+    /// ***Verified:*** False
+    pub fn connect_borrowed<'a>(
+        device: &'a Device,
+        port: u16,
+    ) -> Result<DeviceConnection<'a>, IdeviceError> {
+        let mut to_fill = unsafe { std::mem::zeroed() };
+
+        let result =
+            unsafe { unsafe_bindings::idevice_connect(device.pointer, port, &mut to_fill) }.into();
+
+        if result != IdeviceError::Success {
+            return Err(result);
+        }
+
+        Ok(DeviceConnection {
+            pointer: to_fill,
+            phantom: std::marker::PhantomData,
+        })
+    }
+
     /// Sends data to the device
     /// # Arguments
     /// * `data` - The data to send
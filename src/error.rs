@@ -645,6 +645,11 @@ pub enum WebInspectorError {
     ReceiveTimeout,
     NotEnoughData,
     UnknownError,
+    // Internal errors
+    ApplicationNotFound,
+    PageNotFound,
+    InvalidMessage,
+    IoError,
 }
 
 impl std::error::Error for WebInspectorError {}
@@ -675,6 +680,10 @@ impl std::fmt::Display for WebInspectorError {
             WebInspectorError::ReceiveTimeout => "ReceiveTimeout",
             WebInspectorError::NotEnoughData => "NotEnoughData",
             WebInspectorError::UnknownError => "UnknownError",
+            WebInspectorError::ApplicationNotFound => "ApplicationNotFound",
+            WebInspectorError::PageNotFound => "PageNotFound",
+            WebInspectorError::InvalidMessage => "InvalidMessage",
+            WebInspectorError::IoError => "IoError",
         })
     }
 }
@@ -742,6 +751,11 @@ pub enum ScreenshotrError {
     ReceiveTimeout,
     BadVersion,
     UnknownError,
+    // Internal errors
+    UnsupportedFormat,
+    ConversionFailed,
+    IoError,
+    EmptyScreenshot,
 }
 
 impl std::error::Error for ScreenshotrError {}
@@ -772,6 +786,10 @@ impl std::fmt::Display for ScreenshotrError {
             ScreenshotrError::ReceiveTimeout => "ReceiveTimeout",
             ScreenshotrError::BadVersion => "BadVersion",
             ScreenshotrError::UnknownError => "UnknownError",
+            ScreenshotrError::UnsupportedFormat => "UnsupportedFormat",
+            ScreenshotrError::ConversionFailed => "ConversionFailed",
+            ScreenshotrError::IoError => "IoError",
+            ScreenshotrError::EmptyScreenshot => "EmptyScreenshot",
         })
     }
 }
@@ -789,6 +807,11 @@ pub enum SbservicesError {
     PlistError,
     ConnFailed,
     UnknownError,
+    // Internal errors
+    InvalidLayout,
+    IconNotFound,
+    LookupFailed,
+    IoError,
 }
 
 impl std::error::Error for SbservicesError {}
@@ -813,6 +836,10 @@ impl std::fmt::Display for SbservicesError {
             SbservicesError::PlistError => "PlistError",
             SbservicesError::ConnFailed => "ConnFailed",
             SbservicesError::UnknownError => "UnknownError",
+            SbservicesError::InvalidLayout => "InvalidLayout",
+            SbservicesError::IconNotFound => "IconNotFound",
+            SbservicesError::LookupFailed => "LookupFailed",
+            SbservicesError::IoError => "IoError",
         })
     }
 }
@@ -882,6 +909,10 @@ pub enum RestoredError {
     NotEnoughData,
     ReceiveTimeout,
     UnknownError,
+    // Internal errors
+    UnsupportedDataRequest,
+    InvalidMessage,
+    DeviceCrashed,
 }
 
 impl std::error::Error for RestoredError {}
@@ -910,6 +941,9 @@ impl std::fmt::Display for RestoredError {
             RestoredError::NotEnoughData => "NotEnoughData",
             RestoredError::ReceiveTimeout => "ReceiveTimeout",
             RestoredError::UnknownError => "UnknownError",
+            RestoredError::UnsupportedDataRequest => "UnsupportedDataRequest",
+            RestoredError::InvalidMessage => "InvalidMessage",
+            RestoredError::DeviceCrashed => "DeviceCrashed",
         })
     }
 }
@@ -931,6 +965,9 @@ pub enum PreboardError {
     Timeout,
     OpInProgress,
     UnknownError,
+    // Internal errors
+    DialogTimedOut,
+    StashbagFailed,
 }
 
 impl std::error::Error for PreboardError {}
@@ -963,6 +1000,8 @@ impl std::fmt::Display for PreboardError {
             PreboardError::Timeout => "Timeout",
             PreboardError::OpInProgress => "OpInProgress",
             PreboardError::UnknownError => "UnknownError",
+            PreboardError::DialogTimedOut => "DialogTimedOut",
+            PreboardError::StashbagFailed => "StashbagFailed",
         })
     }
 }
@@ -1088,6 +1127,17 @@ pub enum MobileBackup2Error {
     ReplyNotOk,
     NoCommonVersion,
     UnknownError,
+    // Internal errors
+    LockdowndFailed,
+    IoError,
+    Disconnected,
+    DeviceError,
+    InvalidResponse,
+    Incomplete,
+    BackupNotFound,
+    PasswordRequired,
+    EncryptionAlreadyEnabled,
+    EncryptionNotEnabled,
 }
 
 impl std::error::Error for MobileBackup2Error {}
@@ -1122,6 +1172,16 @@ impl std::fmt::Display for MobileBackup2Error {
             MobileBackup2Error::ReplyNotOk => "ReplyNotOk",
             MobileBackup2Error::NoCommonVersion => "NoCommonVersion",
             MobileBackup2Error::UnknownError => "UnknownError",
+            MobileBackup2Error::LockdowndFailed => "LockdowndFailed",
+            MobileBackup2Error::IoError => "IoError",
+            MobileBackup2Error::Disconnected => "Disconnected",
+            MobileBackup2Error::DeviceError => "DeviceError",
+            MobileBackup2Error::InvalidResponse => "InvalidResponse",
+            MobileBackup2Error::Incomplete => "Incomplete",
+            MobileBackup2Error::BackupNotFound => "BackupNotFound",
+            MobileBackup2Error::PasswordRequired => "PasswordRequired",
+            MobileBackup2Error::EncryptionAlreadyEnabled => "EncryptionAlreadyEnabled",
+            MobileBackup2Error::EncryptionNotEnabled => "EncryptionNotEnabled",
         })
     }
 }
@@ -1143,6 +1203,13 @@ pub enum MobileBackupError {
     BadVersion,
     ReplyNotOk,
     UnknownError,
+    // Internal errors
+    IoError,
+    Disconnected,
+    DeviceError,
+    InvalidResponse,
+    LockdowndFailed,
+    BackupNotFound,
 }
 
 impl std::error::Error for MobileBackupError {}
@@ -1175,6 +1242,12 @@ impl std::fmt::Display for MobileBackupError {
             MobileBackupError::BadVersion => "BadVersion",
             MobileBackupError::ReplyNotOk => "ReplyNotOk",
             MobileBackupError::UnknownError => "UnknownError",
+            MobileBackupError::IoError => "IoError",
+            MobileBackupError::Disconnected => "Disconnected",
+            MobileBackupError::DeviceError => "DeviceError",
+            MobileBackupError::InvalidResponse => "InvalidResponse",
+            MobileBackupError::LockdowndFailed => "LockdowndFailed",
+            MobileBackupError::BackupNotFound => "BackupNotFound",
         })
     }
 }
@@ -1185,6 +1258,70 @@ impl From<MobileBackupError> for String {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupError {
+    IoError,
+    PlistError,
+    InvalidManifest,
+    DatabaseError,
+    Encrypted,
+    InvalidKeybag,
+    WrongPassword,
+    DecryptionFailed,
+    FileNotFound,
+    NotAFile,
+}
+
+impl std::error::Error for BackupError {}
+
+impl std::fmt::Display for BackupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BackupError::IoError => "IoError",
+            BackupError::PlistError => "PlistError",
+            BackupError::InvalidManifest => "InvalidManifest",
+            BackupError::DatabaseError => "DatabaseError",
+            BackupError::Encrypted => "Encrypted",
+            BackupError::InvalidKeybag => "InvalidKeybag",
+            BackupError::WrongPassword => "WrongPassword",
+            BackupError::DecryptionFailed => "DecryptionFailed",
+            BackupError::FileNotFound => "FileNotFound",
+            BackupError::NotAFile => "NotAFile",
+        })
+    }
+}
+
+impl From<BackupError> for String {
+    fn from(value: BackupError) -> String {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncFormatError {
+    InvalidVCard,
+    InvalidICalendar,
+    InvalidDate,
+}
+
+impl std::error::Error for SyncFormatError {}
+
+impl std::fmt::Display for SyncFormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SyncFormatError::InvalidVCard => "InvalidVCard",
+            SyncFormatError::InvalidICalendar => "InvalidICalendar",
+            SyncFormatError::InvalidDate => "InvalidDate",
+        })
+    }
+}
+
+impl From<SyncFormatError> for String {
+    fn from(value: SyncFormatError) -> String {
+        value.to_string()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MobileActivationError {
     Success,
@@ -1232,6 +1369,104 @@ impl From<MobileActivationError> for String {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivationError {
+    LockdowndFailed,
+    MobileActivationFailed,
+    ServerUnreachable,
+    ServerError,
+    InvalidResponse,
+    ActivationRejected,
+    NotActivated,
+}
+
+impl std::error::Error for ActivationError {}
+
+impl std::fmt::Display for ActivationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ActivationError::LockdowndFailed => "LockdowndFailed",
+            ActivationError::MobileActivationFailed => "MobileActivationFailed",
+            ActivationError::ServerUnreachable => "ServerUnreachable",
+            ActivationError::ServerError => "ServerError",
+            ActivationError::InvalidResponse => "InvalidResponse",
+            ActivationError::ActivationRejected => "ActivationRejected",
+            ActivationError::NotActivated => "NotActivated",
+        })
+    }
+}
+
+impl From<ActivationError> for String {
+    fn from(value: ActivationError) -> String {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppArchiveError {
+    InstProxyFailed,
+    AfcFailed,
+    IoError,
+    ArchiveFailed,
+    RestoreFailed,
+    ArchiveNotFound,
+}
+
+impl std::error::Error for AppArchiveError {}
+
+impl std::fmt::Display for AppArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AppArchiveError::InstProxyFailed => "InstProxyFailed",
+            AppArchiveError::AfcFailed => "AfcFailed",
+            AppArchiveError::IoError => "IoError",
+            AppArchiveError::ArchiveFailed => "ArchiveFailed",
+            AppArchiveError::RestoreFailed => "RestoreFailed",
+            AppArchiveError::ArchiveNotFound => "ArchiveNotFound",
+        })
+    }
+}
+
+impl From<AppArchiveError> for String {
+    fn from(value: AppArchiveError) -> String {
+        value.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrashReportError {
+    LockdowndFailed,
+    MoverFailed,
+    AfcFailed,
+    IoError,
+    InvalidHeader,
+    NotFound,
+    // Internal errors
+    InvalidPath,
+}
+
+impl std::error::Error for CrashReportError {}
+
+impl std::fmt::Display for CrashReportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CrashReportError::LockdowndFailed => "LockdowndFailed",
+            CrashReportError::MoverFailed => "MoverFailed",
+            CrashReportError::AfcFailed => "AfcFailed",
+            CrashReportError::IoError => "IoError",
+            CrashReportError::InvalidHeader => "InvalidHeader",
+            CrashReportError::NotFound => "NotFound",
+            CrashReportError::InvalidPath => "InvalidPath",
+        })
+    }
+}
+
+impl From<CrashReportError> for String {
+    fn from(value: CrashReportError) -> String {
+        value.to_string()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MobileImageMounterError {
     Success,
@@ -1297,6 +1532,9 @@ pub enum MisagentError {
     ConnFailed,
     RequestFailed,
     UnknownError,
+    // Internal errors
+    InvalidProfile,
+    IoError,
 }
 
 impl std::error::Error for MisagentError {}
@@ -1323,6 +1561,8 @@ impl std::fmt::Display for MisagentError {
             MisagentError::ConnFailed => "ConnFailed",
             MisagentError::RequestFailed => "RequestFailed",
             MisagentError::UnknownError => "UnknownError",
+            MisagentError::InvalidProfile => "InvalidProfile",
+            MisagentError::IoError => "IoError",
         })
     }
 }
@@ -1341,6 +1581,13 @@ pub enum HouseArrestError {
     ConnFailed,
     InvalidMode,
     UnknownError,
+    // Internal errors
+    InstallationLookupFailed,
+    NotDebuggable,
+    CommandFailed,
+    InvalidResponse,
+    AfcFailed,
+    IoError,
 }
 
 impl std::error::Error for HouseArrestError {}
@@ -1367,6 +1614,12 @@ impl std::fmt::Display for HouseArrestError {
             HouseArrestError::ConnFailed => "ConnFailed",
             HouseArrestError::InvalidMode => "InvalidMode",
             HouseArrestError::UnknownError => "UnknownError",
+            HouseArrestError::InstallationLookupFailed => "InstallationLookupFailed",
+            HouseArrestError::NotDebuggable => "NotDebuggable",
+            HouseArrestError::CommandFailed => "CommandFailed",
+            HouseArrestError::InvalidResponse => "InvalidResponse",
+            HouseArrestError::AfcFailed => "AfcFailed",
+            HouseArrestError::IoError => "IoError",
         })
     }
 }
@@ -1535,6 +1788,9 @@ pub enum CompanionProxyError {
     UnsupportedKey,
     TimeoutReply,
     UnknownError,
+    // Internal errors
+    IoError,
+    ConnectionFailed,
 }
 
 impl std::error::Error for CompanionProxyError {}
@@ -1573,6 +1829,8 @@ impl std::fmt::Display for CompanionProxyError {
             CompanionProxyError::UnsupportedKey => "UnsupportedKey",
             CompanionProxyError::TimeoutReply => "TimeoutReply",
             CompanionProxyError::UnknownError => "UnknownError",
+            CompanionProxyError::IoError => "IoError",
+            CompanionProxyError::ConnectionFailed => "ConnectionFailed",
         })
     }
 }
@@ -0,0 +1,81 @@
+// jkcoxson
+// Runs a blocking service call on a helper thread and wakes the task when it finishes
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+type Work<T> = Box<dyn FnOnce(&AtomicBool) -> T + Send>;
+
+struct Shared<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+}
+
+/// A future for a blocking receive on a service connection.
+/// The receive runs on a helper thread started by the first poll, and the task is only woken once it returns.
+///
+/// The work owns everything it uses, so the thread stays valid even if the future is leaked.
+/// Dropping the future before it completes asks the receive to stop at its next short slice.
+pub struct ServiceFuture<T> {
+    work: Option<Work<T>>,
+    shared: Arc<Mutex<Shared<T>>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<T: Send + 'static> ServiceFuture<T> {
+    /// Wraps a blocking call. The call should check the flag it's given between short receives
+    /// and give up once it's set.
+    pub(crate) fn new(work: impl FnOnce(&AtomicBool) -> T + Send + 'static) -> Self {
+        ServiceFuture {
+            work: Some(Box::new(work)),
+            shared: Arc::new(Mutex::new(Shared {
+                result: None,
+                waker: None,
+            })),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl<T: Send + 'static> Future for ServiceFuture<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        {
+            let mut shared = self.shared.lock().unwrap();
+            if let Some(result) = shared.result.take() {
+                return Poll::Ready(result);
+            }
+            // Register before the thread can finish, so its wake isn't missed
+            shared.waker = Some(cx.waker().clone());
+        }
+
+        if let Some(work) = self.work.take() {
+            let shared = self.shared.clone();
+            let cancelled = self.cancelled.clone();
+            std::thread::spawn(move || {
+                let result = work(&cancelled);
+                let mut shared = shared.lock().unwrap();
+                shared.result = Some(result);
+                if let Some(waker) = shared.waker.take() {
+                    waker.wake();
+                }
+            });
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for ServiceFuture<T> {
+    fn drop(&mut self) {
+        // The thread lets go of its own handles once it notices
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
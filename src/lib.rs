@@ -1,16 +1,24 @@
 #[doc = include_str!("../README.md")]
 #[allow(clippy::all)]
 mod bindings;
+/// Reads backups stored on the host without a device connected
+pub mod backup;
 /// TODO
 pub mod callback;
 /// A debug macro used throughout the crate
 pub mod connection;
 /// A module containing all possible errors produced by the library
 pub mod error;
+/// Futures that wait on blocking service calls without spinning the executor
+pub mod future;
 /// Creates connections and manages high level interfaces for iOS devices
 pub mod idevice;
+mod plist_helpers;
 /// A bare bones representation of a service running on a device.
 /// Useful for services that don't have modules or for running raw commands
 pub mod service;
 /// A module that contains all abstractions for built-in services
 pub mod services;
+mod sqlite;
+/// Converts MobileSync records to and from vCard and iCalendar
+pub mod sync_formats;
@@ -0,0 +1,192 @@
+// jkcoxson
+// Small helpers for reading typed values out of plists returned by the device
+
+use std::time::{Duration, SystemTime};
+
+use plist_plus::{Plist, PlistType};
+
+use crate::bindings as unsafe_bindings;
+
+/// Seconds from the Unix epoch to 01/01/2001, which plist dates count from
+const MAC_EPOCH: i64 = 978307200;
+
+/// Gets a string value from a dictionary
+pub(crate) fn dict_string(dict: &Plist, key: &str) -> Option<String> {
+    let item = dict.dict_get_item(key).ok()?;
+    if item.plist_type != PlistType::String {
+        return None;
+    }
+    item.get_string_val().ok()
+}
+
+/// Gets an unsigned integer value from a dictionary
+pub(crate) fn dict_uint(dict: &Plist, key: &str) -> Option<u64> {
+    let item = dict.dict_get_item(key).ok()?;
+    if item.plist_type != PlistType::Integer {
+        return None;
+    }
+    item.get_uint_val().ok()
+}
+
+/// Gets a boolean value from a dictionary
+pub(crate) fn dict_bool(dict: &Plist, key: &str) -> Option<bool> {
+    let item = dict.dict_get_item(key).ok()?;
+    if item.plist_type != PlistType::Boolean {
+        return None;
+    }
+    item.get_bool_val().ok()
+}
+
+/// Gets a date value from a dictionary
+pub(crate) fn dict_date(dict: &Plist, key: &str) -> Option<SystemTime> {
+    let item = dict.dict_get_item(key).ok()?;
+    date_value(&item)
+}
+
+/// Gets the value of a date node.
+/// Unlike `Plist::get_date_val`, this handles dates before 2001.
+pub(crate) fn date_value(date: &Plist) -> Option<SystemTime> {
+    if date.plist_type != PlistType::Date {
+        return None;
+    }
+    let mut sec = 0;
+    let mut usec = 0;
+    unsafe { unsafe_bindings::plist_get_date_val(date.get_pointer(), &mut sec, &mut usec) };
+    // The seconds are truncated towards 2001 and the microseconds are always positive
+    let micros = if sec < 0 {
+        sec as i64 * 1_000_000 - usec as i64
+    } else {
+        sec as i64 * 1_000_000 + usec as i64
+    };
+    let micros = micros + MAC_EPOCH * 1_000_000;
+    let offset = Duration::from_micros(micros.unsigned_abs());
+    if micros < 0 {
+        SystemTime::UNIX_EPOCH.checked_sub(offset)
+    } else {
+        SystemTime::UNIX_EPOCH.checked_add(offset)
+    }
+}
+
+/// Creates a date node.
+/// Unlike `Plist::new_date`, this handles dates before 2001, and before 1970.
+/// Dates that don't fit a plist date, more than 68 years either side of 2001, are clamped.
+pub(crate) fn new_date(date: SystemTime) -> Plist {
+    let micros = match date.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(after) => after.as_micros() as i128,
+        Err(before) => -(before.duration().as_micros() as i128),
+    } - MAC_EPOCH as i128 * 1_000_000;
+    let sec = micros
+        .div_euclid(1_000_000)
+        .clamp(i32::MIN as i128, i32::MAX as i128) as i32;
+    let usec = micros.rem_euclid(1_000_000) as i32;
+    unsafe { unsafe_bindings::plist_new_date(sec, usec) }.into()
+}
+
+/// Gets a copy of any value from a dictionary
+pub(crate) fn dict_item(dict: &Plist, key: &str) -> Option<Plist> {
+    let item = dict.dict_get_item(key).ok()?;
+    match item.plist_type {
+        PlistType::Unknown | PlistType::None => None,
+        _ => Some(item.clone()),
+    }
+}
+
+/// Gets the bytes of a data node
+pub(crate) fn data_bytes(data: &Plist) -> Option<Vec<u8>> {
+    if data.plist_type != PlistType::Data {
+        return None;
+    }
+    Some(
+        data.get_data_val()
+            .ok()?
+            .into_iter()
+            .map(|b| b as u8)
+            .collect(),
+    )
+}
+
+/// Copies the items out of an array.
+/// The copies are detached from the array, so they can be dropped without touching it.
+pub(crate) fn array_items(array: &Plist) -> Vec<Plist> {
+    let size = match array.array_get_size() {
+        Ok(size) => size,
+        Err(_) => return Vec::new(),
+    };
+    let mut items = Vec::with_capacity(size as usize);
+    for i in 0..size {
+        if let Ok(item) = array.array_get_item(i) {
+            // The item still belongs to the array, so take a copy
+            items.push(item.clone());
+        }
+    }
+    items
+}
+
+/// Copies the key and value pairs out of a dictionary
+pub(crate) fn dict_entries(dict: &Plist) -> Vec<(String, Plist)> {
+    if dict.plist_type != PlistType::Dictionary {
+        return Vec::new();
+    }
+    let mut entries = Vec::new();
+    for item in dict.clone() {
+        if let Some(key) = item.key {
+            entries.push((key, item.plist.clone()));
+        }
+        // The value still belongs to the dictionary being iterated
+        item.plist.false_drop();
+    }
+    entries
+}
+
+/// Collects the strings out of an array, skipping anything that isn't a string
+pub(crate) fn string_array(array: &Plist) -> Vec<String> {
+    array_items(array)
+        .into_iter()
+        .filter(|item| item.plist_type == PlistType::String)
+        .filter_map(|item| item.get_string_val().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(date: SystemTime) -> Option<SystemTime> {
+        let mut dict = Plist::new_dict();
+        let _ = dict.dict_set_item("date", new_date(date));
+        dict_date(&dict, "date")
+    }
+
+    #[test]
+    fn dates_after_2001_round_trip() {
+        let date = SystemTime::UNIX_EPOCH + Duration::from_micros(1_546_635_600_123_456);
+        assert_eq!(round_trip(date), Some(date));
+    }
+
+    #[test]
+    fn dates_before_2001_round_trip() {
+        // 15/06/1985
+        let date = SystemTime::UNIX_EPOCH + Duration::from_secs(487_641_600);
+        assert_eq!(round_trip(date), Some(date));
+    }
+
+    #[test]
+    fn dates_before_1970_round_trip() {
+        // 01/03/1965 12:30
+        let date = SystemTime::UNIX_EPOCH - Duration::from_secs(152_623_800);
+        assert_eq!(round_trip(date), Some(date));
+    }
+
+    #[test]
+    fn fractional_dates_before_2001_round_trip() {
+        let date = SystemTime::UNIX_EPOCH + Duration::from_micros(487_641_600_500_000);
+        assert_eq!(round_trip(date), Some(date));
+    }
+
+    #[test]
+    fn non_dates_are_ignored() {
+        let mut dict = Plist::new_dict();
+        let _ = dict.dict_set_item("date", Plist::new_string("1985-06-15"));
+        assert_eq!(dict_date(&dict, "date"), None);
+    }
+}
@@ -1277,7 +1277,7 @@ fn bindgen_test_layout__OSUnalignedU16() {
         concat!("Alignment of ", stringify!(_OSUnalignedU16))
     );
     assert_eq!(
-        unsafe { &(*(::std::ptr::null::<_OSUnalignedU16>())).__val as *const _ as usize },
+        unsafe { ::std::ptr::addr_of!((*(::std::ptr::null::<_OSUnalignedU16>())).__val) as usize },
         0usize,
         concat!(
             "Offset of field: ",
@@ -1305,7 +1305,7 @@ fn bindgen_test_layout__OSUnalignedU32() {
         concat!("Alignment of ", stringify!(_OSUnalignedU32))
     );
     assert_eq!(
-        unsafe { &(*(::std::ptr::null::<_OSUnalignedU32>())).__val as *const _ as usize },
+        unsafe { ::std::ptr::addr_of!((*(::std::ptr::null::<_OSUnalignedU32>())).__val) as usize },
         0usize,
         concat!(
             "Offset of field: ",
@@ -1333,7 +1333,7 @@ fn bindgen_test_layout__OSUnalignedU64() {
         concat!("Alignment of ", stringify!(_OSUnalignedU64))
     );
     assert_eq!(
-        unsafe { &(*(::std::ptr::null::<_OSUnalignedU64>())).__val as *const _ as usize },
+        unsafe { ::std::ptr::addr_of!((*(::std::ptr::null::<_OSUnalignedU64>())).__val) as usize },
         0usize,
         concat!(
             "Offset of field: ",
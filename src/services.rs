@@ -1,10 +1,21 @@
 // jkcoxson
 
+/// Activates devices through Apple's activation server
+pub mod activation;
 /// Transfers files between host and the iDevice
 pub mod afc;
+/// Pulls app archives to the host and restores apps from them
+pub mod app_archive;
+/// Creates and restores iTunes compatible backups over mobilebackup2
+pub mod backup_engine;
+/// Serves inspectable pages to Chrome DevTools Protocol clients
+#[cfg(feature = "cdp-bridge")]
+pub mod cdp_bridge;
 /// A proxy for interoping with devices paired with the iOS device
 /// This includes the Apple Watch
 pub mod companion_proxy;
+/// Flushes and copies crash reports from the device
+pub mod crash_reports;
 /// Used for debugging applications on the device
 pub mod debug_server;
 /// Relays diagnostic logs from the iOS device to the host
@@ -16,8 +27,12 @@ pub mod file_relay;
 pub mod heartbeat;
 /// iTunes file transfer service
 pub mod house_arrest;
+/// Enumerates inspectable web content and exchanges WebKit inspector messages with it
+pub mod inspector_session;
 /// Manages installing, removing and modifying applications on the device
 pub mod instproxy;
+/// Backs up and restores devices older than iOS 4 over mobilebackup
+pub mod legacy_backup;
 /// A jumping point for other services
 pub mod lockdownd;
 /// Manges and checks provisioning profiles
@@ -40,10 +55,14 @@ pub mod preboard;
 pub mod property_list_service;
 /// Restores an iDevice to a specific backup or iOS version
 pub mod restored;
+/// Answers restored's data requests and reports progress while a device restores
+pub mod restore_session;
 /// Takes a screenshot and returns it to the host
 pub mod screenshotr;
 /// Manages the device's OS base and homescreen.
 pub mod springboard_services;
+/// Runs MobileSync sessions for contacts, calendars and bookmarks
+pub mod sync_session;
 /// The iOS device's settings. Very fun to mess with.
 pub mod userpref;
 /// First used on MacOS, this service is used to inspect the JavaScript and HTML of a site running on the device
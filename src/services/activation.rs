@@ -0,0 +1,499 @@
+// jkcoxson
+// Activates a device through Apple's activation server, either with an activation session or through lockdown
+
+use log::{info, warn};
+use plist_plus::{Plist, PlistType};
+
+use crate::{
+    error::ActivationError,
+    idevice::Device,
+    plist_helpers::dict_item,
+    services::{lockdownd::LockdowndClient, mobile_activation::MobileActivationClient},
+};
+
+/// The endpoint that answers the session info of iOS 10+ devices
+pub const DRM_HANDSHAKE_URL: &str = "https://albert.apple.com/deviceservices/drmHandshake";
+/// The endpoint that turns activation info into an activation record
+pub const DEVICE_ACTIVATION_URL: &str = "https://albert.apple.com/deviceservices/deviceActivation";
+
+const USER_AGENT: &str =
+    "iOS Device Activator (MobileActivation-20 built on Jan 15 2012 at 19:07:28)";
+const BOUNDARY: &str = "------rusty_libimobiledevice_activation";
+
+/// An HTTP response from the activation server
+#[derive(Debug, Clone, Default)]
+pub struct ActivationResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl ActivationResponse {
+    /// Gets a header by name, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Carries the HTTP exchange with the activation server.
+/// Implement this to use a different HTTP client or a local stand-in server.
+pub trait ActivationServer {
+    /// Sends a POST request
+    /// # Arguments
+    /// * `url` - Either `DRM_HANDSHAKE_URL` or `DEVICE_ACTIVATION_URL`
+    /// * `headers` - The request headers
+    /// * `body` - The request body
+    /// # Returns
+    /// The server's response, whatever its status
+    fn post(
+        &mut self,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<ActivationResponse, ActivationError>;
+}
+
+/// How a device was activated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivationMethod {
+    /// The mobileactivation service with a drmHandshake session, used by iOS 10 and later
+    Session,
+    /// The mobileactivation service without a session
+    MobileActivation,
+    /// Lockdown's activation request, used by older devices
+    Lockdown,
+}
+
+/// The result of `activate_device`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivationOutcome {
+    /// The device was already activated, nothing was sent to the server
+    AlreadyActivated,
+    Activated(ActivationMethod),
+}
+
+/// Activates a device, picking the activation path the device supports
+/// # Arguments
+/// * `device` - The device to activate
+/// * `server` - Carries the requests to the activation server
+/// # Returns
+/// Whether the device needed activating and how it was activated
+///
+/// ***Verified:*** False
+pub fn activate_device(
+    device: &Device,
+    server: &mut impl ActivationServer,
+) -> Result<ActivationOutcome, ActivationError> {
+    let lockdown = device
+        .new_lockdownd_client("activate_device")
+        .map_err(|_| ActivationError::LockdowndFailed)?;
+    if activation_state(&lockdown)? != "Unactivated" {
+        info!("{} is already activated", device.get_udid());
+        return Ok(ActivationOutcome::AlreadyActivated);
+    }
+
+    let method = match MobileActivationClient::start_service(device, "activate_device") {
+        Ok(client) => match client.create_activation_session_info() {
+            Ok(session_info) => {
+                activate_with_session(&client, server, &session_info)?;
+                ActivationMethod::Session
+            }
+            Err(e) => {
+                info!(
+                    "No activation session available ({:?}), activating without one",
+                    e
+                );
+                let activation_info = client
+                    .create_activation_info()
+                    .map_err(|_| ActivationError::MobileActivationFailed)?;
+                let (record, _) = request_activation_record(server, &activation_info)?;
+                client
+                    .activate(record, None)
+                    .map_err(|_| ActivationError::MobileActivationFailed)?;
+                ActivationMethod::MobileActivation
+            }
+        },
+        Err(e) => {
+            info!(
+                "mobileactivation is unavailable ({:?}), activating through lockdown",
+                e
+            );
+            let activation_info = lockdown
+                .get_value("ActivationInfo", "")
+                .map_err(|_| ActivationError::LockdowndFailed)?;
+            let (record, _) = request_activation_record(server, &activation_info)?;
+            lockdown
+                .activate(record)
+                .map_err(|_| ActivationError::LockdowndFailed)?;
+            ActivationMethod::Lockdown
+        }
+    };
+
+    let state = activation_state(&lockdown)?;
+    if state == "Unactivated" {
+        warn!("Device accepted the activation record but is still unactivated");
+        return Err(ActivationError::NotActivated);
+    }
+    info!(
+        "Activated {} with {:?}, now {}",
+        device.get_udid(),
+        method,
+        state
+    );
+    Ok(ActivationOutcome::Activated(method))
+}
+
+fn activation_state(lockdown: &LockdowndClient) -> Result<String, ActivationError> {
+    lockdown
+        .get_value("ActivationState", "")
+        .map_err(|_| ActivationError::LockdowndFailed)?
+        .get_string_val()
+        .map_err(|_| ActivationError::InvalidResponse)
+}
+
+fn activate_with_session(
+    client: &MobileActivationClient,
+    server: &mut impl ActivationServer,
+    session_info: &Plist,
+) -> Result<(), ActivationError> {
+    let body = session_info.to_string();
+    let response = server.post(
+        DRM_HANDSHAKE_URL,
+        &[
+            ("Content-Type", "application/x-apple-plist"),
+            ("Accept", "application/xml"),
+            ("User-Agent", USER_AGENT),
+        ],
+        body.as_bytes(),
+    )?;
+    if response.status != 200 {
+        warn!("drmHandshake returned HTTP {}", response.status);
+        return Err(ActivationError::ServerError);
+    }
+    let handshake_response =
+        Plist::from_memory(response.body).map_err(|_| ActivationError::InvalidResponse)?;
+
+    let activation_info = client
+        .create_activation_info_with_handshake(&handshake_response)
+        .map_err(|_| ActivationError::MobileActivationFailed)?;
+    let (record, headers) = request_activation_record(server, &activation_info)?;
+
+    // The device checks the record against the headers it was served with
+    let mut session = Plist::new_dict();
+    for (name, value) in headers {
+        let _ = session.dict_set_item(&name, Plist::new_string(&value));
+    }
+    client
+        .activate(record, Some(session))
+        .map_err(|_| ActivationError::MobileActivationFailed)
+}
+
+/// Posts the activation info and pulls the activation record out of the response
+fn request_activation_record(
+    server: &mut impl ActivationServer,
+    activation_info: &Plist,
+) -> Result<(Plist, Vec<(String, String)>), ActivationError> {
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"activation-info\"\r\n\r\n",
+        BOUNDARY
+    )
+    .into_bytes();
+    body.extend_from_slice(activation_info.to_string().as_bytes());
+    body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+    let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+
+    let response = server.post(
+        DEVICE_ACTIVATION_URL,
+        &[
+            ("Content-Type", &content_type),
+            ("Accept", "application/xml"),
+            ("User-Agent", USER_AGENT),
+        ],
+        &body,
+    )?;
+    if response.status != 200 {
+        warn!("deviceActivation returned HTTP {}", response.status);
+        return Err(ActivationError::ServerError);
+    }
+
+    let is_html = response
+        .header("Content-Type")
+        .is_some_and(|content_type| content_type.contains("text/html"));
+    let plist = if is_html {
+        // Browsers are sent a page with the response embedded in a script tag
+        extract_embedded_plist(&response.body).ok_or(ActivationError::ActivationRejected)?
+    } else {
+        response.body.clone()
+    };
+    let plist = Plist::from_memory(plist).map_err(|_| ActivationError::InvalidResponse)?;
+
+    let record = dict_item(&plist, "ActivationRecord")
+        .or_else(|| {
+            ["iphone-activation", "device-activation"]
+                .iter()
+                .filter_map(|key| dict_item(&plist, key))
+                .find_map(|wrapper| dict_item(&wrapper, "activation-record"))
+        })
+        .filter(|record| record.plist_type == PlistType::Dictionary);
+    match record {
+        Some(record) => Ok((record, response.headers)),
+        None => {
+            // Activation lock and unsupported devices land here
+            warn!("The activation server didn't return an activation record");
+            Err(ActivationError::ActivationRejected)
+        }
+    }
+}
+
+fn extract_embedded_plist(html: &[u8]) -> Option<Vec<u8>> {
+    let html = String::from_utf8_lossy(html);
+    let tag = html.find("text/x-apple-plist")?;
+    let start = tag + html[tag..].find('>')? + 1;
+    let end = start + html[start..].find("</script>")?;
+    Some(html[start..end].trim().as_bytes().to_vec())
+}
+
+/// Sends activation requests to Apple's servers over HTTPS
+#[cfg(feature = "activation-http")]
+pub struct HttpActivationServer {
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "activation-http")]
+impl HttpActivationServer {
+    pub fn new() -> Self {
+        HttpActivationServer {
+            agent: ureq::AgentBuilder::new()
+                .timeout(std::time::Duration::from_secs(60))
+                .build(),
+        }
+    }
+}
+
+#[cfg(feature = "activation-http")]
+impl Default for HttpActivationServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "activation-http")]
+impl ActivationServer for HttpActivationServer {
+    fn post(
+        &mut self,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<ActivationResponse, ActivationError> {
+        let mut request = self.agent.post(url);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        let response = match request.send_bytes(body) {
+            Ok(response) => response,
+            // Error statuses still carry a response worth returning
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => {
+                warn!("Unable to reach {}: {}", url, e);
+                return Err(ActivationError::ServerUnreachable);
+            }
+        };
+
+        let status = response.status();
+        let headers = response
+            .headers_names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = response.header(&name)?.to_string();
+                Some((name, value))
+            })
+            .collect();
+        let mut body = Vec::new();
+        std::io::Read::read_to_end(&mut response.into_reader(), &mut body)
+            .map_err(|_| ActivationError::ServerUnreachable)?;
+        Ok(ActivationResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Request {
+        url: String,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    /// Answers every request with a recorded response and keeps the requests it was sent
+    struct RecordedServer {
+        response: ActivationResponse,
+        requests: Vec<Request>,
+    }
+
+    impl RecordedServer {
+        fn new(status: u16, content_type: &str, body: &str) -> Self {
+            RecordedServer {
+                response: ActivationResponse {
+                    status,
+                    headers: vec![
+                        ("Content-Type".into(), content_type.into()),
+                        ("X-Apple-Request-Id".into(), "1234".into()),
+                    ],
+                    body: body.as_bytes().to_vec(),
+                },
+                requests: Vec::new(),
+            }
+        }
+    }
+
+    impl ActivationServer for RecordedServer {
+        fn post(
+            &mut self,
+            url: &str,
+            headers: &[(&str, &str)],
+            body: &[u8],
+        ) -> Result<ActivationResponse, ActivationError> {
+            let headers = headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
+            self.requests.push(Request {
+                url: url.to_string(),
+                headers,
+                body: body.to_vec(),
+            });
+            Ok(self.response.clone())
+        }
+    }
+
+    const RECORD_PLIST: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <plist version=\"1.0\"><dict><key>ActivationRecord</key><dict>\
+        <key>AccountTokenSignature</key><data>c2lnbmF0dXJl</data>\
+        <key>unbrick</key><true/></dict></dict></plist>";
+
+    const RECORD_HTML: &str = "<!DOCTYPE html><html><head>\
+        <script id=\"protocol\" type=\"text/x-apple-plist\">\n\
+        <plist version=\"1.0\"><dict><key>iphone-activation</key><dict>\
+        <key>activation-record</key><dict><key>unbrick</key><true/></dict>\
+        <key>ack-received</key><true/></dict></dict></plist>\n\
+        </script></head><body>Activated</body></html>";
+
+    const LOCKED_HTML: &str = "<!DOCTYPE html><html><head><title>Activation Lock</title>\
+        </head><body>This iPhone is linked to an Apple ID.</body></html>";
+
+    fn activation_info() -> Plist {
+        let mut info = Plist::new_dict();
+        let _ = info.dict_set_item("ActivationInfoXML", Plist::new_data(b"<dict/>"));
+        info
+    }
+
+    #[test]
+    fn posts_activation_info() {
+        let mut server = RecordedServer::new(200, "application/xml", RECORD_PLIST);
+        let (record, headers) = request_activation_record(&mut server, &activation_info()).unwrap();
+        assert!(dict_item(&record, "AccountTokenSignature").is_some());
+        assert!(headers
+            .iter()
+            .any(|(name, value)| name == "X-Apple-Request-Id" && value == "1234"));
+
+        let request = &server.requests[0];
+        assert_eq!(request.url, DEVICE_ACTIVATION_URL);
+        let content_type = request
+            .headers
+            .iter()
+            .find(|(name, _)| name == "Content-Type")
+            .map(|(_, value)| value.as_str());
+        assert_eq!(
+            content_type,
+            Some(format!("multipart/form-data; boundary={}", BOUNDARY).as_str())
+        );
+        let body = String::from_utf8(request.body.clone()).unwrap();
+        assert!(body.starts_with(&format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"activation-info\"\r\n\r\n",
+            BOUNDARY
+        )));
+        assert!(body.contains("<key>ActivationInfoXML</key>"));
+        assert!(body.ends_with(&format!("\r\n--{}--\r\n", BOUNDARY)));
+    }
+
+    #[test]
+    fn reads_record_from_html() {
+        let mut server = RecordedServer::new(200, "text/html; charset=utf-8", RECORD_HTML);
+        let (record, _) = request_activation_record(&mut server, &activation_info()).unwrap();
+        assert_eq!(record.plist_type, PlistType::Dictionary);
+        assert!(dict_item(&record, "unbrick").is_some());
+    }
+
+    #[test]
+    fn activation_lock_is_rejected() {
+        let mut server = RecordedServer::new(200, "text/html", LOCKED_HTML);
+        assert_eq!(
+            request_activation_record(&mut server, &activation_info()).err(),
+            Some(ActivationError::ActivationRejected)
+        );
+    }
+
+    #[test]
+    fn response_without_record_is_rejected() {
+        let mut server = RecordedServer::new(
+            200,
+            "application/xml",
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<plist version=\"1.0\"><dict>\
+             <key>ErrorMessage</key><string>Unsupported device</string></dict></plist>",
+        );
+        assert_eq!(
+            request_activation_record(&mut server, &activation_info()).err(),
+            Some(ActivationError::ActivationRejected)
+        );
+    }
+
+    #[test]
+    fn server_errors() {
+        let mut server = RecordedServer::new(500, "text/html", "Internal Server Error");
+        assert_eq!(
+            request_activation_record(&mut server, &activation_info()).err(),
+            Some(ActivationError::ServerError)
+        );
+        let mut server = RecordedServer::new(200, "application/xml", "not a plist");
+        assert_eq!(
+            request_activation_record(&mut server, &activation_info()).err(),
+            Some(ActivationError::InvalidResponse)
+        );
+    }
+
+    #[test]
+    fn extracts_embedded_plist() {
+        let plist = extract_embedded_plist(RECORD_HTML.as_bytes()).unwrap();
+        let plist = String::from_utf8(plist).unwrap();
+        assert!(plist.starts_with("<plist version=\"1.0\">"));
+        assert!(plist.ends_with("</plist>"));
+        let plist = Plist::from_memory(plist.into_bytes()).unwrap();
+        let wrapper = dict_item(&plist, "iphone-activation").unwrap();
+        assert!(dict_item(&wrapper, "ack-received").is_some());
+
+        assert_eq!(extract_embedded_plist(LOCKED_HTML.as_bytes()), None);
+        // An unterminated script tag
+        assert_eq!(
+            extract_embedded_plist(b"<script type=\"text/x-apple-plist\"><plist>"),
+            None
+        );
+    }
+
+    #[test]
+    fn response_headers_ignore_case() {
+        let server = RecordedServer::new(200, "application/xml", "");
+        assert_eq!(
+            server.response.header("content-type"),
+            Some("application/xml")
+        );
+        assert_eq!(server.response.header("Location"), None);
+    }
+}
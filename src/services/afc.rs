@@ -17,7 +17,7 @@ use crate::{
 /// Transfers files between host and the iDevice
 pub struct AfcClient<'a> {
     pub(crate) pointer: unsafe_bindings::afc_client_t,
-    phantom: std::marker::PhantomData<&'a Device>,
+    pub(crate) phantom: std::marker::PhantomData<&'a Device>,
 }
 
 impl AfcClient<'_> {
@@ -0,0 +1,186 @@
+// jkcoxson
+// Moves app archives between the device and the host, so apps can be restored with their data later
+
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+};
+
+use log::{info, warn};
+use plist_plus::Plist;
+
+use crate::{
+    error::{AfcError, AppArchiveError},
+    idevice::Device,
+    services::{
+        afc::{AfcClient, AfcFileMode},
+        instproxy::{InstProxyClient, InstallOptions},
+    },
+};
+
+/// The directory instproxy keeps archives in, relative to the AFC root
+pub const ARCHIVES_DIRECTORY: &str = "ApplicationArchives";
+
+/// How many bytes to move per AFC read or write
+const TRANSFER_CHUNK: u32 = 64 * 1024;
+
+/// Options for `archive_app_to_host`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveOptions {
+    /// Leave the app installed after archiving it
+    pub keep_installed: bool,
+    /// Archive the app without its documents and data
+    pub application_only: bool,
+    /// Remove the archive from the device once it's on the host
+    pub remove_from_device: bool,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        ArchiveOptions {
+            keep_installed: true,
+            application_only: false,
+            remove_from_device: true,
+        }
+    }
+}
+
+/// Gets the AFC path of an app's archive
+pub fn archive_path(bundle_id: &str) -> String {
+    format!("{}/{}.zip", ARCHIVES_DIRECTORY, bundle_id)
+}
+
+/// Archives an app on the device and downloads the archive to the host
+/// # Arguments
+/// * `device` - The device the app is installed on
+/// * `bundle_id` - The bundle ID of the app
+/// * `dest` - The path to write the zip archive to
+/// * `options` - How to archive the app
+/// # Returns
+/// *none*
+///
+/// ***Verified:*** False
+pub fn archive_app_to_host(
+    device: &Device,
+    bundle_id: &str,
+    dest: impl AsRef<Path>,
+    options: ArchiveOptions,
+) -> Result<(), AppArchiveError> {
+    let instproxy = InstProxyClient::new(device, "archive_app_to_host")
+        .map_err(|_| AppArchiveError::InstProxyFailed)?;
+
+    let mut archive_options = InstallOptions::new()
+        .skip_uninstall(options.keep_installed)
+        .to_plist();
+    if options.application_only {
+        let _ = archive_options.dict_set_item("ArchiveType", Plist::new_string("ApplicationOnly"));
+    }
+    info!("Archiving {}", bundle_id);
+    instproxy
+        .archive(bundle_id, Some(archive_options))
+        .map_err(|e| {
+            warn!("Unable to archive {}: {:?}", bundle_id, e);
+            AppArchiveError::ArchiveFailed
+        })?;
+
+    let afc = AfcClient::start_service(device, "archive_app_to_host")
+        .map_err(|_| AppArchiveError::AfcFailed)?;
+    let path = archive_path(bundle_id);
+    download(&afc, &path, dest.as_ref())?;
+    info!("Downloaded {} to {}", path, dest.as_ref().display());
+
+    if options.remove_from_device {
+        instproxy
+            .remove_archive(bundle_id, None)
+            .map_err(|_| AppArchiveError::InstProxyFailed)?;
+    }
+    Ok(())
+}
+
+/// Uploads an archive from the host and restores the app from it
+/// # Arguments
+/// * `device` - The device to restore the app on
+/// * `bundle_id` - The bundle ID of the archived app
+/// * `source` - The zip archive made by `archive_app_to_host`
+/// * `remove_from_device` - Whether to remove the uploaded archive after the restore
+/// # Returns
+/// *none*
+///
+/// ***Verified:*** False
+pub fn restore_app_from_host(
+    device: &Device,
+    bundle_id: &str,
+    source: impl AsRef<Path>,
+    remove_from_device: bool,
+) -> Result<(), AppArchiveError> {
+    let afc = AfcClient::start_service(device, "restore_app_from_host")
+        .map_err(|_| AppArchiveError::AfcFailed)?;
+    match afc.make_directory(ARCHIVES_DIRECTORY) {
+        Ok(()) | Err(AfcError::ObjectExists) => {}
+        Err(_) => return Err(AppArchiveError::AfcFailed),
+    }
+    let path = archive_path(bundle_id);
+    upload(&afc, source.as_ref(), &path)?;
+    info!("Uploaded {} to {}", source.as_ref().display(), path);
+
+    let instproxy = InstProxyClient::new(device, "restore_app_from_host")
+        .map_err(|_| AppArchiveError::InstProxyFailed)?;
+    let restored = instproxy.restore(bundle_id, None);
+
+    // Clean up even if the restore failed, so a bad archive isn't left behind
+    if remove_from_device {
+        if let Err(e) = instproxy.remove_archive(bundle_id, None) {
+            warn!("Unable to remove the archive of {}: {:?}", bundle_id, e);
+        }
+    }
+    restored.map_err(|e| {
+        warn!("Unable to restore {}: {:?}", bundle_id, e);
+        AppArchiveError::RestoreFailed
+    })
+}
+
+fn download(afc: &AfcClient, path: &str, dest: &Path) -> Result<(), AppArchiveError> {
+    let handle = match afc.file_open(path, AfcFileMode::ReadOnly) {
+        Ok(handle) => handle,
+        Err(AfcError::ObjectNotFound) => return Err(AppArchiveError::ArchiveNotFound),
+        Err(_) => return Err(AppArchiveError::AfcFailed),
+    };
+    let result = (|| {
+        let mut file = File::create(dest).map_err(|_| AppArchiveError::IoError)?;
+        loop {
+            let chunk = afc
+                .file_read(handle, TRANSFER_CHUNK)
+                .map_err(|_| AppArchiveError::AfcFailed)?;
+            if chunk.is_empty() {
+                return Ok(());
+            }
+            file.write_all(&chunk)
+                .map_err(|_| AppArchiveError::IoError)?;
+        }
+    })();
+    let _ = afc.file_close(handle);
+    result
+}
+
+fn upload(afc: &AfcClient, source: &Path, path: &str) -> Result<(), AppArchiveError> {
+    let mut file = File::open(source).map_err(|_| AppArchiveError::IoError)?;
+    let handle = afc
+        .file_open(path, AfcFileMode::WriteOnly)
+        .map_err(|_| AppArchiveError::AfcFailed)?;
+    let result = (|| {
+        let mut buffer = vec![0; TRANSFER_CHUNK as usize];
+        loop {
+            let read = file
+                .read(&mut buffer)
+                .map_err(|_| AppArchiveError::IoError)?;
+            if read == 0 {
+                return Ok(());
+            }
+            afc.file_write(handle, buffer[..read].to_vec())
+                .map_err(|_| AppArchiveError::AfcFailed)?;
+        }
+    })();
+    let _ = afc.file_close(handle);
+    result
+}
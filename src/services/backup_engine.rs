@@ -0,0 +1,930 @@
+// jkcoxson
+// Drives the DeviceLink message loop of mobilebackup2 to create and restore iTunes compatible backups
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use log::{info, warn};
+use plist_plus::{Plist, PlistType};
+
+use crate::{
+    error::{LockdowndError, MobileBackup2Error},
+    idevice::Device,
+    plist_helpers::{
+        array_items, dict_bool, dict_entries, dict_string, dict_uint, new_date, string_array,
+    },
+    services::mobile_backup::{MobileBackup2Client, MobileBackupRequest},
+    sqlite::Database,
+};
+
+/// The mobilebackup2 protocol versions the engine understands
+const PROTOCOL_VERSIONS: [f64; 2] = [2.0, 2.1];
+
+/// Codes that prefix every chunk of a file transfer
+const CODE_SUCCESS: u8 = 0x00;
+const CODE_ERROR_LOCAL: u8 = 0x06;
+const CODE_ERROR_REMOTE: u8 = 0x0b;
+const CODE_FILE_DATA: u8 = 0x0c;
+
+/// How many bytes to move per raw send or receive
+const TRANSFER_CHUNK: usize = 32 * 1024;
+
+/// The status code the device expects when some files in a batch failed
+const MULTI_STATUS: i32 = -13;
+
+/// Lockdown values copied into Info.plist
+const INFO_KEYS: [(&str, &str); 10] = [
+    ("BuildVersion", "Build Version"),
+    ("DeviceName", "Device Name"),
+    ("DeviceName", "Display Name"),
+    ("IntegratedCircuitCardIdentity", "ICCID"),
+    ("InternationalMobileEquipmentIdentity", "IMEI"),
+    ("MobileEquipmentIdentifier", "MEID"),
+    ("PhoneNumber", "Phone Number"),
+    ("ProductType", "Product Type"),
+    ("ProductVersion", "Product Version"),
+    ("SerialNumber", "Serial Number"),
+];
+
+/// Whether the device should send everything or only what changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupKind {
+    /// Send every file, ignoring any previous backup in the directory
+    Full,
+    /// Send only what changed since the backup already in the directory.
+    /// The device falls back to a full backup if there is none.
+    Incremental,
+}
+
+/// A snapshot of how far a backup has come
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BackupProgress {
+    /// The overall progress reported by the device, from 0 to 100
+    pub percent: f64,
+    /// Files written into the backup directory so far
+    pub files_received: u64,
+    /// Bytes written into the backup directory so far
+    pub bytes_received: u64,
+    /// Files sent to the device so far
+    pub files_sent: u64,
+    /// Bytes sent to the device so far
+    pub bytes_sent: u64,
+    /// The domain of the last file sent during a restore, if the manifest could be read
+    pub domain: Option<DomainProgress>,
+}
+
+/// How much of a single domain has been restored
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DomainProgress {
+    /// The domain name, such as `HomeDomain` or `AppDomain-com.apple.Maps`
+    pub domain: String,
+    /// Files of the domain sent to the device so far
+    pub files_sent: u64,
+    /// Files of the domain in the backup
+    pub files_total: u64,
+}
+
+/// Choices for how a backup is restored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestoreOptions {
+    /// Restore system files as well as user data
+    pub system_files: bool,
+    /// Have the device copy the backup before restoring it
+    pub copy_first: bool,
+    /// Remove items on the device that are not in the backup
+    pub remove_items_not_restored: bool,
+    /// Reboot the device once the restore finishes
+    pub reboot: bool,
+    /// Keep the device's current settings instead of restoring them
+    pub preserve_settings: bool,
+    /// The password of an encrypted backup
+    pub password: Option<String>,
+}
+
+impl Default for RestoreOptions {
+    fn default() -> Self {
+        RestoreOptions {
+            system_files: false,
+            copy_first: false,
+            remove_items_not_restored: false,
+            reboot: true,
+            preserve_settings: true,
+            password: None,
+        }
+    }
+}
+
+impl RestoreOptions {
+    fn to_plist(&self) -> Result<Plist, MobileBackup2Error> {
+        let mut options = Plist::new_dict();
+        for (key, value) in [
+            ("RestoreSystemFiles", self.system_files),
+            ("RestoreDontCopyBackup", !self.copy_first),
+            ("RemoveItemsNotRestored", self.remove_items_not_restored),
+            ("RestoreShouldReboot", self.reboot),
+            ("RestorePreserveSettings", self.preserve_settings),
+        ] {
+            options
+                .dict_set_item(key, Plist::new_bool(value))
+                .map_err(|_| MobileBackup2Error::PlistError)?;
+        }
+        if let Some(password) = &self.password {
+            options
+                .dict_set_item("Password", Plist::new_string(password))
+                .map_err(|_| MobileBackup2Error::PlistError)?;
+        }
+        Ok(options)
+    }
+}
+
+/// The result of a completed backup
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupSummary {
+    /// The directory containing the backup, named after the device's UDID
+    pub path: PathBuf,
+    /// Whether the device sent a full backup
+    pub full: bool,
+    /// The final progress of the transfer
+    pub progress: BackupProgress,
+}
+
+type ProgressCallback<'a> = Box<dyn FnMut(&BackupProgress) + 'a>;
+
+/// Creates backups of a device in the same layout iTunes and Finder use.
+/// The device does the heavy lifting, the engine serves its file system requests.
+pub struct BackupEngine<'a> {
+    client: MobileBackup2Client<'a>,
+    device: &'a Device,
+    udid: String,
+    root: PathBuf,
+    protocol_version: f64,
+    progress: BackupProgress,
+    file_domains: HashMap<String, String>,
+    domains: HashMap<String, DomainProgress>,
+    callback: Option<ProgressCallback<'a>>,
+}
+
+impl<'a> BackupEngine<'a> {
+    /// Starts mobilebackup2 on the device and negotiates a protocol version
+    /// # Arguments
+    /// * `device` - The device to back up
+    /// * `root` - The directory backups are stored in. Each device gets a folder named after its UDID.
+    /// # Returns
+    /// A backup engine ready to run
+    ///
+    /// ***Verified:*** False
+    pub fn new(device: &'a Device, root: impl Into<PathBuf>) -> Result<Self, MobileBackup2Error> {
+        let client = MobileBackup2Client::start_service(device, "backup_engine")?;
+        let mut versions = PROTOCOL_VERSIONS;
+        let protocol_version = client.version_exchange(&mut versions)?;
+        info!(
+            "Negotiated mobilebackup2 protocol version {}",
+            protocol_version
+        );
+
+        Ok(BackupEngine {
+            client,
+            device,
+            udid: device.get_udid(),
+            root: root.into(),
+            protocol_version,
+            progress: BackupProgress::default(),
+            file_domains: HashMap::new(),
+            domains: HashMap::new(),
+            callback: None,
+        })
+    }
+
+    /// Sets a function to be called every time the progress changes
+    /// # Arguments
+    /// * `callback` - The function to call
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn set_progress_callback(&mut self, callback: impl FnMut(&BackupProgress) + 'a) {
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Gets the protocol version agreed on with the device
+    pub fn protocol_version(&self) -> f64 {
+        self.protocol_version
+    }
+
+    /// Gets the directory the device's backup is stored in
+    pub fn backup_dir(&self) -> PathBuf {
+        self.root.join(&self.udid)
+    }
+
+    /// Backs up the device.
+    /// Info.plist is written by the host, the device uploads Status.plist, Manifest.plist and Manifest.db.
+    /// # Arguments
+    /// * `kind` - Whether to force a full backup
+    /// # Returns
+    /// A summary of the finished backup
+    ///
+    /// ***Verified:*** False
+    pub fn backup(&mut self, kind: BackupKind) -> Result<BackupSummary, MobileBackup2Error> {
+        let backup_dir = self.backup_dir();
+        fs::create_dir_all(&backup_dir).map_err(io_error)?;
+
+        if kind == BackupKind::Incremental && !backup_dir.join("Status.plist").exists() {
+            info!("No previous backup found, the device will send a full backup");
+        }
+        let info = info_plist(self.device, &self.udid).map_err(|e| {
+            warn!("Unable to read lockdownd values: {}", e);
+            MobileBackup2Error::LockdowndFailed
+        })?;
+        write_plist(&backup_dir.join("Info.plist"), &info)?;
+
+        let mut options = Plist::new_dict();
+        if kind == BackupKind::Full {
+            options
+                .dict_set_item("ForceFullBackup", Plist::new_bool(true))
+                .map_err(|_| MobileBackup2Error::PlistError)?;
+        }
+
+        self.progress = BackupProgress::default();
+        self.client
+            .send_request(MobileBackupRequest::Backup, &self.udid, &self.udid, options)?;
+        self.run()?;
+
+        let status = read_plist(&backup_dir.join("Status.plist"))?;
+        match dict_string(&status, "SnapshotState").as_deref() {
+            Some("finished") => {}
+            state => {
+                warn!("Backup ended with snapshot state {:?}", state);
+                return Err(MobileBackup2Error::Incomplete);
+            }
+        }
+
+        Ok(BackupSummary {
+            path: backup_dir,
+            full: dict_bool(&status, "IsFullBackup").unwrap_or(kind == BackupKind::Full),
+            progress: self.progress.clone(),
+        })
+    }
+
+    /// Restores a backup from the backup root onto the device
+    /// # Arguments
+    /// * `source` - The UDID of the device the backup was made from. Pass None to use this device's backup.
+    /// * `options` - How the backup should be restored
+    /// # Returns
+    /// The final progress of the restore
+    ///
+    /// ***Verified:*** False
+    pub fn restore(
+        &mut self,
+        source: Option<&str>,
+        options: &RestoreOptions,
+    ) -> Result<BackupProgress, MobileBackup2Error> {
+        let source = source.unwrap_or(&self.udid).to_string();
+        let backup_dir = self.root.join(&source);
+        if !backup_dir.join("Status.plist").exists() || !backup_dir.join("Manifest.plist").exists()
+        {
+            warn!("No complete backup found in {}", backup_dir.display());
+            return Err(MobileBackup2Error::BackupNotFound);
+        }
+        let manifest = read_plist(&backup_dir.join("Manifest.plist"))?;
+        if dict_bool(&manifest, "IsEncrypted").unwrap_or(false) && options.password.is_none() {
+            return Err(MobileBackup2Error::PasswordRequired);
+        }
+        self.load_domains(&backup_dir);
+
+        self.progress = BackupProgress::default();
+        self.client.send_request(
+            MobileBackupRequest::Restore,
+            &self.udid,
+            &source,
+            options.to_plist()?,
+        )?;
+        self.run()?;
+        Ok(self.progress.clone())
+    }
+
+    /// Checks whether the device encrypts its backups
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// True if backups will be encrypted
+    ///
+    /// ***Verified:*** False
+    pub fn backup_encryption_status(&self) -> Result<bool, MobileBackup2Error> {
+        let lockdown = self
+            .device
+            .new_lockdownd_client("backup_engine")
+            .map_err(|e| {
+                warn!("Unable to start lockdownd: {}", e);
+                MobileBackup2Error::LockdowndFailed
+            })?;
+        match lockdown.get_value("WillEncrypt", "com.apple.mobile.backup") {
+            Ok(value) if value.plist_type == PlistType::Boolean => value
+                .get_bool_val()
+                .map_err(|_| MobileBackup2Error::PlistError),
+            // The key is missing until encryption has been turned on once
+            Ok(_) => Ok(false),
+            Err(e) => {
+                warn!("Unable to read WillEncrypt: {}", e);
+                Err(MobileBackup2Error::LockdowndFailed)
+            }
+        }
+    }
+
+    /// Turns on backup encryption. The device may ask the user for their passcode.
+    /// # Arguments
+    /// * `password` - The password future backups will be encrypted with
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn enable_backup_encryption(
+        &mut self,
+        password: impl Into<String>,
+    ) -> Result<(), MobileBackup2Error> {
+        if self.backup_encryption_status()? {
+            return Err(MobileBackup2Error::EncryptionAlreadyEnabled);
+        }
+        self.change_password(None, Some(password.into()))
+    }
+
+    /// Turns off backup encryption. The device may ask the user for their passcode.
+    /// # Arguments
+    /// * `password` - The current backup password
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn disable_backup_encryption(
+        &mut self,
+        password: impl Into<String>,
+    ) -> Result<(), MobileBackup2Error> {
+        if !self.backup_encryption_status()? {
+            return Err(MobileBackup2Error::EncryptionNotEnabled);
+        }
+        self.change_password(Some(password.into()), None)
+    }
+
+    /// Changes the password backups are encrypted with
+    /// # Arguments
+    /// * `old` - The current backup password
+    /// * `new` - The new backup password
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn change_backup_password(
+        &mut self,
+        old: impl Into<String>,
+        new: impl Into<String>,
+    ) -> Result<(), MobileBackup2Error> {
+        if !self.backup_encryption_status()? {
+            return Err(MobileBackup2Error::EncryptionNotEnabled);
+        }
+        self.change_password(Some(old.into()), Some(new.into()))
+    }
+
+    fn change_password(
+        &mut self,
+        old: Option<String>,
+        new: Option<String>,
+    ) -> Result<(), MobileBackup2Error> {
+        let mut options = Plist::new_dict();
+        options
+            .dict_set_item("TargetIdentifier", Plist::new_string(&self.udid))
+            .map_err(|_| MobileBackup2Error::PlistError)?;
+        for (key, password) in [("OldPassword", old), ("NewPassword", new)] {
+            if let Some(password) = password {
+                options
+                    .dict_set_item(key, Plist::new_string(&password))
+                    .map_err(|_| MobileBackup2Error::PlistError)?;
+            }
+        }
+
+        self.client
+            .send_message(Some("ChangePassword".to_string()), options)?;
+        self.run()?;
+        Ok(())
+    }
+
+    /// Reads which domain every file in the backup belongs to, so progress can be reported per domain.
+    /// Encrypted or legacy manifests are skipped and only overall progress is reported.
+    fn load_domains(&mut self, backup_dir: &Path) {
+        self.file_domains.clear();
+        self.domains.clear();
+        let files = match Database::open(&backup_dir.join("Manifest.db"))
+            .and_then(|database| database.table("Files"))
+        {
+            Ok(files) => files,
+            Err(e) => {
+                info!(
+                    "Unable to read Manifest.db, domain progress is unavailable: {}",
+                    e
+                );
+                return;
+            }
+        };
+        let (file_id, domain) = match (files.column("fileID"), files.column("domain")) {
+            (Some(file_id), Some(domain)) => (file_id, domain),
+            _ => return,
+        };
+        for row in &files.rows {
+            if let (Some(file_id), Some(domain)) = (
+                row.get(file_id).and_then(|v| v.as_str()),
+                row.get(domain).and_then(|v| v.as_str()),
+            ) {
+                self.file_domains
+                    .insert(file_id.to_string(), domain.to_string());
+                self.domains
+                    .entry(domain.to_string())
+                    .or_insert_with(|| DomainProgress {
+                        domain: domain.to_string(),
+                        ..Default::default()
+                    })
+                    .files_total += 1;
+            }
+        }
+    }
+
+    /// Serves the device's requests until it reports the operation is over
+    /// # Returns
+    /// The content of the final DLMessageProcessMessage
+    pub(crate) fn run(&mut self) -> Result<Plist, MobileBackup2Error> {
+        loop {
+            let (name, message) = match self.client.receive_message() {
+                Ok(message) => message,
+                Err(MobileBackup2Error::ReceiveTimeout) => {
+                    info!("Device is not ready yet, waiting");
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Lost connection to mobilebackup2: {}", e);
+                    return Err(MobileBackup2Error::Disconnected);
+                }
+            };
+
+            match name.as_str() {
+                "DLMessageDownloadFiles" => {
+                    self.update_percent(&message, 3);
+                    let paths = message_item(&message, 1)
+                        .map(|paths| string_array(&paths))
+                        .unwrap_or_default();
+                    self.send_files(&paths)?;
+                }
+                "DLMessageUploadFiles" => {
+                    self.update_percent(&message, 2);
+                    self.receive_files()?;
+                }
+                "DLMessageGetFreeDiskSpace" => {
+                    let free = free_disk_space(&self.root);
+                    self.client
+                        .send_status_response(0, None, Some(Plist::new_uint(free)))?;
+                }
+                "DLContentsOfDirectory" => {
+                    let path = message_string(&message, 1).unwrap_or_default();
+                    let contents = self.contents_of_directory(&path);
+                    self.client.send_status_response(0, None, Some(contents))?;
+                }
+                "DLMessageCreateDirectory" => {
+                    let path = message_string(&message, 1).unwrap_or_default();
+                    let result = self
+                        .local_path(&path)
+                        .and_then(|path| fs::create_dir_all(path).map_err(io_error));
+                    self.send_result(result)?;
+                }
+                "DLMessageMoveFiles" | "DLMessageMoveItems" => {
+                    self.update_percent(&message, 3);
+                    let moves = message_item(&message, 1)
+                        .map(|moves| dict_entries(&moves))
+                        .unwrap_or_default();
+                    let result = moves.iter().try_for_each(|(from, to)| {
+                        let to = to
+                            .get_string_val()
+                            .map_err(|_| MobileBackup2Error::PlistError)?;
+                        self.move_item(from, &to)
+                    });
+                    self.send_result(result)?;
+                }
+                "DLMessageRemoveFiles" | "DLMessageRemoveItems" => {
+                    self.update_percent(&message, 3);
+                    let paths = message_item(&message, 1)
+                        .map(|paths| string_array(&paths))
+                        .unwrap_or_default();
+                    let result = paths.iter().try_for_each(|path| self.remove_item(path));
+                    self.send_result(result)?;
+                }
+                "DLMessageCopyItem" => {
+                    let from = message_string(&message, 1).unwrap_or_default();
+                    let to = message_string(&message, 2).unwrap_or_default();
+                    let result = self.local_path(&from).and_then(|from| {
+                        copy_recursive(&from, &self.local_path(&to)?).map_err(io_error)
+                    });
+                    self.send_result(result)?;
+                }
+                "DLMessageProcessMessage" => {
+                    let content = message_item(&message, 1)
+                        .filter(|content| content.plist_type == PlistType::Dictionary)
+                        .ok_or(MobileBackup2Error::InvalidResponse)?;
+                    let code = dict_uint(&content, "ErrorCode").unwrap_or(0);
+                    if code != 0 {
+                        warn!(
+                            "Device reported error {}: {}",
+                            code,
+                            dict_string(&content, "ErrorDescription").unwrap_or_default()
+                        );
+                        return Err(MobileBackup2Error::DeviceError);
+                    }
+                    self.progress.percent = 100.0;
+                    self.report();
+                    return Ok(content);
+                }
+                "DLMessageDisconnect" => {
+                    warn!("Device disconnected before finishing");
+                    return Err(MobileBackup2Error::Disconnected);
+                }
+                other => {
+                    warn!("Unsupported DeviceLink message {}", other);
+                    self.client.send_status_response(
+                        -1,
+                        Some("Operation not supported".to_string()),
+                        Some(Plist::new_dict()),
+                    )?;
+                }
+            }
+        }
+    }
+
+    /// Sends files from the backup directory to the device
+    fn send_files(&mut self, paths: &[String]) -> Result<(), MobileBackup2Error> {
+        let mut errors = Plist::new_dict();
+        let mut failed = false;
+        for path in paths {
+            if let Err(e) = self.send_file(path)? {
+                let description = e.to_string();
+                let mut error = Plist::new_dict();
+                for (key, value) in [
+                    ("DLFileErrorString", Plist::new_string(&description)),
+                    (
+                        "DLFileErrorCode",
+                        Plist::new_uint(device_error_code(&e) as u64),
+                    ),
+                ] {
+                    error
+                        .dict_set_item(key, value)
+                        .map_err(|_| MobileBackup2Error::PlistError)?;
+                }
+                errors
+                    .dict_set_item(path, error)
+                    .map_err(|_| MobileBackup2Error::PlistError)?;
+                failed = true;
+            }
+        }
+        // A zero length marks the end of the batch
+        self.send_all(&0u32.to_be_bytes())?;
+
+        if failed {
+            self.client.send_status_response(
+                MULTI_STATUS,
+                Some("Multi status".to_string()),
+                Some(errors),
+            )
+        } else {
+            self.client
+                .send_status_response(0, None, Some(Plist::new_dict()))
+        }
+    }
+
+    /// Sends a single file. Local failures are reported to the device rather than ending the backup.
+    fn send_file(&mut self, path: &str) -> Result<std::io::Result<()>, MobileBackup2Error> {
+        self.send_all(&(path.len() as u32).to_be_bytes())?;
+        self.send_all(path.as_bytes())?;
+
+        let file = self
+            .local_path(path)
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))
+            .and_then(fs::File::open);
+        let mut file = match file {
+            Ok(file) => file,
+            Err(e) => {
+                self.send_local_error(&e)?;
+                return Ok(Err(e));
+            }
+        };
+
+        let mut buffer = vec![0; TRANSFER_CHUNK];
+        loop {
+            let read = match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => {
+                    self.send_local_error(&e)?;
+                    return Ok(Err(e));
+                }
+            };
+            self.send_all(&(read as u32 + 1).to_be_bytes())?;
+            self.send_all(&[CODE_FILE_DATA])?;
+            self.send_all(&buffer[..read])?;
+            self.progress.bytes_sent += read as u64;
+        }
+
+        self.send_all(&1u32.to_be_bytes())?;
+        self.send_all(&[CODE_SUCCESS])?;
+        self.progress.files_sent += 1;
+        let file_id = path.rsplit('/').next().unwrap_or(path);
+        if let Some(domain) = self
+            .file_domains
+            .get(file_id)
+            .and_then(|domain| self.domains.get_mut(domain))
+        {
+            domain.files_sent += 1;
+            self.progress.domain = Some(domain.clone());
+        }
+        self.report();
+        Ok(Ok(()))
+    }
+
+    fn send_local_error(&self, error: &std::io::Error) -> Result<(), MobileBackup2Error> {
+        let description = error.to_string();
+        self.send_all(&(description.len() as u32 + 1).to_be_bytes())?;
+        self.send_all(&[CODE_ERROR_LOCAL])?;
+        self.send_all(description.as_bytes())
+    }
+
+    /// Receives files from the device into the backup directory
+    fn receive_files(&mut self) -> Result<(), MobileBackup2Error> {
+        loop {
+            let device_name_len = self.receive_u32()?;
+            if device_name_len == 0 {
+                break;
+            }
+            // The device name is not needed, the path is relative to the backup root
+            self.receive_exact(device_name_len as usize)?;
+            let path_len = self.receive_u32()?;
+            let path =
+                String::from_utf8_lossy(&self.receive_exact(path_len as usize)?).into_owned();
+
+            let local = self.local_path(&path)?;
+            if let Some(parent) = local.parent() {
+                fs::create_dir_all(parent).map_err(io_error)?;
+            }
+            let mut file = fs::File::create(&local).map_err(io_error)?;
+
+            let mut length = self.receive_u32()?;
+            let mut code = self.receive_exact(1)?[0];
+            while code == CODE_FILE_DATA {
+                let mut remaining = length.saturating_sub(1) as usize;
+                while remaining > 0 {
+                    let chunk = self.receive_exact(remaining.min(TRANSFER_CHUNK))?;
+                    file.write_all(&chunk).map_err(io_error)?;
+                    remaining -= chunk.len();
+                    self.progress.bytes_received += chunk.len() as u64;
+                }
+                length = self.receive_u32()?;
+                code = self.receive_exact(1)?[0];
+            }
+
+            let trailer = self.receive_exact(length.saturating_sub(1) as usize)?;
+            match code {
+                CODE_SUCCESS => {
+                    self.progress.files_received += 1;
+                    self.report();
+                }
+                CODE_ERROR_REMOTE => warn!(
+                    "Device failed to send {}: {}",
+                    path,
+                    String::from_utf8_lossy(&trailer)
+                ),
+                other => warn!("Unknown transfer code {:#x} for {}", other, path),
+            }
+        }
+
+        self.client
+            .send_status_response(0, None, Some(Plist::new_dict()))
+    }
+
+    fn contents_of_directory(&self, path: &str) -> Plist {
+        let mut contents = Plist::new_dict();
+        let entries = self
+            .local_path(path)
+            .ok()
+            .and_then(|path| fs::read_dir(path).ok());
+        for entry in entries.into_iter().flatten().flatten() {
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            let file_type = if metadata.is_dir() {
+                "DLFileTypeDirectory"
+            } else if metadata.is_file() {
+                "DLFileTypeRegular"
+            } else {
+                "DLFileTypeUnknown"
+            };
+            let mut info = Plist::new_dict();
+            let _ = info.dict_set_item("DLFileType", Plist::new_string(file_type));
+            let _ = info.dict_set_item("DLFileSize", Plist::new_uint(metadata.len()));
+            if let Ok(modified) = metadata.modified() {
+                let _ = info.dict_set_item("DLFileModificationDate", new_date(modified));
+            }
+            let _ = contents.dict_set_item(&entry.file_name().to_string_lossy(), info);
+        }
+        contents
+    }
+
+    fn move_item(&self, from: &str, to: &str) -> Result<(), MobileBackup2Error> {
+        let from = self.local_path(from)?;
+        let to = self.local_path(to)?;
+        remove_recursive(&to).map_err(io_error)?;
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        fs::rename(from, to).map_err(io_error)
+    }
+
+    fn remove_item(&self, path: &str) -> Result<(), MobileBackup2Error> {
+        remove_recursive(&self.local_path(path)?).map_err(io_error)
+    }
+
+    /// Tells the device whether a file system operation worked
+    fn send_result(
+        &self,
+        result: Result<(), MobileBackup2Error>,
+    ) -> Result<(), MobileBackup2Error> {
+        match result {
+            Ok(()) => self
+                .client
+                .send_status_response(0, None, Some(Plist::new_dict())),
+            Err(e) => {
+                self.client
+                    .send_status_response(-1, Some(e.to_string()), Some(Plist::new_dict()))
+            }
+        }
+    }
+
+    /// Resolves a path sent by the device against the backup root.
+    /// Paths that would escape the root are refused.
+    fn local_path(&self, path: &str) -> Result<PathBuf, MobileBackup2Error> {
+        let relative = Path::new(path.trim_start_matches('/'));
+        if relative
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            warn!("Refusing to touch {} outside of the backup root", path);
+            return Err(MobileBackup2Error::InvalidArg);
+        }
+        Ok(self.root.join(relative))
+    }
+
+    fn update_percent(&mut self, message: &Plist, index: u32) {
+        if let Some(percent) = message_item(message, index)
+            .filter(|item| item.plist_type == PlistType::Real)
+            .and_then(|item| item.get_real_val().ok())
+        {
+            self.progress.percent = percent;
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        if let Some(callback) = self.callback.as_mut() {
+            callback(&self.progress);
+        }
+    }
+
+    fn send_all(&self, data: &[u8]) -> Result<(), MobileBackup2Error> {
+        let mut sent = 0;
+        while sent < data.len() {
+            match self.client.send_raw(&data[sent..])? {
+                0 => return Err(MobileBackup2Error::Disconnected),
+                count => sent += count as usize,
+            }
+        }
+        Ok(())
+    }
+
+    fn receive_exact(&self, len: usize) -> Result<Vec<u8>, MobileBackup2Error> {
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            let chunk = self.client.receive_raw((len - data.len()) as u32)?;
+            if chunk.is_empty() {
+                return Err(MobileBackup2Error::Disconnected);
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    fn receive_u32(&self) -> Result<u32, MobileBackup2Error> {
+        let bytes = self.receive_exact(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+/// Builds Info.plist from the values lockdownd reports
+pub(crate) fn info_plist(device: &Device, udid: &str) -> Result<Plist, LockdowndError> {
+    let lockdown = device.new_lockdownd_client("backup_engine")?;
+    let values = lockdown.get_value("", "")?;
+
+    let mut info = Plist::new_dict();
+    for (lockdown_key, info_key) in INFO_KEYS {
+        if let Some(value) = dict_string(&values, lockdown_key) {
+            let _ = info.dict_set_item(info_key, Plist::new_string(&value));
+        }
+    }
+    for (key, value) in [
+        ("Last Backup Date", new_date(SystemTime::now())),
+        ("Target Identifier", Plist::new_string(udid)),
+        ("Target Type", Plist::new_string("Device")),
+        ("Unique Identifier", Plist::new_string(&udid.to_uppercase())),
+        ("iTunes Version", Plist::new_string("10.0.1")),
+    ] {
+        let _ = info.dict_set_item(key, value);
+    }
+    Ok(info)
+}
+
+/// Gets a copy of an argument of a DeviceLink message
+fn message_item(message: &Plist, index: u32) -> Option<Plist> {
+    array_items(message).into_iter().nth(index as usize)
+}
+
+fn message_string(message: &Plist, index: u32) -> Option<String> {
+    message_item(message, index)
+        .filter(|item| item.plist_type == PlistType::String)
+        .and_then(|item| item.get_string_val().ok())
+}
+
+/// Maps a host error to the error codes the device understands
+fn device_error_code(error: &std::io::Error) -> i64 {
+    match error.raw_os_error() {
+        Some(libc::ENOENT) => -6,
+        Some(libc::EEXIST) => -7,
+        Some(libc::ENOTDIR) => -8,
+        Some(libc::EISDIR) => -9,
+        Some(libc::ELOOP) => -10,
+        Some(libc::EIO) => -11,
+        Some(libc::ENOSPC) => -15,
+        _ => -1,
+    }
+}
+
+#[cfg(unix)]
+fn free_disk_space(path: &Path) -> u64 {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = match std::ffi::CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return 0,
+    };
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return 0;
+    }
+    stats.f_bavail as u64 * stats.f_frsize as u64
+}
+
+#[cfg(not(unix))]
+fn free_disk_space(_path: &Path) -> u64 {
+    warn!("Free disk space is not supported on this platform");
+    0
+}
+
+fn remove_recursive(path: &Path) -> std::io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> std::io::Result<()> {
+    if fs::metadata(from)?.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(from, to).map(|_| ())
+    }
+}
+
+pub(crate) fn read_plist(path: &Path) -> Result<Plist, MobileBackup2Error> {
+    let data = fs::read(path).map_err(io_error)?;
+    Plist::from_memory(data).map_err(|_| MobileBackup2Error::PlistError)
+}
+
+fn write_plist(path: &Path, plist: &Plist) -> Result<(), MobileBackup2Error> {
+    fs::write(path, plist.to_string()).map_err(io_error)
+}
+
+fn io_error(error: std::io::Error) -> MobileBackup2Error {
+    warn!("Backup directory access failed: {}", error);
+    MobileBackup2Error::IoError
+}
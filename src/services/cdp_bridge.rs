@@ -0,0 +1,1004 @@
+// jkcoxson
+// Serves the device's inspectable pages over the Chrome DevTools Protocol
+
+use std::{
+    collections::HashMap,
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::{info, warn};
+use serde_json::{json, Map, Value};
+use tungstenite::{Message, WebSocket};
+
+use crate::{
+    error::WebInspectorError,
+    idevice::Device,
+    services::inspector_session::{
+        InspectorEvent, InspectorPage, InspectorPageType, InspectorSession, InspectorSocket,
+    },
+};
+
+/// How long a new connection has to send its HTTP request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// The path each page's WebSocket is served under
+const PAGE_PATH: &str = "/devtools/page/";
+/// Commands with no WebKit counterpart that CDP clients send while attaching.
+/// They're answered locally with an empty result.
+const IGNORED_COMMANDS: [&str; 12] = [
+    "Target.setAutoAttach",
+    "Target.setDiscoverTargets",
+    "Runtime.runIfWaitingForDebugger",
+    "Page.setLifecycleEventsEnabled",
+    "Page.setBypassCSP",
+    "Log.startViolationsReport",
+    "Performance.enable",
+    "Performance.disable",
+    "Emulation.setDeviceMetricsOverride",
+    "Emulation.setTouchEmulationEnabled",
+    "Emulation.setFocusEmulationEnabled",
+    "Network.setCacheDisabled",
+];
+
+/// A connection whose HTTP request hasn't fully arrived yet
+struct PendingConnection {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+/// A DevTools client attached to a page
+struct CdpClient {
+    socket: WebSocket<TcpStream>,
+    inspector: InspectorSocket,
+    page: InspectorPage,
+    /// Pages on newer versions of iOS only accept messages wrapped for their target
+    target_id: Option<String>,
+    /// Commands waiting for the page's target to be created
+    queued: Vec<Value>,
+    /// The CDP id and method of each command sent to the page, by the id it was sent with
+    pending: HashMap<u64, (Value, String)>,
+    next_id: u64,
+}
+
+/// A local server exposing the device's pages to Chrome DevTools Protocol clients.
+/// Pages are listed at `/json` and attached to over WebSockets at `/devtools/page/<id>`.
+pub struct CdpBridge<'a> {
+    session: InspectorSession<'a>,
+    listener: TcpListener,
+    connections: Vec<PendingConnection>,
+    clients: Vec<CdpClient>,
+}
+
+impl<'a> CdpBridge<'a> {
+    /// Starts the web inspector on the device and binds the server
+    /// # Arguments
+    /// * `device` - The device whose pages to serve
+    /// * `address` - The address to listen on, such as `127.0.0.1:9222`
+    /// # Returns
+    /// The bridge, which serves requests while `poll` or `run` are called
+    ///
+    /// ***Verified:*** False
+    pub fn new(device: &'a Device, address: impl ToSocketAddrs) -> Result<Self, WebInspectorError> {
+        let listener = TcpListener::bind(address).map_err(io_error)?;
+        listener.set_nonblocking(true).map_err(io_error)?;
+        let session = InspectorSession::new(device)?;
+        info!(
+            "Serving DevTools for {} at http://{}/json",
+            device.get_udid(),
+            listener.local_addr().map_err(io_error)?
+        );
+        Ok(CdpBridge {
+            session,
+            listener,
+            connections: Vec::new(),
+            clients: Vec::new(),
+        })
+    }
+
+    /// Gets the address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr, WebInspectorError> {
+        self.listener.local_addr().map_err(io_error)
+    }
+
+    /// Serves requests until the inspector connection fails
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The error that stopped the bridge
+    ///
+    /// ***Verified:*** False
+    pub fn run(&mut self) -> Result<(), WebInspectorError> {
+        loop {
+            self.poll(Duration::from_millis(50))?;
+        }
+    }
+
+    /// Accepts new connections, forwards messages from DevTools clients and delivers messages from the device
+    /// # Arguments
+    /// * `timeout` - How long to wait for messages from the device
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn poll(&mut self, timeout: Duration) -> Result<(), WebInspectorError> {
+        loop {
+            match self.listener.accept() {
+                // Requests are read as they arrive, a slow client mustn't hold up the others
+                Ok((stream, _)) => match stream.set_nonblocking(true) {
+                    Ok(()) => self.connections.push(PendingConnection {
+                        stream,
+                        deadline: Instant::now() + REQUEST_TIMEOUT,
+                    }),
+                    Err(e) => warn!("Dropping DevTools connection: {}", e),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+        self.route_connections();
+
+        let mut index = 0;
+        while index < self.clients.len() {
+            if self.read_client(index)? {
+                index += 1;
+            } else {
+                let client = self.clients.remove(index);
+                info!("DevTools client for page {} detached", client.page.id);
+                self.session.close_socket(client.inspector)?;
+            }
+        }
+
+        let mut wait = timeout;
+        while let Some(event) = self.session.next_event(wait)? {
+            // Drain whatever else has already arrived without waiting again
+            wait = Duration::from_millis(1);
+            match event {
+                InspectorEvent::SocketData { sender_id, message } => {
+                    if let Some(client) = self
+                        .clients
+                        .iter_mut()
+                        .find(|client| client.inspector.sender_id == sender_id)
+                    {
+                        client.handle_page_message(&mut self.session, message)?;
+                    }
+                }
+                InspectorEvent::ApplicationDisconnected(app_id) => self
+                    .clients
+                    .retain(|client| client.inspector.app_id != app_id),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Routes the connections whose requests have arrived, dropping the ones that took too long
+    fn route_connections(&mut self) {
+        // Fetched at most once per poll, however many connections need it
+        let mut pages = None;
+        let mut index = 0;
+        while index < self.connections.len() {
+            let connection = &self.connections[index];
+            let path = match peek_request_path(&connection.stream) {
+                Ok(Some(path)) => path,
+                Ok(None) if Instant::now() < connection.deadline => {
+                    index += 1;
+                    continue;
+                }
+                Ok(None) => {
+                    warn!("Dropping DevTools connection that didn't send a request in time");
+                    self.connections.remove(index);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Dropping DevTools connection: {}", e);
+                    self.connections.remove(index);
+                    continue;
+                }
+            };
+            let connection = self.connections.remove(index);
+            if let Err(e) = self.accept(connection.stream, &path, &mut pages) {
+                warn!("Dropping DevTools connection: {}", e);
+            }
+        }
+    }
+
+    /// Routes a connection to the listing or to a page's WebSocket, once its request has arrived
+    fn accept(
+        &mut self,
+        stream: TcpStream,
+        path: &str,
+        pages: &mut Option<Vec<InspectorPage>>,
+    ) -> Result<(), WebInspectorError> {
+        // The whole request is already buffered, so these only wait on the socket itself
+        stream.set_nonblocking(false).map_err(io_error)?;
+        stream
+            .set_read_timeout(Some(REQUEST_TIMEOUT))
+            .map_err(io_error)?;
+        stream
+            .set_write_timeout(Some(REQUEST_TIMEOUT))
+            .map_err(io_error)?;
+
+        if let Some(id) = path.strip_prefix(PAGE_PATH) {
+            let page = self
+                .pages(pages)?
+                .iter()
+                .find(|page| page_id(page) == id)
+                .cloned()
+                .ok_or(WebInspectorError::PageNotFound)?;
+            let socket = tungstenite::accept(stream).map_err(|e| {
+                warn!("WebSocket handshake failed: {}", e);
+                WebInspectorError::IoError
+            })?;
+            socket.get_ref().set_nonblocking(true).map_err(io_error)?;
+            let inspector = self.session.open_socket(&page.app_id, page.id)?;
+            info!("DevTools client attached to page {}", page.id);
+            self.clients.push(CdpClient {
+                socket,
+                inspector,
+                page,
+                target_id: None,
+                queued: Vec::new(),
+                pending: HashMap::new(),
+                next_id: 1,
+            });
+            return Ok(());
+        }
+
+        let host = stream.local_addr().map_err(io_error)?;
+        let (status, body) = match path.trim_end_matches('/') {
+            "/json" | "/json/list" => {
+                let pages = self.pages(pages)?;
+                (
+                    "200 OK",
+                    Value::Array(pages.iter().map(|page| listing(page, host)).collect()),
+                )
+            }
+            "/json/version" => (
+                "200 OK",
+                json!({
+                    "Browser": "Mobile Safari",
+                    "Protocol-Version": "1.3",
+                    "User-Agent": "rusty_libimobiledevice",
+                }),
+            ),
+            _ => ("404 Not Found", json!({ "error": "Not found" })),
+        };
+        respond(stream, status, &body)
+    }
+
+    /// Lists the pages of every application, unless they've already been listed
+    fn pages<'p>(
+        &mut self,
+        pages: &'p mut Option<Vec<InspectorPage>>,
+    ) -> Result<&'p [InspectorPage], WebInspectorError> {
+        if pages.is_none() {
+            let mut listed = Vec::new();
+            for app in self.session.applications()? {
+                listed.extend(self.session.pages(&app.id)?);
+            }
+            *pages = Some(listed);
+        }
+        Ok(pages.as_deref().unwrap_or_default())
+    }
+
+    /// Forwards everything a client has sent. Returns false once the client is gone.
+    fn read_client(&mut self, index: usize) -> Result<bool, WebInspectorError> {
+        let client = &mut self.clients[index];
+        match client.socket.flush() {
+            Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(_) => return Ok(false),
+            Ok(()) => {}
+        }
+        loop {
+            let client = &mut self.clients[index];
+            let message = match client.socket.read() {
+                Ok(Message::Text(text)) => text,
+                Ok(Message::Close(_)) => return Ok(false),
+                Ok(_) => continue,
+                Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => {
+                    return Ok(true)
+                }
+                Err(_) => return Ok(false),
+            };
+            match serde_json::from_str::<Value>(&message) {
+                Ok(command) => client.handle_command(&mut self.session, command)?,
+                Err(e) => warn!("Ignoring DevTools message that isn't JSON: {}", e),
+            }
+        }
+    }
+}
+
+impl CdpClient {
+    /// Translates a CDP command and sends it to the page, or answers it here
+    fn handle_command(
+        &mut self,
+        session: &mut InspectorSession,
+        command: Value,
+    ) -> Result<(), WebInspectorError> {
+        let cdp_id = command.get("id").cloned().unwrap_or(Value::Null);
+        let method = command
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let params = command.get("params").cloned().unwrap_or_else(|| json!({}));
+
+        if IGNORED_COMMANDS.contains(&method.as_str()) {
+            self.send_to_client(json!({ "id": cdp_id, "result": {} }));
+            return Ok(());
+        }
+        if method == "Runtime.enable" {
+            // CDP reports console calls through Runtime, WebKit through Console
+            self.send_to_page(session, "Console.enable", json!({}), None)?;
+        }
+        let (webkit_method, webkit_params) = translate_command(&method, params);
+        let webkit_method = webkit_method.to_string();
+        self.send_to_page(
+            session,
+            &webkit_method,
+            webkit_params,
+            Some((cdp_id, method)),
+        )
+    }
+
+    /// Sends a command to the page, remembering which CDP command it answers
+    fn send_to_page(
+        &mut self,
+        session: &mut InspectorSession,
+        method: &str,
+        params: Value,
+        origin: Option<(Value, String)>,
+    ) -> Result<(), WebInspectorError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        if let Some(origin) = origin {
+            self.pending.insert(id, origin);
+        }
+        let message = json!({ "id": id, "method": method, "params": params });
+
+        if self.page.page_type != InspectorPageType::WebPage {
+            return session.send_message(&self.inspector, &message);
+        }
+        match self.target_id.clone() {
+            Some(target_id) => self.send_to_target(session, &target_id, &message),
+            None => {
+                self.queued.push(message);
+                Ok(())
+            }
+        }
+    }
+
+    fn send_to_target(
+        &mut self,
+        session: &mut InspectorSession,
+        target_id: &str,
+        message: &Value,
+    ) -> Result<(), WebInspectorError> {
+        // The wrapper gets its own id, its empty reply isn't passed on
+        let id = self.next_id;
+        self.next_id += 1;
+        let wrapper = json!({
+            "id": id,
+            "method": "Target.sendMessageToTarget",
+            "params": { "targetId": target_id, "message": message.to_string() },
+        });
+        session.send_message(&self.inspector, &wrapper)
+    }
+
+    /// Handles a message from the page, passing it on to the client in CDP form
+    fn handle_page_message(
+        &mut self,
+        session: &mut InspectorSession,
+        message: Value,
+    ) -> Result<(), WebInspectorError> {
+        let method = message.get("method").and_then(Value::as_str);
+        let params = message.get("params");
+        match method {
+            Some("Target.targetCreated") => {
+                let target = params.and_then(|params| params.get("targetInfo"));
+                let is_page = target
+                    .and_then(|target| target.get("type"))
+                    .and_then(Value::as_str)
+                    .is_none_or(|kind| kind == "page");
+                if let (true, Some(target_id)) = (
+                    is_page,
+                    target
+                        .and_then(|target| target.get("targetId"))
+                        .and_then(Value::as_str),
+                ) {
+                    let target_id = target_id.to_string();
+                    self.target_id = Some(target_id.clone());
+                    for queued in std::mem::take(&mut self.queued) {
+                        self.send_to_target(session, &target_id, &queued)?;
+                    }
+                }
+                return Ok(());
+            }
+            Some("Target.targetDestroyed") => {
+                let destroyed = params
+                    .and_then(|params| params.get("targetId"))
+                    .and_then(Value::as_str);
+                if destroyed == self.target_id.as_deref() {
+                    self.target_id = None;
+                }
+                return Ok(());
+            }
+            Some("Target.dispatchMessageFromTarget") => {
+                let inner = params
+                    .and_then(|params| params.get("message"))
+                    .and_then(Value::as_str)
+                    .and_then(|inner| serde_json::from_str(inner).ok());
+                if let Some(inner) = inner {
+                    return self.handle_page_message(session, inner);
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let translated = match message.get("id").and_then(Value::as_u64) {
+            Some(id) => match self.pending.remove(&id) {
+                Some((cdp_id, cdp_method)) => translate_response(&cdp_method, cdp_id, message),
+                // Replies to commands the bridge sent itself
+                None => return Ok(()),
+            },
+            None => translate_event(message),
+        };
+        self.send_to_client(translated);
+        Ok(())
+    }
+
+    fn send_to_client(&mut self, message: Value) {
+        match self.socket.send(Message::Text(message.to_string())) {
+            Ok(()) => {}
+            // The message is buffered and goes out with a later flush
+            Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => warn!("Unable to send to DevTools client: {}", e),
+        }
+    }
+}
+
+/// Maps a CDP command to the WebKit command that does the same thing
+fn translate_command(method: &str, params: Value) -> (&str, Value) {
+    match method {
+        "Log.enable" => ("Console.enable", json!({})),
+        "Log.disable" => ("Console.disable", json!({})),
+        "Log.clear" => ("Console.clearMessages", json!({})),
+        "Page.navigate" => (
+            "Page.navigate",
+            json!({ "url": params.get("url").cloned().unwrap_or(Value::Null) }),
+        ),
+        "Runtime.evaluate" => {
+            let mut params = params;
+            if let Some(params) = params.as_object_mut() {
+                rename(params, "userGesture", "emulateUserGesture");
+                rename(params, "silent", "doNotPauseOnExceptionsAndMuteConsole");
+                for unsupported in [
+                    "timeout",
+                    "throwOnSideEffect",
+                    "replMode",
+                    "uniqueContextId",
+                ] {
+                    params.remove(unsupported);
+                }
+            }
+            ("Runtime.evaluate", params)
+        }
+        _ => (method, params),
+    }
+}
+
+/// Puts a WebKit reply in the shape the CDP command expects
+fn translate_response(method: &str, cdp_id: Value, mut message: Value) -> Value {
+    if let Some(object) = message.as_object_mut() {
+        object.insert("id".to_string(), cdp_id);
+    }
+    let result = match message.get_mut("result").and_then(Value::as_object_mut) {
+        Some(result) => result,
+        None => return message,
+    };
+    match method {
+        "Page.navigate" => {
+            result
+                .entry("frameId")
+                .or_insert_with(|| Value::String("main".to_string()));
+            result
+                .entry("loaderId")
+                .or_insert_with(|| Value::String(String::new()));
+        }
+        "Runtime.evaluate" | "Runtime.callFunctionOn" => {
+            let thrown = result
+                .remove("wasThrown")
+                .and_then(|thrown| thrown.as_bool());
+            result.remove("savedResultIndex");
+            if thrown == Some(true) {
+                let exception = result.get("result").cloned().unwrap_or(Value::Null);
+                let text = exception
+                    .get("description")
+                    .and_then(Value::as_str)
+                    .unwrap_or("Uncaught")
+                    .to_string();
+                result.insert(
+                    "exceptionDetails".to_string(),
+                    json!({
+                        "exceptionId": 0,
+                        "text": text,
+                        "lineNumber": 0,
+                        "columnNumber": 0,
+                        "exception": exception,
+                    }),
+                );
+            }
+        }
+        _ => {}
+    }
+    message
+}
+
+/// Puts a WebKit event in the shape CDP clients expect
+fn translate_event(mut message: Value) -> Value {
+    let method = message
+        .get("method")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    let params = match message.get_mut("params").and_then(Value::as_object_mut) {
+        Some(params) => params,
+        None => return message,
+    };
+    match method.as_str() {
+        "Console.messageAdded" => {
+            let console = params.get("message").cloned().unwrap_or(Value::Null);
+            return json!({
+                "method": "Runtime.consoleAPICalled",
+                "params": console_api_call(&console),
+            });
+        }
+        "Runtime.executionContextCreated" => {
+            if let Some(context) = params.get_mut("context").and_then(Value::as_object_mut) {
+                let is_default = context.get("type").and_then(Value::as_str) == Some("normal");
+                let frame_id = context.remove("frameId").unwrap_or(Value::Null);
+                context.remove("type");
+                context
+                    .entry("origin")
+                    .or_insert_with(|| Value::String(String::new()));
+                context.insert(
+                    "auxData".to_string(),
+                    json!({
+                        "isDefault": is_default,
+                        "type": if is_default { "default" } else { "isolated" },
+                        "frameId": frame_id,
+                    }),
+                );
+            }
+        }
+        method if method.starts_with("Network.") => {
+            rename(params, "walltime", "wallTime");
+        }
+        _ => {}
+    }
+    message
+}
+
+/// Builds the parameters of `Runtime.consoleAPICalled` from a WebKit console message
+fn console_api_call(console: &Value) -> Value {
+    let level = console
+        .get("level")
+        .and_then(Value::as_str)
+        .unwrap_or("log");
+    let kind = match console.get("type").and_then(Value::as_str) {
+        None | Some("log") => match level {
+            "warning" => "warning",
+            "error" => "error",
+            "debug" => "debug",
+            "info" => "info",
+            _ => "log",
+        },
+        Some(kind) => kind,
+    };
+    let args = match console.get("parameters") {
+        Some(Value::Array(parameters)) => Value::Array(parameters.clone()),
+        _ => json!([{
+            "type": "string",
+            "value": console.get("text").cloned().unwrap_or(Value::Null),
+        }]),
+    };
+    // WebKit reports seconds, CDP expects milliseconds
+    let timestamp = console
+        .get("timestamp")
+        .and_then(Value::as_f64)
+        .map(|seconds| seconds * 1000.0)
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|since| since.as_secs_f64() * 1000.0)
+                .unwrap_or_default()
+        });
+    let mut call = json!({
+        "type": kind,
+        "args": args,
+        "executionContextId": 0,
+        "timestamp": timestamp,
+    });
+    let frames = match console.get("stackTrace") {
+        Some(Value::Array(frames)) => Some(Value::Array(frames.clone())),
+        Some(trace) => trace.get("callFrames").cloned(),
+        None => None,
+    };
+    if let Some(frames) = frames {
+        call["stackTrace"] = json!({ "callFrames": frames });
+    }
+    call
+}
+
+fn rename(object: &mut Map<String, Value>, from: &str, to: &str) {
+    if let Some(value) = object.remove(from) {
+        object.insert(to.to_string(), value);
+    }
+}
+
+fn page_id(page: &InspectorPage) -> String {
+    format!("{}/{}", page.app_id, page.id)
+}
+
+/// Describes a page the way Chrome's `/json` endpoint does
+fn listing(page: &InspectorPage, host: SocketAddr) -> Value {
+    let id = page_id(page);
+    let kind = match page.page_type {
+        InspectorPageType::Web | InspectorPageType::WebPage => "page",
+        InspectorPageType::ServiceWorker => "service_worker",
+        _ => "other",
+    };
+    json!({
+        "description": "",
+        "id": id,
+        "title": page.title.clone().unwrap_or_default(),
+        "type": kind,
+        "url": page.url.clone().unwrap_or_default(),
+        "webSocketDebuggerUrl": format!("ws://{}{}{}", host, PAGE_PATH, id),
+        "devtoolsFrontendUrl": format!("/devtools/inspector.html?ws={}{}{}", host, PAGE_PATH, id),
+    })
+}
+
+/// Reads the path of an HTTP request without consuming it,
+/// so a WebSocket handshake can still read it.
+/// Returns None until the request's headers have all arrived.
+fn peek_request_path(stream: &TcpStream) -> Result<Option<String>, WebInspectorError> {
+    let mut buffer = [0u8; 4096];
+    let read = match stream.peek(&mut buffer) {
+        Ok(0) => return Err(WebInspectorError::InvalidMessage),
+        Ok(read) => read,
+        Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(None),
+        Err(e) => return Err(io_error(e)),
+    };
+    let request = &buffer[..read];
+    if !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        return if read == buffer.len() {
+            Err(WebInspectorError::InvalidMessage)
+        } else {
+            Ok(None)
+        };
+    }
+    let end = request
+        .windows(2)
+        .position(|window| window == b"\r\n")
+        .unwrap_or(read);
+    let line = String::from_utf8_lossy(&request[..end]);
+    let mut parts = line.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) => Ok(Some(path.split('?').next().unwrap_or(path).to_string())),
+        _ => Err(WebInspectorError::InvalidMessage),
+    }
+}
+
+fn respond(mut stream: TcpStream, status: &str, body: &Value) -> Result<(), WebInspectorError> {
+    // Consume the request so closing the socket doesn't reset the connection
+    let mut request = [0u8; 4096];
+    let _ = stream.read(&mut request);
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: application/json; charset=UTF-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).map_err(io_error)
+}
+
+fn io_error(e: std::io::Error) -> WebInspectorError {
+    warn!("DevTools bridge IO error: {}", e);
+    WebInspectorError::IoError
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_log_commands() {
+        assert_eq!(
+            translate_command("Log.enable", json!({})),
+            ("Console.enable", json!({}))
+        );
+        assert_eq!(
+            translate_command("Log.clear", json!({})),
+            ("Console.clearMessages", json!({}))
+        );
+    }
+
+    #[test]
+    fn translates_navigate() {
+        let params = json!({ "url": "https://example.com", "transitionType": "typed" });
+        assert_eq!(
+            translate_command("Page.navigate", params),
+            ("Page.navigate", json!({ "url": "https://example.com" }))
+        );
+    }
+
+    #[test]
+    fn translates_evaluate() {
+        let params = json!({
+            "expression": "1 + 1",
+            "userGesture": true,
+            "silent": false,
+            "timeout": 500,
+            "replMode": true,
+            "returnByValue": true,
+        });
+        assert_eq!(
+            translate_command("Runtime.evaluate", params),
+            (
+                "Runtime.evaluate",
+                json!({
+                    "expression": "1 + 1",
+                    "emulateUserGesture": true,
+                    "doNotPauseOnExceptionsAndMuteConsole": false,
+                    "returnByValue": true,
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn passes_other_commands_through() {
+        let params = json!({ "nodeId": 4 });
+        assert_eq!(
+            translate_command("DOM.getOuterHTML", params.clone()),
+            ("DOM.getOuterHTML", params)
+        );
+    }
+
+    #[test]
+    fn responses_take_the_cdp_id() {
+        let reply = json!({ "id": 17, "result": { "root": { "nodeId": 1 } } });
+        assert_eq!(
+            translate_response("DOM.getDocument", json!("abc"), reply),
+            json!({ "id": "abc", "result": { "root": { "nodeId": 1 } } })
+        );
+        // Errors have no result to translate
+        let reply = json!({ "id": 18, "error": { "code": -32601, "message": "Unknown" } });
+        assert_eq!(
+            translate_response("Page.navigate", json!(3), reply),
+            json!({ "id": 3, "error": { "code": -32601, "message": "Unknown" } })
+        );
+    }
+
+    #[test]
+    fn fills_in_navigate_response() {
+        let reply = json!({ "id": 2, "result": {} });
+        assert_eq!(
+            translate_response("Page.navigate", json!(5), reply),
+            json!({ "id": 5, "result": { "frameId": "main", "loaderId": "" } })
+        );
+    }
+
+    #[test]
+    fn reports_thrown_exceptions() {
+        let exception = json!({ "type": "object", "description": "ReferenceError: x" });
+        let reply = json!({
+            "id": 9,
+            "result": { "result": exception, "wasThrown": true, "savedResultIndex": 1 },
+        });
+        assert_eq!(
+            translate_response("Runtime.evaluate", json!(1), reply),
+            json!({
+                "id": 1,
+                "result": {
+                    "result": exception,
+                    "exceptionDetails": {
+                        "exceptionId": 0,
+                        "text": "ReferenceError: x",
+                        "lineNumber": 0,
+                        "columnNumber": 0,
+                        "exception": exception,
+                    },
+                },
+            })
+        );
+
+        let reply = json!({ "id": 10, "result": { "result": { "value": 2 }, "wasThrown": false } });
+        assert_eq!(
+            translate_response("Runtime.callFunctionOn", json!(2), reply),
+            json!({ "id": 2, "result": { "result": { "value": 2 } } })
+        );
+    }
+
+    #[test]
+    fn translates_console_messages() {
+        let event = json!({
+            "method": "Console.messageAdded",
+            "params": {
+                "message": {
+                    "source": "console-api",
+                    "level": "warning",
+                    "text": "careful",
+                    "timestamp": 1.5,
+                    "stackTrace": [{ "functionName": "f", "url": "a.js" }],
+                },
+            },
+        });
+        assert_eq!(
+            translate_event(event),
+            json!({
+                "method": "Runtime.consoleAPICalled",
+                "params": {
+                    "type": "warning",
+                    "args": [{ "type": "string", "value": "careful" }],
+                    "executionContextId": 0,
+                    "timestamp": 1500.0,
+                    "stackTrace": { "callFrames": [{ "functionName": "f", "url": "a.js" }] },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn console_parameters_become_args() {
+        let console = json!({
+            "type": "table",
+            "level": "log",
+            "parameters": [{ "type": "object", "objectId": "1" }],
+            "timestamp": 2.0,
+        });
+        let call = console_api_call(&console);
+        assert_eq!(call["type"], "table");
+        assert_eq!(call["args"], json!([{ "type": "object", "objectId": "1" }]));
+        assert_eq!(call["timestamp"], 2000.0);
+        assert!(call.get("stackTrace").is_none());
+    }
+
+    #[test]
+    fn translates_execution_contexts() {
+        let event = json!({
+            "method": "Runtime.executionContextCreated",
+            "params": { "context": { "id": 3, "name": "", "type": "normal", "frameId": "0.1" } },
+        });
+        assert_eq!(
+            translate_event(event),
+            json!({
+                "method": "Runtime.executionContextCreated",
+                "params": {
+                    "context": {
+                        "id": 3,
+                        "name": "",
+                        "origin": "",
+                        "auxData": { "isDefault": true, "type": "default", "frameId": "0.1" },
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn translates_network_events() {
+        let event = json!({
+            "method": "Network.requestWillBeSent",
+            "params": { "requestId": "1", "walltime": 1700000000.5 },
+        });
+        assert_eq!(
+            translate_event(event),
+            json!({
+                "method": "Network.requestWillBeSent",
+                "params": { "requestId": "1", "wallTime": 1700000000.5 },
+            })
+        );
+        let event = json!({ "method": "DOM.documentUpdated", "params": {} });
+        assert_eq!(translate_event(event.clone()), event);
+    }
+
+    #[test]
+    fn lists_pages() {
+        let page = InspectorPage {
+            id: 7,
+            app_id: "PID:42".to_string(),
+            title: Some("Example".to_string()),
+            url: Some("https://example.com/".to_string()),
+            page_type: InspectorPageType::WebPage,
+            connection_id: None,
+        };
+        let host: SocketAddr = "127.0.0.1:9222".parse().unwrap();
+        assert_eq!(
+            listing(&page, host),
+            json!({
+                "description": "",
+                "id": "PID:42/7",
+                "title": "Example",
+                "type": "page",
+                "url": "https://example.com/",
+                "webSocketDebuggerUrl": "ws://127.0.0.1:9222/devtools/page/PID:42/7",
+                "devtoolsFrontendUrl":
+                    "/devtools/inspector.html?ws=127.0.0.1:9222/devtools/page/PID:42/7",
+            })
+        );
+    }
+
+    /// Connects to a local listener, returning the client end and the accepted end
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        server.set_nonblocking(true).unwrap();
+        (client, server)
+    }
+
+    /// Peeks until the request has been seen, data on loopback can take a moment to arrive
+    fn peek_arrived(stream: &TcpStream) -> Result<Option<String>, WebInspectorError> {
+        for _ in 0..100 {
+            match peek_request_path(stream) {
+                Ok(None) => std::thread::sleep(Duration::from_millis(5)),
+                result => return result,
+            }
+        }
+        Ok(None)
+    }
+
+    #[test]
+    fn waits_for_the_whole_request() {
+        let (mut client, server) = connection();
+        assert_eq!(peek_request_path(&server), Ok(None));
+
+        client
+            .write_all(b"GET /json/list?t=1 HTTP/1.1\r\nHost: local")
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(peek_request_path(&server), Ok(None));
+
+        client.write_all(b"host\r\n\r\n").unwrap();
+        assert_eq!(peek_arrived(&server), Ok(Some("/json/list".to_string())));
+        // The request is left for the WebSocket handshake
+        assert_eq!(
+            peek_request_path(&server),
+            Ok(Some("/json/list".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        let (mut client, server) = connection();
+        client.write_all(b"POST /json HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(
+            peek_arrived(&server),
+            Err(WebInspectorError::InvalidMessage)
+        );
+
+        let (client, server) = connection();
+        drop(client);
+        assert_eq!(
+            peek_arrived(&server),
+            Err(WebInspectorError::InvalidMessage)
+        );
+
+        // Headers that don't end within the buffer
+        let (mut client, server) = connection();
+        client.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        client.write_all(&[b'a'; 5000]).unwrap();
+        assert_eq!(
+            peek_arrived(&server),
+            Err(WebInspectorError::InvalidMessage)
+        );
+    }
+}
@@ -1,17 +1,42 @@
 // jkcoxson
 
 use crate::{
-    bindings as unsafe_bindings, error::CompanionProxyError, idevice::Device,
+    bindings as unsafe_bindings,
+    connection::DeviceConnection,
+    error::{CompanionProxyError, IdeviceError},
+    idevice::{get_device, Device},
+    plist_helpers::{dict_string, string_array},
     services::lockdownd::LockdowndService,
 };
-use std::ffi::CString;
+use std::{
+    ffi::{c_void, CString},
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
 
+use log::{info, warn};
 use plist_plus::Plist;
 
+/// The port lockdown listens on, on the device and on paired watches
+pub const LOCKDOWN_PORT: u16 = 62078;
+
+/// How long the forwarder waits on either side of a relay before checking the other
+const RELAY_POLL_MS: u32 = 10;
+/// How many bytes to relay at once
+const RELAY_CHUNK: u32 = 16 * 1024;
+
 /// A proxy for interoping with devices paired with the iOS device
 /// This includes the Apple Watch
 pub struct CompanionProxy<'a> {
     pub(crate) pointer: unsafe_bindings::companion_proxy_client_t,
+    device: unsafe_bindings::idevice_t,
     phantom: std::marker::PhantomData<&'a Device>,
 }
 
@@ -40,6 +65,7 @@ impl CompanionProxy<'_> {
 
         Ok(CompanionProxy {
             pointer,
+            device: device.pointer,
             phantom: std::marker::PhantomData,
         })
     }
@@ -73,6 +99,7 @@ impl CompanionProxy<'_> {
 
         Ok(CompanionProxy {
             pointer,
+            device: device.pointer,
             phantom: std::marker::PhantomData,
         })
     }
@@ -218,6 +245,345 @@ impl CompanionProxy<'_> {
 
         Ok(())
     }
+
+    /// Gets the UDIDs of the devices paired with the iOS device.
+    /// The query runs on its own connection, so this companion proxy stays usable.
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The UDIDs of the paired devices, empty if there are none
+    ///
+    /// ***Verified:*** False
+    pub fn paired_devices(&self) -> Result<Vec<String>, CompanionProxyError> {
+        match self.query_connection()?.get_device_registry() {
+            Ok(registry) => Ok(string_array(&registry)),
+            Err(CompanionProxyError::NoDevices) => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Gets a value from the device's registry.
+    /// The query runs on its own connection, so this companion proxy stays usable.
+    /// # Arguments
+    /// * `udid` - The UDID of the paired device
+    /// * `key` - The value to fetch from the registry
+    /// # Returns
+    /// A plist containing the value
+    ///
+    /// ***Verified:*** False
+    pub fn registry_value(
+        &self,
+        udid: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<Plist, CompanionProxyError> {
+        self.query_connection()?.get_value_from_registry(udid, key)
+    }
+
+    /// Starts a connection for a query that closes it
+    fn query_connection(&self) -> Result<CompanionProxy<'_>, CompanionProxyError> {
+        let label_c_string = CString::new("companion_proxy_query").unwrap();
+        let mut pointer = unsafe { std::mem::zeroed() };
+        let result = unsafe {
+            unsafe_bindings::companion_proxy_client_start_service(
+                self.device,
+                &mut pointer,
+                label_c_string.as_ptr(),
+            )
+        }
+        .into();
+        if result != CompanionProxyError::Success {
+            return Err(result);
+        }
+
+        Ok(CompanionProxy {
+            pointer,
+            device: self.device,
+            phantom: std::marker::PhantomData,
+        })
+    }
+
+    /// Listens for paired devices connecting and disconnecting.
+    /// The listener owns this connection until it's dropped,
+    /// though `paired_devices` and `registry_value` still work meanwhile.
+    /// # Arguments
+    /// * `on_event` - Called from the listening thread with every event
+    /// # Returns
+    /// The listener, which stops listening when dropped
+    ///
+    /// ***Verified:*** False
+    pub fn listen_for_devices(
+        &self,
+        on_event: impl FnMut(CompanionDeviceEvent) + Send + 'static,
+    ) -> Result<CompanionDeviceListener<'_>, CompanionProxyError> {
+        let callback: *mut DeviceEventCallback = Box::into_raw(Box::new(Box::new(on_event)));
+        let result = unsafe {
+            unsafe_bindings::companion_proxy_start_listening_for_devices(
+                self.pointer,
+                Some(device_event_trampoline),
+                callback as *mut c_void,
+            )
+        }
+        .into();
+        if result != CompanionProxyError::Success {
+            drop(unsafe { Box::from_raw(callback) });
+            return Err(result);
+        }
+
+        Ok(CompanionDeviceListener {
+            pointer: self.pointer,
+            callback,
+            phantom: std::marker::PhantomData,
+        })
+    }
+
+    /// Listens for paired devices connecting and disconnecting, reporting them on a channel
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The listener, which stops listening when dropped, and a channel that receives the events
+    ///
+    /// ***Verified:*** False
+    pub fn listen_for_devices_with_channel(
+        &self,
+    ) -> Result<
+        (
+            CompanionDeviceListener<'_>,
+            mpsc::Receiver<CompanionDeviceEvent>,
+        ),
+        CompanionProxyError,
+    > {
+        let (sender, receiver) = mpsc::channel();
+        let listener = self.listen_for_devices(move |event| {
+            let _ = sender.send(event);
+        })?;
+        Ok((listener, receiver))
+    }
+
+    /// Forwards a service on a paired device to a TCP port on the host.
+    /// Connections to the local port are relayed to the port the iOS device opens for the service.
+    /// The port is opened on its own connection, so this companion proxy stays usable.
+    /// # Arguments
+    /// * `remote_port` - The port of the service on the paired device, such as `LOCKDOWN_PORT`
+    /// * `service_name` - The name of the service, such as `com.apple.mobile.lockdown`
+    /// * `local_address` - The address to listen on. Use port 0 to pick any free port
+    /// # Returns
+    /// The running forwarder, which stops forwarding when dropped
+    ///
+    /// ***Verified:*** False
+    pub fn forward_service(
+        &self,
+        remote_port: u16,
+        service_name: impl Into<String>,
+        local_address: impl ToSocketAddrs,
+    ) -> Result<CompanionServiceForwarder<'_>, CompanionProxyError> {
+        let listener =
+            TcpListener::bind(local_address).map_err(|_| CompanionProxyError::IoError)?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|_| CompanionProxyError::IoError)?;
+        let local_address = listener
+            .local_addr()
+            .map_err(|_| CompanionProxyError::IoError)?;
+
+        // Relays connect on their own threads, so they get a handle to the device they can own
+        let udid = unsafe { std::ffi::CStr::from_ptr((*self.device).udid) }
+            .to_string_lossy()
+            .into_owned();
+        let device = Arc::new(get_device(udid).map_err(|_| CompanionProxyError::ConnectionFailed)?);
+
+        let service_name = service_name.into();
+        let proxy = self.query_connection()?;
+        let device_port =
+            proxy.start_forwarding_service_port(remote_port, &service_name, Plist::new_dict())?;
+        info!(
+            "Forwarding {} on port {} through device port {} to {}",
+            service_name, remote_port, device_port, local_address
+        );
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::spawn(move || {
+            accept_connections(listener, device, device_port, thread_stop)
+        });
+
+        Ok(CompanionServiceForwarder {
+            proxy,
+            remote_port,
+            local_address,
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+type DeviceEventCallback = Box<dyn FnMut(CompanionDeviceEvent) + Send>;
+
+unsafe extern "C" fn device_event_trampoline(
+    event: unsafe_bindings::plist_t,
+    user_data: *mut c_void,
+) {
+    let callback = &mut *(user_data as *mut DeviceEventCallback);
+    // The event is freed once the callback returns
+    let borrowed: Plist = event.into();
+    let event = borrowed.clone();
+    borrowed.false_drop();
+    // Unwinding into C is undefined, and there's no caller to hand the panic to
+    let event = CompanionDeviceEvent::from_plist(event);
+    if panic::catch_unwind(AssertUnwindSafe(|| callback(event))).is_err() {
+        warn!("The paired device callback panicked");
+    }
+}
+
+/// A change to the devices paired with the iOS device
+#[derive(Debug, Clone)]
+pub enum CompanionDeviceEvent {
+    /// A paired device, such as an Apple Watch, connected to the iOS device
+    Attached(String),
+    /// A paired device disconnected from the iOS device
+    Detached(String),
+    /// A message this crate doesn't recognize
+    Other(Plist),
+}
+
+impl CompanionDeviceEvent {
+    /// Parses a message sent while listening for devices.
+    /// The device sends a `MessageType` of `DeviceAttached` or `DeviceDetached`,
+    /// with the paired device's UDID under `PairedDevice`.
+    /// # Arguments
+    /// * `event` - The dictionary sent by the device
+    /// # Returns
+    /// The event it reports
+    pub fn from_plist(event: Plist) -> Self {
+        let udid = dict_string(&event, "PairedDevice");
+        match (dict_string(&event, "MessageType").as_deref(), udid) {
+            (Some("DeviceAttached"), Some(udid)) => CompanionDeviceEvent::Attached(udid),
+            (Some("DeviceDetached"), Some(udid)) => CompanionDeviceEvent::Detached(udid),
+            _ => CompanionDeviceEvent::Other(event),
+        }
+    }
+}
+
+/// Reports paired devices as they connect and disconnect, until it's dropped
+pub struct CompanionDeviceListener<'a> {
+    pointer: unsafe_bindings::companion_proxy_client_t,
+    callback: *mut DeviceEventCallback,
+    phantom: std::marker::PhantomData<&'a CompanionProxy<'a>>,
+}
+
+impl Drop for CompanionDeviceListener<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            // Waits for the listening thread, so the callback can't be called after it's freed
+            unsafe_bindings::companion_proxy_stop_listening_for_devices(self.pointer);
+            drop(Box::from_raw(self.callback));
+        }
+    }
+}
+
+/// Relays connections from a local TCP port to a service on a paired device, until it's dropped
+pub struct CompanionServiceForwarder<'a> {
+    proxy: CompanionProxy<'a>,
+    remote_port: u16,
+    local_address: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CompanionServiceForwarder<'_> {
+    /// Gets the local address that forwards to the service
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_address
+    }
+}
+
+impl Drop for CompanionServiceForwarder<'_> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if let Err(e) = self.proxy.stop_forwarding_service_port(self.remote_port) {
+            warn!(
+                "Unable to stop forwarding port {}: {:?}",
+                self.remote_port, e
+            );
+        }
+    }
+}
+
+fn accept_connections(
+    listener: TcpListener,
+    device: Arc<Device>,
+    device_port: u16,
+    stop: Arc<AtomicBool>,
+) {
+    let mut relays: Vec<JoinHandle<()>> = Vec::new();
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                info!("Relaying {} to device port {}", peer, device_port);
+                let device = device.clone();
+                let stop = stop.clone();
+                relays.push(std::thread::spawn(move || {
+                    if let Err(e) = relay(stream, &device, device_port, &stop) {
+                        warn!("Relay for {} ended: {:?}", peer, e);
+                    }
+                }));
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(RELAY_POLL_MS as u64 * 10));
+            }
+            Err(e) => {
+                warn!("Unable to accept a connection to forward: {}", e);
+                break;
+            }
+        }
+        relays.retain(|relay| !relay.is_finished());
+    }
+    for relay in relays {
+        let _ = relay.join();
+    }
+}
+
+/// Copies bytes both ways between a local client and the device until either side closes
+fn relay(
+    mut stream: TcpStream,
+    device: &Device,
+    device_port: u16,
+    stop: &AtomicBool,
+) -> Result<(), CompanionProxyError> {
+    let connection = DeviceConnection::connect_borrowed(device, device_port)
+        .map_err(|_| CompanionProxyError::ConnectionFailed)?;
+    stream
+        .set_read_timeout(Some(Duration::from_millis(RELAY_POLL_MS as u64)))
+        .map_err(|_| CompanionProxyError::IoError)?;
+
+    let mut buffer = vec![0; RELAY_CHUNK as usize];
+    while !stop.load(Ordering::Relaxed) {
+        match connection.receive(RELAY_CHUNK, Some(RELAY_POLL_MS)) {
+            Ok(data) if !data.is_empty() => stream
+                .write_all(&data)
+                .map_err(|_| CompanionProxyError::IoError)?,
+            Ok(_) | Err(IdeviceError::Timeout) => {}
+            Err(_) => return Ok(()),
+        }
+
+        match stream.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => {
+                let mut sent = 0;
+                while sent < read {
+                    sent += connection
+                        .send(&buffer[sent..read])
+                        .map_err(|_| CompanionProxyError::ConnectionFailed)?
+                        as usize;
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => return Err(CompanionProxyError::IoError),
+        }
+    }
+    Ok(())
 }
 
 impl Drop for CompanionProxy<'_> {
@@ -227,3 +593,89 @@ impl Drop for CompanionProxy<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UDID: &str = "00008301-000A1B2C3D4E5F60";
+
+    fn message(xml: &str) -> Plist {
+        Plist::from_xml(format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <plist version=\"1.0\">\n<dict>\n{}</dict>\n</plist>\n",
+            xml
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_attached_devices() {
+        let event = CompanionDeviceEvent::from_plist(message(
+            "<key>MessageType</key><string>DeviceAttached</string>\n\
+             <key>PairedDevice</key><string>00008301-000A1B2C3D4E5F60</string>\n",
+        ));
+        assert!(matches!(
+            event,
+            CompanionDeviceEvent::Attached(udid) if udid == UDID
+        ));
+    }
+
+    #[test]
+    fn parses_detached_devices() {
+        let event = CompanionDeviceEvent::from_plist(message(
+            "<key>MessageType</key><string>DeviceDetached</string>\n\
+             <key>PairedDevice</key><string>00008301-000A1B2C3D4E5F60</string>\n",
+        ));
+        assert!(matches!(
+            event,
+            CompanionDeviceEvent::Detached(udid) if udid == UDID
+        ));
+    }
+
+    #[test]
+    fn listener_reports_device_events() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let received = events.clone();
+        let callback: DeviceEventCallback = Box::new(move |event| {
+            received.lock().unwrap().push(event);
+        });
+        let user_data = Box::into_raw(Box::new(callback));
+        for kind in ["DeviceAttached", "DeviceDetached"] {
+            let event = message(&format!(
+                "<key>MessageType</key><string>{}</string>\n\
+                 <key>PairedDevice</key><string>00008301-000A1B2C3D4E5F60</string>\n",
+                kind
+            ));
+            // The listener hands over a message it frees afterwards
+            unsafe { device_event_trampoline(event.get_pointer(), user_data as *mut c_void) };
+        }
+        drop(unsafe { Box::from_raw(user_data) });
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], CompanionDeviceEvent::Attached(udid) if udid == UDID));
+        assert!(matches!(&events[1], CompanionDeviceEvent::Detached(udid) if udid == UDID));
+    }
+
+    #[test]
+    fn keeps_unrecognized_messages() {
+        for xml in [
+            // Other message types
+            "<key>MessageType</key><string>DevicePaired</string>\n\
+             <key>PairedDevice</key><string>00008301-000A1B2C3D4E5F60</string>\n",
+            // Only exact values match
+            "<key>MessageType</key><string>DeviceAttachedLater</string>\n\
+             <key>PairedDevice</key><string>00008301-000A1B2C3D4E5F60</string>\n",
+            // The UDID is only read from PairedDevice
+            "<key>MessageType</key><string>DeviceAttached</string>\n\
+             <key>UDID</key><string>00008301-000A1B2C3D4E5F60</string>\n",
+            "<key>Command</key><string>DeviceAttached</string>\n\
+             <key>PairedDevice</key><string>00008301-000A1B2C3D4E5F60</string>\n",
+            "<key>Error</key><string>NoDevices</string>\n",
+        ] {
+            let event = CompanionDeviceEvent::from_plist(message(xml));
+            assert!(matches!(event, CompanionDeviceEvent::Other(_)), "{}", xml);
+        }
+    }
+}
@@ -0,0 +1,584 @@
+// jkcoxson
+// Flushes crash reports on the device and copies them to the host
+
+use std::{
+    fs::File,
+    io::Write,
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use log::{info, warn};
+use serde_json::Value;
+
+use crate::{
+    connection::DeviceConnection,
+    error::{AfcError, CrashReportError, IdeviceError},
+    idevice::Device,
+    services::afc::{AfcClient, AfcFileMode},
+};
+
+/// Moves pending crash reports to where the copy service can reach them
+pub const MOVER_SERVICE: &str = "com.apple.crashreportmover";
+/// Serves the moved crash reports over AFC
+pub const COPY_SERVICE: &str = "com.apple.crashreportcopymobile";
+
+/// How many times to wait for the mover to report it's done
+const MOVER_ATTEMPTS: u32 = 10;
+const MOVER_WAIT_MS: u32 = 2000;
+/// How many bytes to read per AFC read
+const TRANSFER_CHUNK: u32 = 64 * 1024;
+
+/// A crash report on the device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashReport {
+    /// The path of the report, relative to the copy service's root
+    pub path: String,
+    /// The file name of the report
+    pub name: String,
+    /// The process the report is about, as named by the file
+    pub process: String,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl CrashReport {
+    /// Whether the report is a JSON `.ips` report rather than a text `.crash` report
+    pub fn is_ips(&self) -> bool {
+        self.name.ends_with(".ips") || self.name.ends_with(".ips.synced")
+    }
+}
+
+/// Picks which crash reports to list or download
+#[derive(Debug, Clone, Default)]
+pub struct CrashReportFilter {
+    /// Only reports about this process
+    pub process: Option<String>,
+    /// Only reports written at or after this time
+    pub since: Option<SystemTime>,
+    /// Only reports written before this time
+    pub until: Option<SystemTime>,
+}
+
+impl CrashReportFilter {
+    /// Whether a report passes the filter
+    pub fn matches(&self, report: &CrashReport) -> bool {
+        if let Some(process) = &self.process {
+            // Reports for some events prefix or suffix the process, like ExcUserFault_Name
+            let matches_process = report.process == *process
+                || report.process.split(['_', '.']).any(|part| part == process);
+            if !matches_process {
+                return false;
+            }
+        }
+        match report.modified {
+            Some(modified) => {
+                self.since.is_none_or(|since| modified >= since)
+                    && self.until.is_none_or(|until| modified < until)
+            }
+            None => self.since.is_none() && self.until.is_none(),
+        }
+    }
+}
+
+/// The JSON header on the first line of an `.ips` report
+#[derive(Debug, Clone)]
+pub struct IpsHeader {
+    /// The kind of report, such as 309 for a crash
+    pub bug_type: Option<String>,
+    pub name: Option<String>,
+    pub app_name: Option<String>,
+    pub app_version: Option<String>,
+    pub build_version: Option<String>,
+    pub bundle_id: Option<String>,
+    pub os_version: Option<String>,
+    /// When the report was written, as the device formats it
+    pub timestamp: Option<String>,
+    pub incident_id: Option<String>,
+    pub slice_uuid: Option<String>,
+    /// Every field in the header
+    pub fields: Value,
+}
+
+impl IpsHeader {
+    /// Parses the header of an `.ips` report
+    /// # Arguments
+    /// * `report` - The report, or at least its first line
+    /// # Returns
+    /// The header
+    pub fn parse(report: &[u8]) -> Result<Self, CrashReportError> {
+        let line = report.split(|b| *b == b'\n').next().unwrap_or_default();
+        let fields: Value =
+            serde_json::from_slice(line).map_err(|_| CrashReportError::InvalidHeader)?;
+        if !fields.is_object() {
+            return Err(CrashReportError::InvalidHeader);
+        }
+
+        let field = |key: &str| match fields.get(key)? {
+            Value::String(value) => Some(value.clone()),
+            Value::Number(value) => Some(value.to_string()),
+            _ => None,
+        };
+        Ok(IpsHeader {
+            bug_type: field("bug_type"),
+            name: field("name"),
+            app_name: field("app_name"),
+            app_version: field("app_version"),
+            build_version: field("build_version"),
+            bundle_id: field("bundleID"),
+            os_version: field("os_version"),
+            timestamp: field("timestamp"),
+            incident_id: field("incident_id"),
+            slice_uuid: field("slice_uuid"),
+            fields: fields.clone(),
+        })
+    }
+}
+
+/// Asks the device to move its pending crash reports to where the copy service can reach them
+/// # Arguments
+/// * `device` - The device to flush
+/// # Returns
+/// *none*
+///
+/// ***Verified:*** False
+pub fn flush(device: &Device) -> Result<(), CrashReportError> {
+    let mut lockdown = device
+        .new_lockdownd_client("crash_reports")
+        .map_err(|_| CrashReportError::LockdowndFailed)?;
+    // `true` starts the service without sending the escrow bag
+    let service = lockdown
+        .start_service(MOVER_SERVICE, true)
+        .map_err(|_| CrashReportError::LockdowndFailed)?;
+    let ssl_enabled = unsafe { (*service.pointer).ssl_enabled } != 0;
+
+    let connection = DeviceConnection::connect_borrowed(device, service.port as u16)
+        .map_err(|_| CrashReportError::MoverFailed)?;
+    if ssl_enabled {
+        connection
+            .enable_ssl(true)
+            .map_err(|_| CrashReportError::MoverFailed)?;
+    }
+
+    // The mover says ping once the reports have been moved
+    let mut received = Vec::new();
+    for _ in 0..MOVER_ATTEMPTS {
+        match connection.receive(4 - received.len() as u32, Some(MOVER_WAIT_MS)) {
+            Ok(data) => received.extend(data),
+            Err(IdeviceError::Timeout) => {}
+            Err(_) => return Err(CrashReportError::MoverFailed),
+        }
+        if received.len() >= 4 {
+            break;
+        }
+    }
+    if received != b"ping" {
+        warn!("The crash report mover didn't confirm the move");
+        return Err(CrashReportError::MoverFailed);
+    }
+    info!("Flushed crash reports on {}", device.get_udid());
+    Ok(())
+}
+
+/// Lists, downloads and deletes the crash reports on a device
+pub struct CrashReportClient<'a> {
+    afc: AfcClient<'a>,
+}
+
+impl CrashReportClient<'_> {
+    /// Flushes pending crash reports and connects to the copy service
+    /// # Arguments
+    /// * `device` - The device to get crash reports from
+    /// # Returns
+    /// The client
+    ///
+    /// ***Verified:*** False
+    pub fn new(device: &Device) -> Result<Self, CrashReportError> {
+        flush(device)?;
+        Self::without_flush(device)
+    }
+
+    /// Connects to the copy service, leaving pending crash reports where they are
+    /// # Arguments
+    /// * `device` - The device to get crash reports from
+    /// # Returns
+    /// The client
+    ///
+    /// ***Verified:*** False
+    pub fn without_flush(device: &Device) -> Result<Self, CrashReportError> {
+        let mut lockdown = device
+            .new_lockdownd_client("crash_reports")
+            .map_err(|_| CrashReportError::LockdowndFailed)?;
+        let service = lockdown
+            .start_service(COPY_SERVICE, true)
+            .map_err(|_| CrashReportError::LockdowndFailed)?;
+        let afc =
+            AfcClient::with_service(device, service).map_err(|_| CrashReportError::AfcFailed)?;
+        Ok(CrashReportClient { afc })
+    }
+
+    /// Lists the crash reports on the device
+    /// # Arguments
+    /// * `filter` - Which reports to list
+    /// # Returns
+    /// The matching reports, oldest first
+    ///
+    /// ***Verified:*** False
+    pub fn list(&self, filter: &CrashReportFilter) -> Result<Vec<CrashReport>, CrashReportError> {
+        let mut reports = Vec::new();
+        let mut directories = vec![String::from("/")];
+        while let Some(directory) = directories.pop() {
+            let entries = self
+                .afc
+                .read_directory(directory.as_str())
+                .map_err(|_| CrashReportError::AfcFailed)?;
+            for name in entries {
+                if name == "." || name == ".." {
+                    continue;
+                }
+                let path = if directory == "/" {
+                    name.clone()
+                } else {
+                    format!("{}/{}", directory, name)
+                };
+                let info = match self.afc.get_file_info(path.as_str()) {
+                    Ok(info) => info,
+                    // Reports can be rotated away while listing
+                    Err(AfcError::ObjectNotFound) => continue,
+                    Err(_) => return Err(CrashReportError::AfcFailed),
+                };
+                if info.get("st_ifmt").map(String::as_str) == Some("S_IFDIR") {
+                    directories.push(path);
+                    continue;
+                }
+                if !is_report(&name) {
+                    continue;
+                }
+
+                let report = CrashReport {
+                    process: process_name(&name),
+                    size: info
+                        .get("st_size")
+                        .and_then(|size| size.parse().ok())
+                        .unwrap_or(0),
+                    modified: info
+                        .get("st_mtime")
+                        .and_then(|mtime| mtime.parse().ok())
+                        .map(|nanos| SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos)),
+                    path,
+                    name,
+                };
+                if filter.matches(&report) {
+                    reports.push(report);
+                }
+            }
+        }
+        reports.sort_by(|a, b| a.modified.cmp(&b.modified).then(a.path.cmp(&b.path)));
+        Ok(reports)
+    }
+
+    /// Reads a crash report
+    /// # Arguments
+    /// * `report` - The report to read
+    /// # Returns
+    /// The contents of the report
+    ///
+    /// ***Verified:*** False
+    pub fn read(&self, report: &CrashReport) -> Result<Vec<u8>, CrashReportError> {
+        let handle = match self
+            .afc
+            .file_open(report.path.as_str(), AfcFileMode::ReadOnly)
+        {
+            Ok(handle) => handle,
+            Err(AfcError::ObjectNotFound) => return Err(CrashReportError::NotFound),
+            Err(_) => return Err(CrashReportError::AfcFailed),
+        };
+        let mut contents = Vec::with_capacity(report.size as usize);
+        let result = loop {
+            match self.afc.file_read(handle, TRANSFER_CHUNK) {
+                Ok(chunk) if chunk.is_empty() => break Ok(()),
+                Ok(chunk) => contents.extend(chunk),
+                Err(_) => break Err(CrashReportError::AfcFailed),
+            }
+        };
+        let _ = self.afc.file_close(handle);
+        result.map(|_| contents)
+    }
+
+    /// Reads the header of an `.ips` crash report
+    /// # Arguments
+    /// * `report` - The report to read
+    /// # Returns
+    /// The parsed header
+    ///
+    /// ***Verified:*** False
+    pub fn read_header(&self, report: &CrashReport) -> Result<IpsHeader, CrashReportError> {
+        if !report.is_ips() {
+            return Err(CrashReportError::InvalidHeader);
+        }
+        IpsHeader::parse(&self.read(report)?)
+    }
+
+    /// Downloads a crash report into a directory on the host.
+    /// The report keeps its path relative to the copy service's root, such as `Retired/Name.ips`,
+    /// so reports with the same name in different directories don't overwrite each other.
+    /// # Arguments
+    /// * `report` - The report to download
+    /// * `dest` - The directory to write it to
+    /// # Returns
+    /// The path of the downloaded report
+    ///
+    /// ***Verified:*** False
+    pub fn download(
+        &self,
+        report: &CrashReport,
+        dest: impl AsRef<Path>,
+    ) -> Result<PathBuf, CrashReportError> {
+        let path = local_path(dest.as_ref(), &report.path).ok_or_else(|| {
+            warn!("Refusing to download crash report {}", report.path);
+            CrashReportError::InvalidPath
+        })?;
+        let contents = self.read(report)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|_| CrashReportError::IoError)?;
+        }
+        File::create(&path)
+            .and_then(|mut file| file.write_all(&contents))
+            .map_err(|_| CrashReportError::IoError)?;
+        Ok(path)
+    }
+
+    /// Downloads every matching crash report into a directory on the host
+    /// # Arguments
+    /// * `filter` - Which reports to download
+    /// * `dest` - The directory to write them to, created if needed
+    /// * `delete` - Whether to delete each report from the device once it's downloaded
+    /// # Returns
+    /// The paths of the downloaded reports
+    ///
+    /// ***Verified:*** False
+    pub fn download_all(
+        &self,
+        filter: &CrashReportFilter,
+        dest: impl AsRef<Path>,
+        delete: bool,
+    ) -> Result<Vec<PathBuf>, CrashReportError> {
+        std::fs::create_dir_all(dest.as_ref()).map_err(|_| CrashReportError::IoError)?;
+        let mut paths = Vec::new();
+        for report in self.list(filter)? {
+            paths.push(self.download(&report, dest.as_ref())?);
+            if delete {
+                self.delete(&report)?;
+            }
+        }
+        info!(
+            "Downloaded {} crash reports to {}",
+            paths.len(),
+            dest.as_ref().display()
+        );
+        Ok(paths)
+    }
+
+    /// Deletes a crash report from the device
+    /// # Arguments
+    /// * `report` - The report to delete
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn delete(&self, report: &CrashReport) -> Result<(), CrashReportError> {
+        match self.afc.remove_path(report.path.as_str()) {
+            Ok(()) => Ok(()),
+            Err(AfcError::ObjectNotFound) => Err(CrashReportError::NotFound),
+            Err(_) => Err(CrashReportError::AfcFailed),
+        }
+    }
+}
+
+/// Gets where a report at `report_path` on the device goes under `dest`.
+/// Only plain path components are kept, so a report can't be written outside `dest`.
+fn local_path(dest: &Path, report_path: &str) -> Option<PathBuf> {
+    let mut path = dest.to_path_buf();
+    let mut components = 0;
+    for component in Path::new(report_path).components() {
+        match component {
+            Component::Normal(part) => {
+                path.push(part);
+                components += 1;
+            }
+            Component::RootDir | Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (components > 0).then_some(path)
+}
+
+fn is_report(name: &str) -> bool {
+    name.ends_with(".ips") || name.ends_with(".ips.synced") || name.ends_with(".crash")
+}
+
+/// Gets the process from a report name like `Name-2024-01-31-120000.ips`
+fn process_name(name: &str) -> String {
+    let stem = [".ips.synced", ".ips", ".crash"]
+        .iter()
+        .find_map(|extension| name.strip_suffix(extension))
+        .unwrap_or(name);
+    // Drop the date and time the device appends
+    let parts: Vec<&str> = stem.split('-').collect();
+    let is_timestamp = parts.len() > 4
+        && parts[parts.len() - 4..]
+            .iter()
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()));
+    if is_timestamp {
+        parts[..parts.len() - 4].join("-")
+    } else {
+        stem.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(path: &str, modified: Option<u64>) -> CrashReport {
+        let name = path.rsplit('/').next().unwrap().to_string();
+        CrashReport {
+            path: path.to_string(),
+            process: process_name(&name),
+            name,
+            size: 0,
+            modified: modified.map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs)),
+        }
+    }
+
+    #[test]
+    fn parses_ips_header() {
+        let report = br#"{"app_name":"MobileSafari","timestamp":"2024-01-31 12:00:00.00 +0100","app_version":"17.2","slice_uuid":"8d3c1f4e-0000-0000-0000-000000000000","build_version":"8617.1.17.10.9","bug_type":"309","os_version":"iPhone OS 17.2.1 (21C66)","bundleID":"com.apple.mobilesafari","incident_id":"5B0B7F2E-0000-0000-0000-000000000000","name":"MobileSafari"}
+{
+  "uptime" : 100000,
+  "procName" : "MobileSafari"
+}"#;
+        let header = IpsHeader::parse(report).unwrap();
+        assert_eq!(header.bug_type.as_deref(), Some("309"));
+        assert_eq!(header.name.as_deref(), Some("MobileSafari"));
+        assert_eq!(header.app_version.as_deref(), Some("17.2"));
+        assert_eq!(header.bundle_id.as_deref(), Some("com.apple.mobilesafari"));
+        assert_eq!(
+            header.os_version.as_deref(),
+            Some("iPhone OS 17.2.1 (21C66)")
+        );
+        assert_eq!(
+            header.incident_id.as_deref(),
+            Some("5B0B7F2E-0000-0000-0000-000000000000")
+        );
+        // Only the first line is the header
+        assert!(header.fields.get("uptime").is_none());
+    }
+
+    #[test]
+    fn numeric_header_fields_become_strings() {
+        let header = IpsHeader::parse(br#"{"bug_type":288,"name":null}"#).unwrap();
+        assert_eq!(header.bug_type.as_deref(), Some("288"));
+        assert_eq!(header.name, None);
+        assert_eq!(header.app_name, None);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        for report in [
+            &b"Incident Identifier: 5B0B7F2E\nCrashReporter Key: abc"[..],
+            b"[1, 2, 3]\n",
+            b"",
+        ] {
+            assert_eq!(
+                IpsHeader::parse(report).err(),
+                Some(CrashReportError::InvalidHeader)
+            );
+        }
+    }
+
+    #[test]
+    fn process_names() {
+        assert_eq!(
+            process_name("MobileSafari-2024-01-31-120000.ips"),
+            "MobileSafari"
+        );
+        assert_eq!(
+            process_name("JetsamEvent-2024-01-31-120000.ips.synced"),
+            "JetsamEvent"
+        );
+        assert_eq!(process_name("my-app-2024-01-31-120000.crash"), "my-app");
+        assert_eq!(
+            process_name("ExcUserFault_backboardd-2024-01-31-120000.ips"),
+            "ExcUserFault_backboardd"
+        );
+        // Nothing that looks like a timestamp to drop
+        assert_eq!(process_name("stacks+SpringBoard.ips"), "stacks+SpringBoard");
+        assert_eq!(process_name("name-with-a-date.ips"), "name-with-a-date");
+        assert_eq!(
+            process_name("Name-2024-01-31-12000a.ips"),
+            "Name-2024-01-31-12000a"
+        );
+    }
+
+    #[test]
+    fn recognizes_reports() {
+        assert!(is_report("Name-2024-01-31-120000.ips"));
+        assert!(is_report("Name.crash"));
+        assert!(is_report("Name.ips.synced"));
+        assert!(!is_report("Analytics.ips.ca.synced"));
+        assert!(!is_report("log.txt"));
+        assert!(report("Retired/Name.ips.synced", None).is_ips());
+        assert!(!report("Name.crash", None).is_ips());
+    }
+
+    #[test]
+    fn filters_by_process() {
+        let filter = CrashReportFilter {
+            process: Some("backboardd".to_string()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&report("backboardd-2024-01-31-120000.ips", None)));
+        assert!(filter.matches(&report(
+            "ExcUserFault_backboardd-2024-01-31-120000.ips",
+            None
+        )));
+        assert!(filter.matches(&report("stacks.backboardd.ips", None)));
+        assert!(!filter.matches(&report("backboardd2-2024-01-31-120000.ips", None)));
+        assert!(!filter.matches(&report("SpringBoard-2024-01-31-120000.ips", None)));
+    }
+
+    #[test]
+    fn filters_by_time() {
+        let at = |secs| Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        let filter = CrashReportFilter {
+            since: at(1000),
+            until: at(2000),
+            ..Default::default()
+        };
+        assert!(filter.matches(&report("A.ips", Some(1000))));
+        assert!(filter.matches(&report("A.ips", Some(1999))));
+        assert!(!filter.matches(&report("A.ips", Some(999))));
+        assert!(!filter.matches(&report("A.ips", Some(2000))));
+        // Reports without a time only pass filters without one
+        assert!(!filter.matches(&report("A.ips", None)));
+        assert!(CrashReportFilter::default().matches(&report("A.ips", None)));
+    }
+
+    #[test]
+    fn keeps_report_directories() {
+        let dest = Path::new("/tmp/reports");
+        assert_eq!(
+            local_path(dest, "Retired/Name.ips"),
+            Some(dest.join("Retired").join("Name.ips"))
+        );
+        assert_eq!(
+            local_path(dest, "Assistant/Name.ips"),
+            Some(dest.join("Assistant").join("Name.ips"))
+        );
+        assert_eq!(local_path(dest, "/Name.ips"), Some(dest.join("Name.ips")));
+        assert_eq!(local_path(dest, "../Name.ips"), None);
+        assert_eq!(local_path(dest, "Retired/../../Name.ips"), None);
+        assert_eq!(local_path(dest, "/"), None);
+    }
+}
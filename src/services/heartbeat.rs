@@ -1,12 +1,33 @@
 // jkcoxson
 
-use std::{ffi::CString, future::Future, pin::Pin};
+use std::{
+    ffi::CString,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
-use crate::{bindings as unsafe_bindings, error::HeartbeatError, idevice::Device};
+use crate::{
+    bindings as unsafe_bindings,
+    error::HeartbeatError,
+    future::ServiceFuture,
+    idevice::Device,
+    plist_helpers::{dict_string, dict_uint},
+};
 
-use log::info;
+use log::{info, warn};
 use plist_plus::Plist;
 
+/// The interval assumed until the device sends its own
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(15);
+/// How long past the interval to wait for a heartbeat before giving up on the device
+const HEARTBEAT_GRACE: Duration = Duration::from_secs(10);
+/// How often the keeper's thread checks whether it has been stopped, in milliseconds
+const STOP_CHECK_MS: u32 = 500;
+
 /// A required service for most other services.
 /// iOS will close other connections if there is no active heartbeat client
 ///
@@ -25,9 +46,6 @@ pub struct HeartbeatClient {
 unsafe impl Send for HeartbeatClient {}
 unsafe impl Sync for HeartbeatClient {}
 
-unsafe impl Send for HeartbeatClientFuture {}
-unsafe impl Sync for HeartbeatClientFuture {}
-
 impl HeartbeatClient {
     /// Starts a new service with heartbeat
     /// # Arguments
@@ -82,94 +100,192 @@ impl HeartbeatClient {
     ///
     /// ***Verified:*** False
     pub fn receive(&self, timeout: u32) -> Result<Plist, HeartbeatError> {
-        let mut plist_ptr = unsafe { std::mem::zeroed() };
-
-        let result = unsafe {
-            if timeout == 0 {
-                unsafe_bindings::heartbeat_receive(self.pointer, &mut plist_ptr)
-            } else {
-                {
-                    unsafe_bindings::heartbeat_receive_with_timeout(
-                        self.pointer,
-                        &mut plist_ptr,
-                        timeout,
-                    )
-                }
-            }
-        }
-        .into();
-        if result != HeartbeatError::Success {
-            return Err(result);
-        }
-
-        Ok(plist_ptr.into())
+        receive(self.pointer, timeout)
     }
 
     /// Receive data from the heartbeat service as a future.
+    /// The receive runs on a helper thread, and the task is woken when a message, an error or the timeout arrives.
+    /// The helper thread holds its own reference to the client, so it outlives the future safely.
     /// If the error is a MuxError, this usually means that the device has disconnected.
     /// # Arguments
-    /// * `timeout` - How long to wait for a message. If 0, this will block indefinitely.
+    /// * `timeout` - How long to wait for a message. If 0, this will wait indefinitely.
     /// # Returns
     /// The message as a plist
     ///
     /// ***Verified:*** False
-    pub fn receive_async(&self, timeout: u32) -> HeartbeatClientFuture {
-        HeartbeatClientFuture {
-            pointer: self.pointer,
-            start_time: std::time::Instant::now(),
-            timeout,
-        }
+    pub fn receive_async(self: &Arc<Self>, timeout: u32) -> HeartbeatClientFuture {
+        let client = self.clone();
+        ServiceFuture::new(move |cancelled| {
+            let deadline =
+                (timeout != 0).then(|| Instant::now() + Duration::from_millis(timeout as u64));
+            loop {
+                // Receive in short slices so dropping the future isn't held up
+                let slice = match deadline {
+                    Some(deadline) => deadline
+                        .saturating_duration_since(Instant::now())
+                        .as_millis()
+                        .clamp(1, STOP_CHECK_MS as u128)
+                        as u32,
+                    None => STOP_CHECK_MS,
+                };
+                match client.receive(slice) {
+                    Err(HeartbeatError::Timeout) | Err(HeartbeatError::NotEnoughData) => {
+                        let expired = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+                        if expired || cancelled.load(Ordering::Relaxed) {
+                            return Err(HeartbeatError::Timeout);
+                        }
+                    }
+                    result => return result,
+                }
+            }
+        })
     }
 }
 
-pub struct HeartbeatClientFuture {
+/// Waits for a heartbeat message without spinning
+pub type HeartbeatClientFuture = ServiceFuture<Result<Plist, HeartbeatError>>;
+
+fn receive(
     pointer: unsafe_bindings::heartbeat_client_t,
-    start_time: std::time::Instant,
     timeout: u32,
+) -> Result<Plist, HeartbeatError> {
+    let mut plist_ptr = unsafe { std::mem::zeroed() };
+
+    let result = unsafe {
+        if timeout == 0 {
+            unsafe_bindings::heartbeat_receive(pointer, &mut plist_ptr)
+        } else {
+            unsafe_bindings::heartbeat_receive_with_timeout(pointer, &mut plist_ptr, timeout)
+        }
+    }
+    .into();
+    if result != HeartbeatError::Success {
+        return Err(result);
+    }
+
+    Ok(plist_ptr.into())
 }
 
-impl HeartbeatClientFuture {
-    fn receive(&self) -> Result<Plist, HeartbeatError> {
-        let mut plist_ptr = unsafe { std::mem::zeroed() };
-        let result = unsafe {
-            unsafe_bindings::heartbeat_receive_with_timeout(self.pointer, &mut plist_ptr, 1000)
+/// Keeps a heartbeat connection alive on a background thread, answering each Marco with a Polo.
+/// The thread stops when the keeper is dropped.
+pub struct HeartbeatKeeper {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HeartbeatKeeper {
+    /// Starts a heartbeat connection and answers it in the background
+    /// # Arguments
+    /// * `device` - The device to keep alive
+    /// * `label` - The label to give the connection
+    /// * `on_disconnect` - Called from the keeper's thread if the device stops responding
+    /// # Returns
+    /// The running keeper
+    ///
+    /// ***Verified:*** False
+    pub fn start(
+        device: &Device,
+        label: impl Into<String>,
+        on_disconnect: impl FnOnce(HeartbeatError) + Send + 'static,
+    ) -> Result<Self, HeartbeatError> {
+        let client = HeartbeatClient::new(device, label)?;
+        Ok(Self::with_client(client, on_disconnect))
+    }
+
+    /// Starts a heartbeat connection and answers it in the background, reporting a disconnect on a channel
+    /// # Arguments
+    /// * `device` - The device to keep alive
+    /// * `label` - The label to give the connection
+    /// # Returns
+    /// The running keeper and a channel that receives the error if the device stops responding
+    ///
+    /// ***Verified:*** False
+    pub fn start_with_channel(
+        device: &Device,
+        label: impl Into<String>,
+    ) -> Result<(Self, mpsc::Receiver<HeartbeatError>), HeartbeatError> {
+        let (sender, receiver) = mpsc::channel();
+        let keeper = Self::start(device, label, move |error| {
+            let _ = sender.send(error);
+        })?;
+        Ok((keeper, receiver))
+    }
+
+    /// Answers an existing heartbeat connection in the background
+    /// # Arguments
+    /// * `client` - The heartbeat connection to answer
+    /// * `on_disconnect` - Called from the keeper's thread if the device stops responding
+    /// # Returns
+    /// The running keeper
+    ///
+    /// ***Verified:*** False
+    pub fn with_client(
+        client: HeartbeatClient,
+        on_disconnect: impl FnOnce(HeartbeatError) + Send + 'static,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::spawn(move || {
+            if let Err(error) = keep_alive(&client, &thread_stop) {
+                warn!("Heartbeat lost: {:?}", error);
+                on_disconnect(error);
+            }
+        });
+        HeartbeatKeeper {
+            stop,
+            thread: Some(thread),
         }
-        .into();
-        if result != HeartbeatError::Success {
-            return Err(result);
+    }
+
+    /// Checks whether the keeper is still answering heartbeats
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+}
+
+impl Drop for HeartbeatKeeper {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
         }
-        Ok(plist_ptr.into())
     }
 }
 
-impl Future for HeartbeatClientFuture {
-    type Output = Result<Plist, HeartbeatError>;
-
-    fn poll(
-        self: Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Self::Output> {
-        // Determine if we have passed the timeout + 5 seconds
-        let now = std::time::Instant::now();
-        let elapsed = now.duration_since(self.start_time);
-        let timeout = self.timeout as u128;
-        let elapsed_ms = elapsed.as_millis();
-        if elapsed_ms > timeout + 5000 {
-            // Query the heartbeat service for a message
-            match self.receive() {
-                Ok(plist) => {
-                    info!("Received heartbeat message");
-                    return std::task::Poll::Ready(Ok(plist));
+/// Answers heartbeats until stopped, returning the error if the device goes away
+fn keep_alive(client: &HeartbeatClient, stop: &AtomicBool) -> Result<(), HeartbeatError> {
+    let mut interval = DEFAULT_INTERVAL;
+    let mut last_heard = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        // Wait in short slices so a stop request isn't held up by a quiet device
+        let message = match client.receive(STOP_CHECK_MS) {
+            Ok(message) => message,
+            Err(HeartbeatError::Timeout) | Err(HeartbeatError::NotEnoughData) => {
+                if last_heard.elapsed() > interval + HEARTBEAT_GRACE {
+                    return Err(HeartbeatError::Timeout);
                 }
-                Err(error) => {
-                    info!("Received heartbeat error: {:?}", error);
-                    return std::task::Poll::Ready(Err(error));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        last_heard = Instant::now();
+
+        match dict_string(&message, "Command").as_deref() {
+            Some("Marco") => {
+                if let Some(seconds) = dict_uint(&message, "Interval") {
+                    interval = Duration::from_secs(seconds);
                 }
+                let mut polo = Plist::new_dict();
+                let _ = polo.dict_set_item("Command", Plist::new_string("Polo"));
+                client.send(polo)?;
             }
+            Some("SleepyTime") => info!("Device is going to sleep"),
+            command => info!("Unexpected heartbeat command {:?}", command),
         }
-        cx.waker().wake_by_ref();
-        std::task::Poll::Pending
     }
+    Ok(())
 }
 
 impl Drop for HeartbeatClient {
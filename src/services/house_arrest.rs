@@ -1,12 +1,24 @@
 // jkcoxson
 
-use std::ffi::CString;
+use std::{
+    ffi::CString,
+    ops::Deref,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
 use crate::{
-    bindings as unsafe_bindings, error::HouseArrestError, idevice::Device,
-    services::lockdownd::LockdowndService,
+    bindings as unsafe_bindings,
+    error::{AfcError, HouseArrestError},
+    idevice::Device,
+    plist_helpers::dict_string,
+    services::{
+        afc::{AfcClient, AfcFileMode, LinkType},
+        lockdownd::LockdowndService,
+    },
 };
 
+use log::warn;
 use plist_plus::Plist;
 
 /// iTunes file transfer service.
@@ -16,7 +28,7 @@ pub struct HouseArrest<'a> {
     phantom: std::marker::PhantomData<&'a Device>,
 }
 
-impl HouseArrest<'_> {
+impl<'a> HouseArrest<'a> {
     /// Creates a new house arrest service from a lockdown service
     /// # Arguments
     /// * `device` - The device to create the sevice with
@@ -144,6 +156,129 @@ impl HouseArrest<'_> {
 
         Ok(plist_t.into())
     }
+
+    /// Vends the whole data container of an app over AFC.
+    /// Only works for apps signed with the get-task-allow entitlement.
+    /// # Arguments
+    /// * `bundle_id` - The bundle identifier of the app
+    /// # Returns
+    /// An AFC client rooted at the app's container
+    ///
+    /// ***Verified:*** False
+    pub fn vend_container(
+        self,
+        bundle_id: impl Into<String>,
+    ) -> Result<HouseArrestAfcClient<'a>, HouseArrestError> {
+        self.vend(VendCommand::Container, bundle_id)
+    }
+
+    /// Vends the Documents folder of an app over AFC.
+    /// Only works for apps with file sharing enabled.
+    /// # Arguments
+    /// * `bundle_id` - The bundle identifier of the app
+    /// # Returns
+    /// An AFC client rooted at the app's container, with access limited to Documents
+    ///
+    /// ***Verified:*** False
+    pub fn vend_documents(
+        self,
+        bundle_id: impl Into<String>,
+    ) -> Result<HouseArrestAfcClient<'a>, HouseArrestError> {
+        self.vend(VendCommand::Documents, bundle_id)
+    }
+
+    /// Sends a vend command and switches the connection over to AFC.
+    /// The house arrest client is consumed, as the connection can't be used for commands afterwards.
+    /// # Arguments
+    /// * `command` - What to vend
+    /// * `bundle_id` - The bundle identifier of the app
+    /// # Returns
+    /// An AFC client rooted at the app's container
+    ///
+    /// ***Verified:*** False
+    pub fn vend(
+        self,
+        command: VendCommand,
+        bundle_id: impl Into<String>,
+    ) -> Result<HouseArrestAfcClient<'a>, HouseArrestError> {
+        let bundle_id = bundle_id.into();
+        let reply = self.send_command(command.as_str(), bundle_id.as_str())?;
+
+        if let Some(error) = dict_string(&reply, "Error") {
+            warn!(
+                "Unable to vend {} for {}: {}",
+                command.as_str(),
+                bundle_id,
+                error
+            );
+            return Err(match error.as_str() {
+                "InstallationLookupFailed" | "ApplicationLookupFailed" => {
+                    HouseArrestError::InstallationLookupFailed
+                }
+                "NotDebuggable" | "InstallationNotDebuggable" => HouseArrestError::NotDebuggable,
+                _ => HouseArrestError::CommandFailed,
+            });
+        }
+        if dict_string(&reply, "Status").as_deref() != Some("Complete") {
+            warn!("Unexpected house arrest reply: {:?}", reply);
+            return Err(HouseArrestError::InvalidResponse);
+        }
+
+        let mut afc_pointer = unsafe { std::mem::zeroed() };
+        let result: AfcError = unsafe {
+            unsafe_bindings::afc_client_new_from_house_arrest_client(self.pointer, &mut afc_pointer)
+        }
+        .into();
+        if result != AfcError::Success {
+            warn!(
+                "Unable to create an AFC client from house arrest: {}",
+                result
+            );
+            return Err(HouseArrestError::AfcFailed);
+        }
+
+        Ok(HouseArrestAfcClient {
+            afc: AfcClient {
+                pointer: afc_pointer,
+                phantom: std::marker::PhantomData,
+            },
+            _house_arrest: self,
+        })
+    }
+}
+
+/// The commands house arrest accepts to vend an app's files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VendCommand {
+    /// The whole app container, including Library and tmp
+    Container,
+    /// Only the Documents folder
+    Documents,
+}
+
+impl VendCommand {
+    fn as_str(&self) -> &'static str {
+        match self {
+            VendCommand::Container => "VendContainer",
+            VendCommand::Documents => "VendDocuments",
+        }
+    }
+}
+
+/// An AFC client scoped to an app's sandbox.
+/// The AFC connection is borrowed from house arrest, so both are kept alive together.
+pub struct HouseArrestAfcClient<'a> {
+    // Dropped before the house arrest client that owns the connection
+    afc: AfcClient<'a>,
+    _house_arrest: HouseArrest<'a>,
+}
+
+impl<'a> Deref for HouseArrestAfcClient<'a> {
+    type Target = AfcClient<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.afc
+    }
 }
 
 impl Drop for HouseArrest<'_> {
@@ -153,3 +288,458 @@ impl Drop for HouseArrest<'_> {
         }
     }
 }
+
+/// The folders of an app container that are captured in a snapshot
+const CONTAINER_ROOTS: [&str; 3] = ["Documents", "Library", "tmp"];
+
+/// How many bytes to move per AFC read or write
+const TRANSFER_CHUNK: u32 = 1024 * 1024;
+
+/// Where a snapshot of an app container is stored on the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContainerArchive {
+    /// A directory mirroring the container
+    Directory(PathBuf),
+    /// A tar file containing the container
+    #[cfg(feature = "container-tar")]
+    Tar(PathBuf),
+}
+
+/// Captures the Documents, Library and tmp folders of an app
+/// # Arguments
+/// * `device` - The device the app is installed on
+/// * `bundle_id` - The bundle identifier of the app
+/// * `dest` - Where to store the snapshot
+/// # Returns
+/// The number of files captured
+///
+/// ***Verified:*** False
+pub fn snapshot_app_container(
+    device: &Device,
+    bundle_id: impl Into<String>,
+    dest: &ContainerArchive,
+) -> Result<u64, HouseArrestError> {
+    HouseArrest::start_service(device, "snapshot_app_container")?
+        .vend_container(bundle_id)?
+        .snapshot(dest)
+}
+
+/// Restores a snapshot taken with `snapshot_app_container`
+/// # Arguments
+/// * `device` - The device the app is installed on
+/// * `bundle_id` - The bundle identifier of the app
+/// * `src` - The snapshot to restore
+/// * `wipe` - Whether to empty the container before restoring
+/// # Returns
+/// The number of files restored
+///
+/// ***Verified:*** False
+pub fn restore_app_container(
+    device: &Device,
+    bundle_id: impl Into<String>,
+    src: &ContainerArchive,
+    wipe: bool,
+) -> Result<u64, HouseArrestError> {
+    HouseArrest::start_service(device, "restore_app_container")?
+        .vend_container(bundle_id)?
+        .restore(src, wipe)
+}
+
+impl HouseArrestAfcClient<'_> {
+    /// Copies the Documents, Library and tmp folders of the container to the host.
+    /// Modification times are kept, AFC doesn't expose permissions so defaults are used.
+    /// # Arguments
+    /// * `dest` - Where to store the snapshot
+    /// # Returns
+    /// The number of files captured
+    ///
+    /// ***Verified:*** False
+    pub fn snapshot(&self, dest: &ContainerArchive) -> Result<u64, HouseArrestError> {
+        match dest {
+            ContainerArchive::Directory(path) => {
+                let mut sink = DirectorySink { root: path.clone() };
+                self.snapshot_into(&mut sink)
+            }
+            #[cfg(feature = "container-tar")]
+            ContainerArchive::Tar(path) => {
+                let file = std::fs::File::create(path).map_err(io_error)?;
+                let mut sink = TarSink {
+                    builder: tar::Builder::new(file),
+                };
+                let files = self.snapshot_into(&mut sink)?;
+                sink.builder
+                    .into_inner()
+                    .and_then(|file| file.sync_all())
+                    .map_err(io_error)?;
+                Ok(files)
+            }
+        }
+    }
+
+    /// Copies a snapshot back into the container
+    /// # Arguments
+    /// * `src` - The snapshot to restore
+    /// * `wipe` - Whether to empty the container before restoring
+    /// # Returns
+    /// The number of files restored
+    ///
+    /// ***Verified:*** False
+    pub fn restore(&self, src: &ContainerArchive, wipe: bool) -> Result<u64, HouseArrestError> {
+        if wipe {
+            self.wipe()?;
+        }
+        match src {
+            ContainerArchive::Directory(path) => {
+                let mut files = 0;
+                for root in CONTAINER_ROOTS {
+                    let local = path.join(root);
+                    if local.is_dir() {
+                        files += self.restore_directory(&local, root)?;
+                    }
+                }
+                Ok(files)
+            }
+            #[cfg(feature = "container-tar")]
+            ContainerArchive::Tar(path) => self.restore_tar(path),
+        }
+    }
+
+    /// Removes everything inside the Documents, Library and tmp folders of the container
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn wipe(&self) -> Result<(), HouseArrestError> {
+        for root in CONTAINER_ROOTS {
+            let entries = match self.read_directory(root) {
+                Ok(entries) => entries,
+                Err(AfcError::ObjectNotFound) => continue,
+                Err(e) => return Err(afc_error(e)),
+            };
+            for entry in entries.iter().filter(|e| *e != "." && *e != "..") {
+                self.remove_path_and_contents(format!("{}/{}", root, entry))
+                    .map_err(afc_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn snapshot_into(&self, sink: &mut dyn SnapshotSink) -> Result<u64, HouseArrestError> {
+        let mut files = 0;
+        for root in CONTAINER_ROOTS {
+            let info = match self.get_file_info(root) {
+                Ok(info) => info,
+                Err(AfcError::ObjectNotFound) => continue,
+                Err(e) => return Err(afc_error(e)),
+            };
+            sink.directory(root, afc_mtime(&info)).map_err(io_error)?;
+            files += self.snapshot_directory(root, sink)?;
+        }
+        Ok(files)
+    }
+
+    fn snapshot_directory(
+        &self,
+        path: &str,
+        sink: &mut dyn SnapshotSink,
+    ) -> Result<u64, HouseArrestError> {
+        let mut files = 0;
+        let entries = self.read_directory(path).map_err(afc_error)?;
+        for entry in entries.iter().filter(|e| *e != "." && *e != "..") {
+            let entry_path = format!("{}/{}", path, entry);
+            let info = self.get_file_info(entry_path.as_str()).map_err(afc_error)?;
+            let mtime = afc_mtime(&info);
+            match info.get("st_ifmt").map(|s| s.as_str()) {
+                Some("S_IFDIR") => {
+                    sink.directory(&entry_path, mtime).map_err(io_error)?;
+                    files += self.snapshot_directory(&entry_path, sink)?;
+                }
+                Some("S_IFLNK") => {
+                    if let Some(target) = info.get("LinkTarget") {
+                        sink.symlink(&entry_path, target, mtime).map_err(io_error)?;
+                    }
+                }
+                _ => {
+                    let data = self.read_file(&entry_path)?;
+                    sink.file(&entry_path, &data, mtime).map_err(io_error)?;
+                    files += 1;
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, HouseArrestError> {
+        let handle = self
+            .file_open(path, AfcFileMode::ReadOnly)
+            .map_err(afc_error)?;
+        let mut data = Vec::new();
+        let result = loop {
+            match self.file_read(handle, TRANSFER_CHUNK) {
+                Ok(chunk) if chunk.is_empty() => break Ok(()),
+                Ok(chunk) => data.extend_from_slice(&chunk),
+                Err(e) => break Err(afc_error(e)),
+            }
+        };
+        let _ = self.file_close(handle);
+        result.map(|_| data)
+    }
+
+    fn write_file(
+        &self,
+        path: &str,
+        data: &[u8],
+        mtime: Option<SystemTime>,
+    ) -> Result<(), HouseArrestError> {
+        let handle = self
+            .file_open(path, AfcFileMode::WriteOnly)
+            .map_err(afc_error)?;
+        let result = data
+            .chunks(TRANSFER_CHUNK as usize)
+            .try_for_each(|chunk| self.file_write(handle, chunk.to_vec()))
+            .map_err(afc_error);
+        let _ = self.file_close(handle);
+        result?;
+        self.set_mtime(path, mtime);
+        Ok(())
+    }
+
+    fn set_mtime(&self, path: &str, mtime: Option<SystemTime>) {
+        let nanos = mtime
+            .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|mtime| mtime.as_nanos() as u64);
+        if let Some(nanos) = nanos {
+            if let Err(e) = self.set_file_time(path, nanos) {
+                warn!("Unable to set the modification time of {}: {}", path, e);
+            }
+        }
+    }
+
+    fn make_directory_if_missing(&self, path: &str) -> Result<(), HouseArrestError> {
+        match self.make_directory(path) {
+            Ok(()) | Err(AfcError::ObjectExists) => Ok(()),
+            Err(e) => Err(afc_error(e)),
+        }
+    }
+
+    fn restore_directory(&self, local: &Path, remote: &str) -> Result<u64, HouseArrestError> {
+        self.make_directory_if_missing(remote)?;
+        let mut files = 0;
+        for entry in std::fs::read_dir(local).map_err(io_error)? {
+            let entry = entry.map_err(io_error)?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let remote_path = format!("{}/{}", remote, name);
+            let metadata = std::fs::symlink_metadata(entry.path()).map_err(io_error)?;
+            if metadata.file_type().is_symlink() {
+                let target = std::fs::read_link(entry.path()).map_err(io_error)?;
+                let _ = self.remove_path(remote_path.as_str());
+                self.make_link(
+                    target.to_string_lossy(),
+                    LinkType::SymbolicLink,
+                    remote_path.as_str(),
+                )
+                .map_err(afc_error)?;
+            } else if metadata.is_dir() {
+                files += self.restore_directory(&entry.path(), &remote_path)?;
+                self.set_mtime(&remote_path, metadata.modified().ok());
+            } else {
+                let data = std::fs::read(entry.path()).map_err(io_error)?;
+                self.write_file(&remote_path, &data, metadata.modified().ok())?;
+                files += 1;
+            }
+        }
+        Ok(files)
+    }
+
+    #[cfg(feature = "container-tar")]
+    fn restore_tar(&self, path: &Path) -> Result<u64, HouseArrestError> {
+        use std::io::Read;
+
+        let file = std::fs::File::open(path).map_err(io_error)?;
+        let mut archive = tar::Archive::new(file);
+        let mut files = 0;
+        for entry in archive.entries().map_err(io_error)? {
+            let mut entry = entry.map_err(io_error)?;
+            let entry_path = entry.path().map_err(io_error)?;
+            let remote_path = entry_path
+                .to_string_lossy()
+                .trim_end_matches('/')
+                .to_string();
+            if !CONTAINER_ROOTS
+                .iter()
+                .any(|root| remote_path == *root || remote_path.starts_with(&format!("{}/", root)))
+            {
+                warn!(
+                    "Skipping {}, it is outside of the container folders",
+                    remote_path
+                );
+                continue;
+            }
+            let mtime = entry
+                .header()
+                .mtime()
+                .ok()
+                .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+            match entry.header().entry_type() {
+                tar::EntryType::Directory => {
+                    self.make_directory_if_missing(&remote_path)?;
+                    self.set_mtime(&remote_path, mtime);
+                }
+                tar::EntryType::Symlink => {
+                    if let Ok(Some(target)) = entry.link_name() {
+                        let _ = self.remove_path(remote_path.as_str());
+                        self.make_link(
+                            target.to_string_lossy(),
+                            LinkType::SymbolicLink,
+                            remote_path.as_str(),
+                        )
+                        .map_err(afc_error)?;
+                    }
+                }
+                tar::EntryType::Regular => {
+                    let mut data = Vec::new();
+                    entry.read_to_end(&mut data).map_err(io_error)?;
+                    self.write_file(&remote_path, &data, mtime)?;
+                    files += 1;
+                }
+                other => warn!(
+                    "Skipping {}, unsupported entry type {:?}",
+                    remote_path, other
+                ),
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Receives the contents of a container as it is walked
+trait SnapshotSink {
+    fn directory(&mut self, path: &str, mtime: Option<SystemTime>) -> std::io::Result<()>;
+    fn file(&mut self, path: &str, data: &[u8], mtime: Option<SystemTime>) -> std::io::Result<()>;
+    fn symlink(
+        &mut self,
+        path: &str,
+        target: &str,
+        mtime: Option<SystemTime>,
+    ) -> std::io::Result<()>;
+}
+
+struct DirectorySink {
+    root: PathBuf,
+}
+
+impl SnapshotSink for DirectorySink {
+    fn directory(&mut self, path: &str, mtime: Option<SystemTime>) -> std::io::Result<()> {
+        let path = self.root.join(path);
+        std::fs::create_dir_all(&path)?;
+        set_local_mtime(&path, mtime);
+        Ok(())
+    }
+
+    fn file(&mut self, path: &str, data: &[u8], mtime: Option<SystemTime>) -> std::io::Result<()> {
+        let path = self.root.join(path);
+        std::fs::write(&path, data)?;
+        set_local_mtime(&path, mtime);
+        Ok(())
+    }
+
+    fn symlink(
+        &mut self,
+        path: &str,
+        target: &str,
+        _mtime: Option<SystemTime>,
+    ) -> std::io::Result<()> {
+        let path = self.root.join(path);
+        #[cfg(unix)]
+        {
+            let _ = std::fs::remove_file(&path);
+            std::os::unix::fs::symlink(target, path)
+        }
+        #[cfg(not(unix))]
+        {
+            warn!(
+                "Skipping symlink {} -> {}, not supported on this platform",
+                path.display(),
+                target
+            );
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "container-tar")]
+struct TarSink {
+    builder: tar::Builder<std::fs::File>,
+}
+
+#[cfg(feature = "container-tar")]
+impl TarSink {
+    fn header(entry_type: tar::EntryType, mode: u32, mtime: Option<SystemTime>) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_size(0);
+        let mtime = mtime
+            .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|mtime| mtime.as_secs())
+            .unwrap_or_default();
+        header.set_mtime(mtime);
+        header
+    }
+}
+
+#[cfg(feature = "container-tar")]
+impl SnapshotSink for TarSink {
+    fn directory(&mut self, path: &str, mtime: Option<SystemTime>) -> std::io::Result<()> {
+        let mut header = Self::header(tar::EntryType::Directory, 0o755, mtime);
+        self.builder
+            .append_data(&mut header, format!("{}/", path), std::io::empty())
+    }
+
+    fn file(&mut self, path: &str, data: &[u8], mtime: Option<SystemTime>) -> std::io::Result<()> {
+        let mut header = Self::header(tar::EntryType::Regular, 0o644, mtime);
+        header.set_size(data.len() as u64);
+        self.builder.append_data(&mut header, path, data)
+    }
+
+    fn symlink(
+        &mut self,
+        path: &str,
+        target: &str,
+        mtime: Option<SystemTime>,
+    ) -> std::io::Result<()> {
+        let mut header = Self::header(tar::EntryType::Symlink, 0o777, mtime);
+        self.builder.append_link(&mut header, path, target)
+    }
+}
+
+/// Reads the modification time out of AFC file info, which is in nanoseconds
+fn afc_mtime(info: &std::collections::HashMap<String, String>) -> Option<SystemTime> {
+    let nanos = info.get("st_mtime")?.parse::<u64>().ok()?;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos))
+}
+
+fn set_local_mtime(path: &Path, mtime: Option<SystemTime>) {
+    if let Some(mtime) = mtime {
+        let result = std::fs::File::open(path).and_then(|file| file.set_modified(mtime));
+        if let Err(e) = result {
+            warn!(
+                "Unable to set the modification time of {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+fn afc_error(error: AfcError) -> HouseArrestError {
+    warn!("AFC operation in app container failed: {}", error);
+    HouseArrestError::AfcFailed
+}
+
+fn io_error(error: std::io::Error) -> HouseArrestError {
+    warn!("Unable to access app container snapshot: {}", error);
+    HouseArrestError::IoError
+}
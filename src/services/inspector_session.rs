@@ -0,0 +1,474 @@
+// jkcoxson
+// Speaks the WebKit remote inspector protocol over a web inspector connection
+
+use std::{
+    collections::{hash_map::RandomState, HashMap, VecDeque},
+    hash::{BuildHasher, Hasher},
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use plist_plus::{Plist, PlistType};
+use serde_json::Value;
+
+use crate::{
+    error::WebInspectorError,
+    idevice::Device,
+    plist_helpers::{data_bytes, dict_bool, dict_entries, dict_item, dict_string, dict_uint},
+    services::web_inspector::WebInspectorClient,
+};
+
+/// How many unclaimed events are kept before the oldest are dropped
+const MAX_BACKLOG: usize = 1024;
+/// How long to wait for the device to answer a request
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An application on the device that has inspectable content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InspectorApplication {
+    /// The identifier the inspector uses for the application, such as `PID:123`
+    pub id: String,
+    pub bundle_id: Option<String>,
+    pub name: Option<String>,
+    /// Whether the application hosts web views on behalf of another, like a web content process
+    pub is_proxy: bool,
+    pub is_active: bool,
+    /// The identifier of the application this one is a proxy for
+    pub host_id: Option<String>,
+}
+
+impl InspectorApplication {
+    fn from_plist(app: &Plist) -> Option<Self> {
+        Some(InspectorApplication {
+            id: dict_string(app, "WIRApplicationIdentifierKey")?,
+            bundle_id: dict_string(app, "WIRApplicationBundleIdentifierKey"),
+            name: dict_string(app, "WIRApplicationNameKey"),
+            is_proxy: flag(app, "WIRIsApplicationProxyKey"),
+            is_active: flag(app, "WIRIsApplicationActiveKey"),
+            host_id: dict_string(app, "WIRHostApplicationIdentifierKey"),
+        })
+    }
+}
+
+/// What kind of content a page is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InspectorPageType {
+    /// A web view on older versions of iOS
+    Web,
+    /// A web view on newer versions of iOS, which routes messages through targets
+    WebPage,
+    JavaScript,
+    ServiceWorker,
+    Automation,
+    Itml,
+    Other(String),
+}
+
+impl From<&str> for InspectorPageType {
+    fn from(value: &str) -> Self {
+        match value {
+            "WIRTypeWeb" => InspectorPageType::Web,
+            "WIRTypeWebPage" => InspectorPageType::WebPage,
+            "WIRTypeJavaScript" => InspectorPageType::JavaScript,
+            "WIRTypeServiceWorker" => InspectorPageType::ServiceWorker,
+            "WIRTypeAutomation" => InspectorPageType::Automation,
+            "WIRTypeITML" => InspectorPageType::Itml,
+            other => InspectorPageType::Other(other.to_string()),
+        }
+    }
+}
+
+/// A page or JavaScript context that can be inspected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InspectorPage {
+    pub id: u64,
+    /// The application the page belongs to
+    pub app_id: String,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub page_type: InspectorPageType,
+    /// The connection already inspecting the page, if any
+    pub connection_id: Option<String>,
+}
+
+impl InspectorPage {
+    fn from_plist(app_id: &str, page: &Plist) -> Option<Self> {
+        Some(InspectorPage {
+            id: dict_uint(page, "WIRPageIdentifierKey")?,
+            app_id: app_id.to_string(),
+            title: dict_string(page, "WIRTitleKey"),
+            url: dict_string(page, "WIRURLKey"),
+            page_type: dict_string(page, "WIRTypeKey")
+                .as_deref()
+                .unwrap_or("WIRTypeWeb")
+                .into(),
+            connection_id: dict_string(page, "WIRConnectionIdentifierKey"),
+        })
+    }
+}
+
+/// A socket to a page, through which inspector messages are exchanged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InspectorSocket {
+    pub app_id: String,
+    pub page_id: u64,
+    /// Identifies this end of the socket to the device
+    pub sender_id: String,
+}
+
+/// Something the device reported
+#[derive(Debug, Clone)]
+pub enum InspectorEvent {
+    ApplicationConnected(InspectorApplication),
+    ApplicationUpdated(InspectorApplication),
+    ApplicationDisconnected(String),
+    /// The pages of an application changed
+    Listing {
+        app_id: String,
+        pages: Vec<InspectorPage>,
+    },
+    /// An inspector message arrived on a socket
+    SocketData {
+        sender_id: String,
+        message: Value,
+    },
+    /// A message this session doesn't interpret, with its selector and argument
+    Other(String, Plist),
+}
+
+/// A session with the device's web inspector, tracking applications and their pages
+pub struct InspectorSession<'a> {
+    client: WebInspectorClient<'a>,
+    connection_id: String,
+    applications: HashMap<String, InspectorApplication>,
+    listings: HashMap<String, Vec<InspectorPage>>,
+    backlog: VecDeque<InspectorEvent>,
+}
+
+impl<'a> InspectorSession<'a> {
+    /// Starts the web inspector service and identifies this host to it
+    /// # Arguments
+    /// * `device` - The device to inspect
+    /// # Returns
+    /// The session
+    ///
+    /// ***Verified:*** False
+    pub fn new(device: &'a Device) -> Result<Self, WebInspectorError> {
+        let client = WebInspectorClient::start_service(device, "inspector_session")?;
+        let session = InspectorSession {
+            client,
+            connection_id: new_uuid(),
+            applications: HashMap::new(),
+            listings: HashMap::new(),
+            backlog: VecDeque::new(),
+        };
+        session.send_rpc("_rpc_reportIdentifier:", Plist::new_dict())?;
+        info!("Inspector session {} started", session.connection_id);
+        Ok(session)
+    }
+
+    /// Gets the identifier this session reported to the device
+    pub fn connection_id(&self) -> &str {
+        &self.connection_id
+    }
+
+    /// Asks the device which applications can be inspected
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The applications with inspectable content
+    ///
+    /// ***Verified:*** False
+    pub fn applications(&mut self) -> Result<Vec<InspectorApplication>, WebInspectorError> {
+        self.send_rpc("_rpc_getConnectedApplications:", Plist::new_dict())?;
+        self.wait_for(|event| {
+            matches!(event, InspectorEvent::Other(selector, _)
+                if selector == "_rpc_reportConnectedApplicationList:")
+        })?;
+        let mut applications: Vec<InspectorApplication> =
+            self.applications.values().cloned().collect();
+        applications.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(applications)
+    }
+
+    /// Asks an application for its inspectable pages
+    /// # Arguments
+    /// * `app_id` - The application's inspector identifier
+    /// # Returns
+    /// The application's pages
+    ///
+    /// ***Verified:*** False
+    pub fn pages(&mut self, app_id: &str) -> Result<Vec<InspectorPage>, WebInspectorError> {
+        if !self.applications.contains_key(app_id) {
+            self.applications()?;
+            if !self.applications.contains_key(app_id) {
+                return Err(WebInspectorError::ApplicationNotFound);
+            }
+        }
+        let mut argument = Plist::new_dict();
+        let _ = argument.dict_set_item("WIRApplicationIdentifierKey", Plist::new_string(app_id));
+        self.send_rpc("_rpc_forwardGetListing:", argument)?;
+        self.wait_for(
+            |event| matches!(event, InspectorEvent::Listing { app_id: id, .. } if id == app_id),
+        )?;
+        Ok(self.listings.get(app_id).cloned().unwrap_or_default())
+    }
+
+    /// Opens a socket to a page
+    /// # Arguments
+    /// * `app_id` - The application the page belongs to
+    /// * `page_id` - The page to inspect
+    /// # Returns
+    /// The socket to send and receive inspector messages on
+    ///
+    /// ***Verified:*** False
+    pub fn open_socket(
+        &mut self,
+        app_id: &str,
+        page_id: u64,
+    ) -> Result<InspectorSocket, WebInspectorError> {
+        let known = self
+            .listings
+            .get(app_id)
+            .is_some_and(|pages| pages.iter().any(|page| page.id == page_id));
+        if !known && !self.pages(app_id)?.iter().any(|page| page.id == page_id) {
+            return Err(WebInspectorError::PageNotFound);
+        }
+
+        let socket = InspectorSocket {
+            app_id: app_id.to_string(),
+            page_id,
+            sender_id: new_uuid(),
+        };
+        let mut argument = socket_argument(&socket);
+        let _ = argument.dict_set_item("WIRAutomaticallyPause", Plist::new_bool(false));
+        self.send_rpc("_rpc_forwardSocketSetup:", argument)?;
+        Ok(socket)
+    }
+
+    /// Sends an inspector message to a page
+    /// # Arguments
+    /// * `socket` - The socket to the page
+    /// * `message` - The message, such as `{"id": 1, "method": "Runtime.evaluate", ...}`
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn send_message(
+        &mut self,
+        socket: &InspectorSocket,
+        message: &Value,
+    ) -> Result<(), WebInspectorError> {
+        let data = serde_json::to_vec(message).map_err(|_| WebInspectorError::InvalidMessage)?;
+        let mut argument = socket_argument(socket);
+        let _ = argument.dict_set_item("WIRSocketDataKey", Plist::new_data(&data));
+        self.send_rpc("_rpc_forwardSocketData:", argument)
+    }
+
+    /// Receives the next inspector message sent on a socket
+    /// # Arguments
+    /// * `socket` - The socket to the page
+    /// * `timeout` - How long to wait for a message
+    /// # Returns
+    /// The message, or `None` if none arrived in time
+    ///
+    /// ***Verified:*** False
+    pub fn receive_message(
+        &mut self,
+        socket: &InspectorSocket,
+        timeout: Duration,
+    ) -> Result<Option<Value>, WebInspectorError> {
+        let found = self.wait_until(timeout, |event| {
+            matches!(event, InspectorEvent::SocketData { sender_id, .. }
+                if *sender_id == socket.sender_id)
+        })?;
+        Ok(match found {
+            Some(InspectorEvent::SocketData { message, .. }) => Some(message),
+            _ => None,
+        })
+    }
+
+    /// Closes a socket, detaching the inspector from the page
+    /// # Arguments
+    /// * `socket` - The socket to close
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn close_socket(&mut self, socket: InspectorSocket) -> Result<(), WebInspectorError> {
+        self.backlog.retain(|event| {
+            !matches!(event, InspectorEvent::SocketData { sender_id, .. }
+                if *sender_id == socket.sender_id)
+        });
+        self.send_rpc("_rpc_forwardDidClose:", socket_argument(&socket))
+    }
+
+    /// Gets the next event from the device, including messages for any socket
+    /// # Arguments
+    /// * `timeout` - How long to wait for an event
+    /// # Returns
+    /// The event, or `None` if none arrived in time
+    ///
+    /// ***Verified:*** False
+    pub fn next_event(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<InspectorEvent>, WebInspectorError> {
+        self.wait_until(timeout, |_| true)
+    }
+
+    fn send_rpc(&self, selector: &str, mut argument: Plist) -> Result<(), WebInspectorError> {
+        let _ = argument.dict_set_item(
+            "WIRConnectionIdentifierKey",
+            Plist::new_string(&self.connection_id),
+        );
+        let mut message = Plist::new_dict();
+        let _ = message.dict_set_item("__selector", Plist::new_string(selector));
+        let _ = message.dict_set_item("__argument", argument);
+        self.client.send(message)
+    }
+
+    /// Waits for a reply to a request, failing if the device doesn't answer
+    fn wait_for(
+        &mut self,
+        matches: impl Fn(&InspectorEvent) -> bool,
+    ) -> Result<InspectorEvent, WebInspectorError> {
+        self.wait_until(REPLY_TIMEOUT, matches)?
+            .ok_or(WebInspectorError::ReceiveTimeout)
+    }
+
+    /// Takes the first matching event, receiving more until one matches or time runs out.
+    /// Events that don't match are kept for later.
+    fn wait_until(
+        &mut self,
+        timeout: Duration,
+        matches: impl Fn(&InspectorEvent) -> bool,
+    ) -> Result<Option<InspectorEvent>, WebInspectorError> {
+        if let Some(position) = self.backlog.iter().position(&matches) {
+            return Ok(self.backlog.remove(position));
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            // A timeout of 0 would wait forever
+            let millis = remaining.as_millis().clamp(1, u32::MAX as u128) as u32;
+            let message = match self.client.receive(millis) {
+                Ok(message) => message,
+                Err(WebInspectorError::ReceiveTimeout) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            let event = match self.handle(&message) {
+                Some(event) => event,
+                None => continue,
+            };
+            if matches(&event) {
+                return Ok(Some(event));
+            }
+            if self.backlog.len() >= MAX_BACKLOG {
+                self.backlog.pop_front();
+            }
+            self.backlog.push_back(event);
+        }
+    }
+
+    /// Updates the tracked applications and pages from a message and turns it into an event
+    fn handle(&mut self, message: &Plist) -> Option<InspectorEvent> {
+        let selector = dict_string(message, "__selector")?;
+        let argument = dict_item(message, "__argument").unwrap_or_else(Plist::new_dict);
+        match selector.as_str() {
+            "_rpc_reportConnectedApplicationList:" => {
+                self.applications.clear();
+                if let Some(apps) = dict_item(&argument, "WIRApplicationDictionaryKey") {
+                    for (_, app) in dict_entries(&apps) {
+                        if let Some(app) = InspectorApplication::from_plist(&app) {
+                            self.applications.insert(app.id.clone(), app);
+                        }
+                    }
+                }
+                Some(InspectorEvent::Other(selector, argument))
+            }
+            "_rpc_applicationConnected:" | "_rpc_applicationUpdated:" => {
+                let app = InspectorApplication::from_plist(&argument)?;
+                self.applications.insert(app.id.clone(), app.clone());
+                Some(if selector == "_rpc_applicationConnected:" {
+                    InspectorEvent::ApplicationConnected(app)
+                } else {
+                    InspectorEvent::ApplicationUpdated(app)
+                })
+            }
+            "_rpc_applicationDisconnected:" => {
+                let app_id = dict_string(&argument, "WIRApplicationIdentifierKey")?;
+                self.applications.remove(&app_id);
+                self.listings.remove(&app_id);
+                Some(InspectorEvent::ApplicationDisconnected(app_id))
+            }
+            "_rpc_applicationSentListing:" => {
+                let app_id = dict_string(&argument, "WIRApplicationIdentifierKey")?;
+                let mut pages: Vec<InspectorPage> = dict_item(&argument, "WIRListingKey")
+                    .map(|listing| dict_entries(&listing))
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|(_, page)| InspectorPage::from_plist(&app_id, page))
+                    .collect();
+                pages.sort_by_key(|page| page.id);
+                self.listings.insert(app_id.clone(), pages.clone());
+                Some(InspectorEvent::Listing { app_id, pages })
+            }
+            "_rpc_applicationSentData:" => {
+                let sender_id = dict_string(&argument, "WIRDestinationKey")?;
+                let data =
+                    dict_item(&argument, "WIRMessageDataKey").and_then(|data| data_bytes(&data))?;
+                match serde_json::from_slice(&data) {
+                    Ok(message) => Some(InspectorEvent::SocketData { sender_id, message }),
+                    Err(e) => {
+                        warn!("Dropping inspector message that isn't JSON: {}", e);
+                        None
+                    }
+                }
+            }
+            _ => Some(InspectorEvent::Other(selector, argument)),
+        }
+    }
+}
+
+fn socket_argument(socket: &InspectorSocket) -> Plist {
+    let mut argument = Plist::new_dict();
+    let _ = argument.dict_set_item(
+        "WIRApplicationIdentifierKey",
+        Plist::new_string(&socket.app_id),
+    );
+    let _ = argument.dict_set_item("WIRPageIdentifierKey", Plist::new_uint(socket.page_id));
+    let _ = argument.dict_set_item("WIRSenderKey", Plist::new_string(&socket.sender_id));
+    argument
+}
+
+/// Reads a flag the device sends as either a boolean or an integer
+fn flag(dict: &Plist, key: &str) -> bool {
+    match dict.dict_get_item(key) {
+        Ok(item) if item.plist_type == PlistType::Integer => dict_uint(dict, key) != Some(0),
+        _ => dict_bool(dict, key).unwrap_or(false),
+    }
+}
+
+/// Makes a random version 4 UUID
+fn new_uuid() -> String {
+    let mut bytes = [0u8; 16];
+    for half in bytes.chunks_mut(8) {
+        let random = RandomState::new().build_hasher().finish();
+        half.copy_from_slice(&random.to_le_bytes());
+    }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
@@ -1,11 +1,41 @@
 // jkcoxson
 
-use std::ffi::{CStr, CString};
-
-use crate::{bindings as unsafe_bindings, error::InstProxyError, idevice::Device};
+use std::{
+    any::Any,
+    ffi::{c_void, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc,
+};
+
+use crate::{
+    bindings as unsafe_bindings,
+    error::InstProxyError,
+    idevice::Device,
+    plist_helpers::{array_items, dict_bool, dict_entries, dict_item, dict_string},
+};
 
 use log::info;
-use plist_plus::Plist;
+use plist_plus::{Plist, PlistType};
+
+/// The attributes `AppInfo` is built from, for use as return attributes
+pub const APP_INFO_ATTRIBUTES: [&str; 13] = [
+    "CFBundleIdentifier",
+    "CFBundleDisplayName",
+    "CFBundleName",
+    "CFBundleVersion",
+    "CFBundleShortVersionString",
+    "CFBundleExecutable",
+    "ApplicationType",
+    "Path",
+    "Container",
+    "Entitlements",
+    "SignerIdentity",
+    "IsAppClip",
+    "SBAppTags",
+];
+
+/// How many browsed apps are held before the browse waits for the iterator to catch up
+const BROWSE_BUFFER: usize = 64;
 
 /// Manages installing, removing and modifying applications on the device
 pub struct InstProxyClient<'a> {
@@ -524,6 +554,339 @@ impl InstProxyClient<'_> {
         info!("Instproxy get_path_for_bundle_identifier done");
         Ok(unsafe { CStr::from_ptr(path_ptr).to_string_lossy().into_owned() })
     }
+
+    /// Lists installed applications, calling back with each as its page arrives
+    /// # Arguments
+    /// * `options` - The options for the browse
+    /// * `on_app` - Called with every application
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn browse_apps_with_callback(
+        &self,
+        options: &InstallOptions,
+        on_app: impl FnMut(AppInfo),
+    ) -> Result<(), InstProxyError> {
+        browse_apps(self, &options.to_plist(), on_app)
+    }
+
+    /// Lists installed applications lazily.
+    /// The browse runs on a scoped thread and waits whenever the iterator falls behind.
+    /// Whatever the iterator hasn't taken when `with_apps` returns is thrown away.
+    /// # Arguments
+    /// * `options` - The options for the browse
+    /// * `with_apps` - Given an iterator over the apps, ending with an error if the browse failed
+    /// # Returns
+    /// Whatever `with_apps` returns
+    ///
+    /// ***Verified:*** False
+    pub fn browse_apps<R>(
+        &self,
+        options: &InstallOptions,
+        with_apps: impl FnOnce(AppIterator<'_>) -> R,
+    ) -> R {
+        let options = options.to_plist();
+        // The scope joins the browse before returning, however the iterator is dropped
+        std::thread::scope(|scope| {
+            let (sender, receiver) = mpsc::sync_channel(BROWSE_BUFFER);
+            scope.spawn(move || {
+                let mut receiver_gone = false;
+                let result = browse_apps(self, &options, |app| {
+                    // The browse can't be cut short, so the rest of it is thrown away
+                    if !receiver_gone && sender.send(Ok(app)).is_err() {
+                        receiver_gone = true;
+                    }
+                });
+                if let Err(e) = result {
+                    let _ = sender.send(Err(e));
+                }
+            });
+
+            let result = with_apps(AppIterator {
+                receiver: &receiver,
+            });
+            // Unblocks the browse, which then finishes without anyone listening
+            drop(receiver);
+            result
+        })
+    }
+
+    /// Looks up information about apps on the device
+    /// # Arguments
+    /// * `app_ids` - The bundle IDs of the apps to look up. Pass an empty vector for every app
+    /// * `options` - The options for the lookup
+    /// # Returns
+    /// The apps that were found
+    ///
+    /// ***Verified:*** False
+    pub fn lookup_apps(
+        &self,
+        app_ids: Vec<String>,
+        options: &InstallOptions,
+    ) -> Result<Vec<AppInfo>, InstProxyError> {
+        let results = self.lookup(app_ids, Some(options.to_plist()))?;
+        Ok(dict_entries(&results)
+            .iter()
+            .filter_map(|(_, app)| AppInfo::from_plist(app))
+            .collect())
+    }
+}
+
+/// What a browse's trampoline is given, so a panic in the callback can be rethrown after the browse
+struct BrowseContext<'a> {
+    callback: &'a mut dyn FnMut(&Plist),
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+fn browse_apps(
+    client: &InstProxyClient,
+    options: &Plist,
+    mut on_app: impl FnMut(AppInfo),
+) -> Result<(), InstProxyError> {
+    let mut on_status = |status: &Plist| {
+        if let Some(list) = dict_item(status, "CurrentList") {
+            for app in array_items(&list) {
+                if let Some(app) = AppInfo::from_plist(&app) {
+                    on_app(app);
+                }
+            }
+        }
+    };
+    let mut context = BrowseContext {
+        callback: &mut on_status,
+        panic: None,
+    };
+
+    // The browse is synchronous, so the context only has to outlive this call
+    let result = unsafe {
+        unsafe_bindings::instproxy_browse_with_callback(
+            client.pointer,
+            options.get_pointer(),
+            Some(browse_trampoline),
+            &mut context as *mut BrowseContext as *mut c_void,
+        )
+    }
+    .into();
+    if let Some(panic) = context.panic {
+        panic::resume_unwind(panic);
+    }
+    if result != InstProxyError::Success {
+        return Err(result);
+    }
+    Ok(())
+}
+
+unsafe extern "C" fn browse_trampoline(
+    _command: unsafe_bindings::plist_t,
+    status: unsafe_bindings::plist_t,
+    user_data: *mut c_void,
+) {
+    let context = &mut *(user_data as *mut BrowseContext);
+    // The status is freed once the callback returns
+    let status: Plist = status.into();
+    // Unwinding into C is undefined, so the panic is held until the browse returns
+    if context.panic.is_none() {
+        let callback = &mut context.callback;
+        if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| callback(&status))) {
+            context.panic = Some(panic);
+        }
+    }
+    status.false_drop();
+}
+
+/// Installed applications, as a browse sends them
+pub struct AppIterator<'a> {
+    receiver: &'a mpsc::Receiver<Result<AppInfo, InstProxyError>>,
+}
+
+impl Iterator for AppIterator<'_> {
+    type Item = Result<AppInfo, InstProxyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// An installed application
+#[derive(Debug, Clone)]
+pub struct AppInfo {
+    pub bundle_id: String,
+    /// The display name, falling back to the bundle name
+    pub display_name: Option<String>,
+    pub version: Option<String>,
+    pub short_version: Option<String>,
+    pub executable: Option<String>,
+    /// System, User or Internal
+    pub application_type: Option<String>,
+    /// The path of the app bundle on the device
+    pub path: Option<String>,
+    /// The path of the app's data container on the device
+    pub container: Option<String>,
+    pub entitlements: Option<Plist>,
+    /// The certificate the app was signed with
+    pub signer_identity: Option<String>,
+    pub is_app_clip: bool,
+    /// Every attribute the device returned
+    pub attributes: Plist,
+}
+
+impl AppInfo {
+    /// Reads an application's attributes
+    /// # Arguments
+    /// * `attributes` - The dictionary returned by a browse or lookup
+    /// # Returns
+    /// The application, or None if it has no bundle ID
+    pub fn from_plist(attributes: &Plist) -> Option<Self> {
+        Some(AppInfo {
+            bundle_id: dict_string(attributes, "CFBundleIdentifier")?,
+            display_name: dict_string(attributes, "CFBundleDisplayName")
+                .or_else(|| dict_string(attributes, "CFBundleName")),
+            version: dict_string(attributes, "CFBundleVersion"),
+            short_version: dict_string(attributes, "CFBundleShortVersionString"),
+            executable: dict_string(attributes, "CFBundleExecutable"),
+            application_type: dict_string(attributes, "ApplicationType"),
+            path: dict_string(attributes, "Path"),
+            container: dict_string(attributes, "Container"),
+            entitlements: dict_item(attributes, "Entitlements")
+                .filter(|entitlements| entitlements.plist_type == PlistType::Dictionary),
+            signer_identity: dict_string(attributes, "SignerIdentity"),
+            is_app_clip: dict_bool(attributes, "IsAppClip").unwrap_or(false),
+            attributes: attributes.clone(),
+        })
+    }
+}
+
+/// The kinds of applications a browse or lookup covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApplicationType {
+    System,
+    User,
+    Internal,
+    Any,
+}
+
+impl ApplicationType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ApplicationType::System => "System",
+            ApplicationType::User => "User",
+            ApplicationType::Internal => "Internal",
+            ApplicationType::Any => "Any",
+        }
+    }
+}
+
+/// How a package being installed was signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageType {
+    /// Signed with a development certificate
+    Developer,
+    /// Bought from the App Store
+    Customer,
+}
+
+/// Client options for instproxy operations.
+/// Convert into a `Plist` to pass to the methods that take raw options.
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    application_type: Option<ApplicationType>,
+    package_type: Option<PackageType>,
+    itunes_metadata: Option<Vec<u8>>,
+    application_sinf: Option<Vec<u8>>,
+    skip_uninstall: Option<bool>,
+    return_attributes: Option<Vec<String>>,
+}
+
+impl InstallOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the kinds of applications to browse or look up
+    pub fn application_type(mut self, application_type: ApplicationType) -> Self {
+        self.application_type = Some(application_type);
+        self
+    }
+
+    /// Sets how the package being installed was signed
+    pub fn package_type(mut self, package_type: PackageType) -> Self {
+        self.package_type = Some(package_type);
+        self
+    }
+
+    /// Sets the iTunesMetadata.plist of the package being installed
+    pub fn itunes_metadata(mut self, metadata: Vec<u8>) -> Self {
+        self.itunes_metadata = Some(metadata);
+        self
+    }
+
+    /// Sets the SINF of the package being installed
+    pub fn application_sinf(mut self, sinf: Vec<u8>) -> Self {
+        self.application_sinf = Some(sinf);
+        self
+    }
+
+    /// Sets whether archiving an app leaves it installed
+    pub fn skip_uninstall(mut self, skip_uninstall: bool) -> Self {
+        self.skip_uninstall = Some(skip_uninstall);
+        self
+    }
+
+    /// Limits the attributes a browse or lookup returns
+    pub fn return_attributes(
+        mut self,
+        attributes: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.return_attributes = Some(attributes.into_iter().map(|a| a.into()).collect());
+        self
+    }
+
+    /// Limits the attributes a browse or lookup returns to the ones `AppInfo` reads
+    pub fn app_info_attributes(self) -> Self {
+        self.return_attributes(APP_INFO_ATTRIBUTES)
+    }
+
+    /// Builds the options dictionary
+    pub fn to_plist(&self) -> Plist {
+        let mut options = InstProxyClient::client_options_new();
+        if let Some(application_type) = self.application_type {
+            let _ = options.dict_set_item(
+                "ApplicationType",
+                Plist::new_string(application_type.as_str()),
+            );
+        }
+        if let Some(package_type) = self.package_type {
+            let package_type = match package_type {
+                PackageType::Developer => "Developer",
+                PackageType::Customer => "Customer",
+            };
+            let _ = options.dict_set_item("PackageType", Plist::new_string(package_type));
+        }
+        if let Some(metadata) = &self.itunes_metadata {
+            let _ = options.dict_set_item("iTunesMetadata", Plist::new_data(metadata));
+        }
+        if let Some(sinf) = &self.application_sinf {
+            let _ = options.dict_set_item("ApplicationSINF", Plist::new_data(sinf));
+        }
+        if let Some(skip_uninstall) = self.skip_uninstall {
+            let _ = options.dict_set_item("SkipUninstall", Plist::new_bool(skip_uninstall));
+        }
+        if let Some(attributes) = &self.return_attributes {
+            let mut array = Plist::new_array();
+            for attribute in attributes {
+                let _ = array.array_append_item(Plist::new_string(attribute));
+            }
+            let _ = options.dict_set_item("ReturnAttributes", array);
+        }
+        options
+    }
+}
+
+impl From<InstallOptions> for Plist {
+    fn from(options: InstallOptions) -> Self {
+        options.to_plist()
+    }
 }
 
 /// The options that can be used when browsing installed apps
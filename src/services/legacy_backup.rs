@@ -0,0 +1,390 @@
+// jkcoxson
+// Drives the mobilebackup message flow used by devices older than iOS 4
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use plist_plus::{Plist, PlistType};
+
+use crate::{
+    error::MobileBackupError,
+    idevice::Device,
+    plist_helpers::{
+        array_items, data_bytes, dict_bool, dict_entries, dict_item, dict_string, dict_uint,
+        string_array,
+    },
+    services::{
+        backup_engine::info_plist,
+        mobile_backup::{MobileBackupClient, MobileBackupRestoreFlags},
+    },
+};
+
+/// The newest protocol version the legacy service speaks
+const PROTOCOL_VERSION: &str = "1.6";
+
+/// DLFileStatusKey values, marking whether more hunks of a file follow
+const FILE_STATUS_HUNK: u64 = 1;
+const FILE_STATUS_LAST_HUNK: u64 = 2;
+
+/// How many bytes of a file are sent per DLSendFile message
+const HUNK_SIZE: usize = 32 * 1024;
+
+/// A snapshot of how far a legacy backup or restore has come
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LegacyBackupProgress {
+    /// The size of the whole backup, as announced by the device
+    pub total_bytes: Option<u64>,
+    /// Bytes written into the backup directory so far
+    pub bytes_received: u64,
+    /// Files written into the backup directory so far
+    pub files_received: u64,
+    /// Files sent to the device so far
+    pub files_sent: u64,
+    /// The path on the device of the file being transferred
+    pub current_file: Option<String>,
+}
+
+type ProgressCallback<'a> = Box<dyn FnMut(&LegacyBackupProgress) + 'a>;
+
+/// Backs up and restores devices running iOS 3 and older.
+/// Each file is stored as a `<hash>.mddata` file with its metadata in `<hash>.mdinfo`.
+pub struct LegacyBackupEngine<'a> {
+    client: MobileBackupClient<'a>,
+    device: &'a Device,
+    udid: String,
+    root: PathBuf,
+    progress: LegacyBackupProgress,
+    callback: Option<ProgressCallback<'a>>,
+}
+
+impl<'a> LegacyBackupEngine<'a> {
+    /// Starts mobilebackup on the device
+    /// # Arguments
+    /// * `device` - The device to back up
+    /// * `root` - The directory backups are stored in. Each device gets a folder named after its UDID.
+    /// # Returns
+    /// A legacy backup engine ready to run
+    ///
+    /// ***Verified:*** False
+    pub fn new(device: &'a Device, root: impl Into<PathBuf>) -> Result<Self, MobileBackupError> {
+        let client = MobileBackupClient::start_service(device, "legacy_backup")?;
+        Ok(LegacyBackupEngine {
+            client,
+            device,
+            udid: device.get_udid(),
+            root: root.into(),
+            progress: LegacyBackupProgress::default(),
+            callback: None,
+        })
+    }
+
+    /// Sets a function to be called every time the progress changes
+    /// # Arguments
+    /// * `callback` - The function to call
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn set_progress_callback(&mut self, callback: impl FnMut(&LegacyBackupProgress) + 'a) {
+        self.callback = Some(Box::new(callback));
+    }
+
+    /// Gets the directory the device's backup is stored in
+    pub fn backup_dir(&self) -> PathBuf {
+        self.root.join(&self.udid)
+    }
+
+    /// Backs up the device. If a Manifest.plist from an earlier backup exists, only changes are sent.
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The final progress of the backup
+    ///
+    /// ***Verified:*** False
+    pub fn backup(&mut self) -> Result<LegacyBackupProgress, MobileBackupError> {
+        let backup_dir = self.backup_dir();
+        fs::create_dir_all(&backup_dir).map_err(io_error)?;
+        let manifest_path = backup_dir.join("Manifest.plist");
+        let manifest = read_plist(&manifest_path).ok();
+        if manifest.is_none() {
+            info!("No previous backup found, the device will send a full backup");
+        }
+
+        let info = info_plist(self.device, &self.udid).map_err(|e| {
+            warn!("Unable to read lockdownd values: {}", e);
+            MobileBackupError::LockdowndFailed
+        })?;
+        fs::write(backup_dir.join("Info.plist"), info.to_string()).map_err(io_error)?;
+
+        self.progress = LegacyBackupProgress::default();
+        self.client
+            .request_backup(manifest, "/", PROTOCOL_VERSION)?;
+
+        let mut hunk_index = 0;
+        let message = loop {
+            let message = self.receive()?;
+            if message_string(&message, 0).as_deref() != Some("DLSendFile") {
+                break message;
+            }
+            let file_info = message_item(&message, 2)
+                .filter(|file_info| file_info.plist_type == PlistType::Dictionary)
+                .ok_or(MobileBackupError::InvalidResponse)?;
+            let status = dict_uint(&file_info, "DLFileStatusKey").unwrap_or(FILE_STATUS_LAST_HUNK);
+            let is_manifest = dict_bool(&file_info, "BackupManifestKey").unwrap_or(false);
+            let dest =
+                dict_string(&file_info, "DLFileDest").ok_or(MobileBackupError::InvalidResponse)?;
+
+            if hunk_index == 0 {
+                if self.progress.total_bytes.is_none() {
+                    self.progress.total_bytes = dict_uint(&file_info, "BackupTotalSizeKey");
+                }
+                if !is_manifest {
+                    self.progress.current_file = dict_string(&file_info, "DLFileSource");
+                }
+            }
+
+            let data_path = if is_manifest {
+                local_path(&backup_dir, &dest, "")?
+            } else {
+                local_path(&backup_dir, &dest, ".mddata")?
+            };
+            let data = message_item(&message, 1)
+                .and_then(|data| data_bytes(&data))
+                .unwrap_or_default();
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(hunk_index != 0)
+                .truncate(hunk_index == 0)
+                .open(&data_path)
+                .map_err(io_error)?;
+            file.write_all(&data).map_err(io_error)?;
+            self.progress.bytes_received += data.len() as u64;
+            hunk_index += 1;
+
+            if status == FILE_STATUS_LAST_HUNK {
+                if is_manifest {
+                    fs::rename(&data_path, &manifest_path).map_err(io_error)?;
+                } else if let Some(backup_file_info) = dict_item(&file_info, "BackupFileInfo") {
+                    let info_path = local_path(&backup_dir, &dest, ".mdinfo")?;
+                    let info: Vec<u8> = backup_file_info.into();
+                    fs::write(info_path, info).map_err(io_error)?;
+                    self.progress.files_received += 1;
+                }
+                self.client.send_backup_file_received()?;
+                hunk_index = 0;
+            } else if status != FILE_STATUS_HUNK {
+                warn!("Unknown file status {} for {}", status, dest);
+            }
+            self.report();
+        };
+
+        let content = self.process_message(&message)?;
+        match dict_string(&content, "BackupMessageTypeKey").as_deref() {
+            Some("BackupMessageBackupFinished") => {}
+            other => {
+                warn!("Unexpected backup message {:?}", other);
+                return Err(MobileBackupError::InvalidResponse);
+            }
+        }
+
+        if let Some(deleted) = dict_item(&content, "BackupFilesToDeleteKey") {
+            for hash in string_array(&deleted) {
+                for extension in [".mddata", ".mdinfo"] {
+                    let path = local_path(&backup_dir, &hash, extension)?;
+                    if let Err(e) = fs::remove_file(&path) {
+                        if e.kind() != std::io::ErrorKind::NotFound {
+                            return Err(io_error(e));
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(manifest) = dict_item(&content, "BackupManifestKey") {
+            fs::write(&manifest_path, manifest.to_string()).map_err(io_error)?;
+        }
+
+        self.progress.current_file = None;
+        self.report();
+        Ok(self.progress.clone())
+    }
+
+    /// Restores the backup in the backup directory onto the device.
+    /// The device reboots once the restore completes.
+    /// # Arguments
+    /// * `flags` - What the device should keep or show while restoring
+    /// # Returns
+    /// The final progress of the restore
+    ///
+    /// ***Verified:*** False
+    pub fn restore(
+        &mut self,
+        flags: &[MobileBackupRestoreFlags],
+    ) -> Result<LegacyBackupProgress, MobileBackupError> {
+        let backup_dir = self.backup_dir();
+        let manifest = read_plist(&backup_dir.join("Manifest.plist")).map_err(|_| {
+            warn!("No backup found in {}", backup_dir.display());
+            MobileBackupError::BackupNotFound
+        })?;
+        // The list of files is stored as a nested binary plist
+        let data = dict_item(&manifest, "Data")
+            .and_then(|data| data_bytes(&data))
+            .and_then(|data| Plist::from_memory(data).ok())
+            .ok_or(MobileBackupError::PlistError)?;
+        let files = dict_item(&data, "Files")
+            .map(|files| dict_entries(&files))
+            .unwrap_or_default();
+
+        self.client
+            .request_restore_with_flags(manifest, flags, PROTOCOL_VERSION)?;
+
+        self.progress = LegacyBackupProgress::default();
+        for (hash, _) in files {
+            self.send_file(&backup_dir, &hash)?;
+        }
+
+        if let Some(applications) = dict_item(&data, "Applications") {
+            let mut content = Plist::new_dict();
+            let _ = content.dict_set_item("AppInfo", applications);
+            let _ = content.dict_set_item(
+                "BackupMessageTypeKey",
+                Plist::new_string("BackupMessageRestoreApplicationSent"),
+            );
+            let mut message = Plist::new_array();
+            let _ = message.array_append_item(Plist::new_string("DLMessageProcessMessage"));
+            let _ = message.array_append_item(content);
+            self.client.send(message)?;
+            self.client.receive_restore_application_received()?;
+        }
+
+        self.client.send_restore_complete()?;
+        self.progress.current_file = None;
+        self.report();
+        Ok(self.progress.clone())
+    }
+
+    /// Sends a single file and its metadata in hunks
+    fn send_file(&mut self, backup_dir: &Path, hash: &str) -> Result<(), MobileBackupError> {
+        let mdinfo = read_plist(&local_path(backup_dir, hash, ".mdinfo")?)?;
+        let metadata = dict_item(&mdinfo, "Metadata")
+            .and_then(|metadata| data_bytes(&metadata))
+            .and_then(|metadata| Plist::from_memory(metadata).ok())
+            .ok_or(MobileBackupError::PlistError)?;
+        let path = dict_string(&metadata, "Path").ok_or(MobileBackupError::PlistError)?;
+        let data = fs::read(local_path(backup_dir, hash, ".mddata")?).map_err(io_error)?;
+
+        self.progress.current_file = Some(path.clone());
+        self.report();
+
+        // Empty files still need a final hunk
+        let hunks: Vec<&[u8]> = if data.is_empty() {
+            vec![&[]]
+        } else {
+            data.chunks(HUNK_SIZE).collect()
+        };
+        let count = hunks.len();
+        let mut offset = 0;
+        for (index, hunk) in hunks.into_iter().enumerate() {
+            let status = if index + 1 == count {
+                FILE_STATUS_LAST_HUNK
+            } else {
+                FILE_STATUS_HUNK
+            };
+            let mut file_info = Plist::new_dict();
+            let _ = file_info.dict_set_item("Path", Plist::new_string(&path));
+            for key in ["Version", "Domain"] {
+                if let Some(value) = dict_string(&metadata, key) {
+                    let _ = file_info.dict_set_item(key, Plist::new_string(&value));
+                }
+            }
+            let _ = file_info.dict_set_item(
+                "Greylist",
+                Plist::new_bool(dict_bool(&metadata, "Greylist").unwrap_or(false)),
+            );
+            let _ = file_info.dict_set_item("DLFileOffsetKey", Plist::new_uint(offset));
+            let _ = file_info.dict_set_item("DLFileStatusKey", Plist::new_uint(status));
+
+            let mut message = Plist::new_array();
+            let _ = message.array_append_item(Plist::new_string("DLSendFile"));
+            let _ = message.array_append_item(Plist::new_data(hunk));
+            let _ = message.array_append_item(file_info);
+            self.client.send(message)?;
+            offset += hunk.len() as u64;
+        }
+
+        self.client.receive_restore_file_received()?;
+        self.progress.files_sent += 1;
+        self.report();
+        Ok(())
+    }
+
+    fn receive(&self) -> Result<Plist, MobileBackupError> {
+        self.client.receive().map_err(|e| {
+            warn!("Lost connection to mobilebackup: {}", e);
+            MobileBackupError::Disconnected
+        })
+    }
+
+    /// Unpacks a DLMessageProcessMessage, turning device side errors into an error
+    fn process_message(&self, message: &Plist) -> Result<Plist, MobileBackupError> {
+        if message_string(message, 0).as_deref() != Some("DLMessageProcessMessage") {
+            warn!("Unexpected message {:?}", message_string(message, 0));
+            return Err(MobileBackupError::InvalidResponse);
+        }
+        let content = message_item(message, 1)
+            .filter(|content| content.plist_type == PlistType::Dictionary)
+            .ok_or(MobileBackupError::InvalidResponse)?;
+        if dict_string(&content, "BackupMessageTypeKey").as_deref() == Some("BackupMessageError") {
+            warn!(
+                "Device reported error: {}",
+                dict_string(&content, "BackupErrorReasonKey").unwrap_or_default()
+            );
+            return Err(MobileBackupError::DeviceError);
+        }
+        Ok(content)
+    }
+
+    fn report(&mut self) {
+        if let Some(callback) = self.callback.as_mut() {
+            callback(&self.progress);
+        }
+    }
+}
+
+fn message_item(message: &Plist, index: usize) -> Option<Plist> {
+    array_items(message).into_iter().nth(index)
+}
+
+fn message_string(message: &Plist, index: usize) -> Option<String> {
+    message_item(message, index)
+        .filter(|item| item.plist_type == PlistType::String)
+        .and_then(|item| item.get_string_val().ok())
+}
+
+/// Builds the path of a backup file from the name the device uses for it.
+/// Names containing path separators are refused so the device can't write outside the backup.
+fn local_path(
+    backup_dir: &Path,
+    name: &str,
+    extension: &str,
+) -> Result<PathBuf, MobileBackupError> {
+    if name.is_empty() || name.contains('/') || name.contains('\\') || name == ".." {
+        warn!("Refusing to touch {} outside of the backup directory", name);
+        return Err(MobileBackupError::InvalidArg);
+    }
+    Ok(backup_dir.join(format!("{}{}", name, extension)))
+}
+
+fn read_plist(path: &Path) -> Result<Plist, MobileBackupError> {
+    let data = fs::read(path).map_err(io_error)?;
+    Plist::from_memory(data).map_err(|_| MobileBackupError::PlistError)
+}
+
+fn io_error(error: std::io::Error) -> MobileBackupError {
+    warn!("Backup directory access failed: {}", error);
+    MobileBackupError::IoError
+}
@@ -1,12 +1,16 @@
 // jkcoxson
 
 use crate::{
-    bindings as unsafe_bindings, error::MisagentError, idevice::Device,
+    bindings as unsafe_bindings,
+    error::MisagentError,
+    idevice::Device,
+    plist_helpers::{array_items, data_bytes, dict_bool, dict_date, dict_item, dict_string},
     services::lockdownd::LockdowndService,
 };
-use std::{ffi::CString, os::raw::c_char};
+use std::{ffi::CString, path::Path, time::SystemTime};
 
-use plist_plus::Plist;
+use log::warn;
+use plist_plus::{Plist, PlistType};
 
 /// Manges and checks provisioning profiles
 pub struct MisagentClient<'a> {
@@ -49,11 +53,12 @@ impl MisagentClient<'_> {
     /// ***Verified:*** False
     pub fn start_service(device: &Device, label: impl Into<String>) -> Result<Self, MisagentError> {
         let mut pointer = unsafe { std::mem::zeroed() };
+        let label_c_string = CString::new(label.into()).unwrap();
         let result = unsafe {
             unsafe_bindings::misagent_client_start_service(
                 device.pointer,
                 &mut pointer,
-                label.into().as_ptr() as *const c_char,
+                label_c_string.as_ptr(),
             )
         }
         .into();
@@ -106,9 +111,83 @@ impl MisagentClient<'_> {
         Ok(plist.into())
     }
 
+    /// Installs a parsed provisioning profile on the device
+    /// # Arguments
+    /// * `profile` - The profile to install
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn install_profile(&self, profile: &ProvisioningProfile) -> Result<(), MisagentError> {
+        self.install(profile.to_plist())
+    }
+
+    /// Installs a .mobileprovision file on the device
+    /// # Arguments
+    /// * `path` - The path to the profile on the host
+    /// # Returns
+    /// The profile that was installed
+    ///
+    /// ***Verified:*** False
+    pub fn install_from_file(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<ProvisioningProfile, MisagentError> {
+        let profile = ProvisioningProfile::from_file(path)?;
+        self.install_profile(&profile)?;
+        Ok(profile)
+    }
+
+    /// Lists the provisioning profiles installed on the device
+    /// # Arguments
+    /// * `low_version` - Whether the device verion is lower than iOS 9.3
+    /// # Returns
+    /// The parsed profiles. Profiles that can't be parsed are skipped.
+    ///
+    /// ***Verified:*** False
+    pub fn list_profiles(
+        &self,
+        low_version: bool,
+    ) -> Result<Vec<ProvisioningProfile>, MisagentError> {
+        let profiles = self.copy(low_version)?;
+        let mut parsed = Vec::new();
+        for item in array_items(&profiles) {
+            let data = match data_bytes(&item) {
+                Some(data) => data,
+                None => continue,
+            };
+            match ProvisioningProfile::from_bytes(data) {
+                Ok(profile) => parsed.push(profile),
+                Err(e) => warn!("Skipping unreadable provisioning profile: {}", e),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Removes every expired provisioning profile from the device
+    /// # Arguments
+    /// * `low_version` - Whether the device verion is lower than iOS 9.3
+    /// # Returns
+    /// The profiles that were removed
+    ///
+    /// ***Verified:*** False
+    pub fn remove_expired(
+        &self,
+        low_version: bool,
+    ) -> Result<Vec<ProvisioningProfile>, MisagentError> {
+        let mut removed = Vec::new();
+        for profile in self.list_profiles(low_version)? {
+            if profile.is_expired() {
+                self.remove(profile.uuid.as_str())?;
+                removed.push(profile);
+            }
+        }
+        Ok(removed)
+    }
+
     /// Removes a provisioning profile from the device
     /// # Arguments
-    /// * `id` - The ID of the provisioning profile
+    /// * `id` - The UUID of the provisioning profile
     /// # Returns
     /// *none*
     ///
@@ -139,6 +218,136 @@ impl MisagentClient<'_> {
     }
 }
 
+/// A provisioning profile, parsed from a .mobileprovision file
+#[derive(Debug, Clone)]
+pub struct ProvisioningProfile {
+    pub uuid: String,
+    pub name: String,
+    pub team_id: Option<String>,
+    pub team_name: Option<String>,
+    /// The application identifier, including the team prefix
+    pub app_id: Option<String>,
+    pub app_id_name: Option<String>,
+    pub creation_date: Option<SystemTime>,
+    pub expiration_date: Option<SystemTime>,
+    pub entitlements: Option<Plist>,
+    /// The UDIDs the profile is valid for. Empty for enterprise profiles.
+    pub provisioned_devices: Vec<String>,
+    pub provisions_all_devices: bool,
+    /// The raw CMS signed profile
+    pub data: Vec<u8>,
+}
+
+impl ProvisioningProfile {
+    /// Parses a CMS signed provisioning profile.
+    /// The signature isn't verified, the embedded plist is read as is.
+    /// # Arguments
+    /// * `data` - The contents of a .mobileprovision file
+    /// # Returns
+    /// The parsed profile
+    ///
+    /// ***Verified:*** False
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, MisagentError> {
+        let start = find(&data, b"<?xml");
+        let end = find(&data, b"</plist>");
+        let xml = match (start, end) {
+            (Some(start), Some(end)) if start < end => &data[start..end + b"</plist>".len()],
+            _ => {
+                warn!("Provisioning profile doesn't contain a plist");
+                return Err(MisagentError::InvalidProfile);
+            }
+        };
+        let xml = String::from_utf8_lossy(xml).into_owned();
+        let plist = match Plist::from_xml(xml) {
+            Ok(plist) if plist.plist_type == PlistType::Dictionary => plist,
+            _ => {
+                warn!("Unable to parse the plist inside the provisioning profile");
+                return Err(MisagentError::InvalidProfile);
+            }
+        };
+
+        let uuid = match dict_string(&plist, "UUID") {
+            Some(uuid) => uuid,
+            None => {
+                warn!("Provisioning profile has no UUID");
+                return Err(MisagentError::InvalidProfile);
+            }
+        };
+        let entitlements = dict_item(&plist, "Entitlements");
+        let app_id = entitlements
+            .as_ref()
+            .and_then(|e| dict_string(e, "application-identifier"));
+        let team_id = dict_item(&plist, "TeamIdentifier")
+            .and_then(|teams| array_items(&teams).into_iter().next())
+            .and_then(|team| team.get_string_val().ok());
+        let provisioned_devices = dict_item(&plist, "ProvisionedDevices")
+            .map(|devices| {
+                array_items(&devices)
+                    .iter()
+                    .filter_map(|udid| udid.get_string_val().ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(ProvisioningProfile {
+            uuid,
+            name: dict_string(&plist, "Name").unwrap_or_default(),
+            team_id,
+            team_name: dict_string(&plist, "TeamName"),
+            app_id,
+            app_id_name: dict_string(&plist, "AppIDName"),
+            creation_date: dict_date(&plist, "CreationDate"),
+            expiration_date: dict_date(&plist, "ExpirationDate"),
+            entitlements,
+            provisioned_devices,
+            provisions_all_devices: dict_bool(&plist, "ProvisionsAllDevices").unwrap_or(false),
+            data,
+        })
+    }
+
+    /// Reads and parses a .mobileprovision file
+    /// # Arguments
+    /// * `path` - The path to the file
+    /// # Returns
+    /// The parsed profile
+    ///
+    /// ***Verified:*** False
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, MisagentError> {
+        let path = path.as_ref();
+        match std::fs::read(path) {
+            Ok(data) => Self::from_bytes(data),
+            Err(e) => {
+                warn!("Unable to read {}: {}", path.display(), e);
+                Err(MisagentError::IoError)
+            }
+        }
+    }
+
+    /// Whether the profile has passed its expiration date
+    pub fn is_expired(&self) -> bool {
+        match self.expiration_date {
+            Some(expiration_date) => expiration_date <= SystemTime::now(),
+            None => false,
+        }
+    }
+
+    /// Whether the profile can be used on a device
+    pub fn includes_device(&self, udid: &str) -> bool {
+        self.provisions_all_devices || self.provisioned_devices.iter().any(|d| d == udid)
+    }
+
+    /// The profile as a data plist, as expected by `install`
+    pub fn to_plist(&self) -> Plist {
+        Plist::new_data(&self.data)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
 impl Drop for MisagentClient<'_> {
     fn drop(&mut self) {
         unsafe {
@@ -128,24 +128,62 @@ impl MobileActivationClient<'_> {
         Ok(plist.into())
     }
 
-    /// Gets the activation info from Apple's servers
+    /// Gets the activation info from Apple's servers.
+    /// libimobiledevice needs the drmHandshake response to build the activation info,
+    /// which this can't pass, so it always fails
     /// # Arguments
     /// *none*
     /// # Returns
-    /// Both the handshake reponse and the activation info
+    /// Always `InvalidArg`
     ///
     /// ***Verified:*** False
+    #[deprecated(note = "use create_activation_info_with_handshake")]
     pub fn create_activation_info_with_session(
         &self,
     ) -> Result<(Plist, Plist), MobileActivationError> {
-        let plist = unsafe { std::mem::zeroed() };
-        let mut session_plist = unsafe { std::mem::zeroed() };
+        Err(MobileActivationError::InvalidArg)
+    }
+
+    /// Gets the activation info for devices that don't use activation sessions
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// A plist with the activation info to send to Apple's servers
+    ///
+    /// ***Verified:*** False
+    pub fn create_activation_info(&self) -> Result<Plist, MobileActivationError> {
+        let mut plist = unsafe { std::mem::zeroed() };
+
+        let result = unsafe {
+            unsafe_bindings::mobileactivation_create_activation_info(self.pointer, &mut plist)
+        }
+        .into();
+
+        if result != MobileActivationError::Success {
+            return Err(result);
+        }
+
+        Ok(plist.into())
+    }
+
+    /// Gets the activation info for a session, using the handshake response from Apple's servers
+    /// # Arguments
+    /// * `handshake_response` - The response to the session info from the drmHandshake endpoint
+    /// # Returns
+    /// A plist with the activation info to send to Apple's servers
+    ///
+    /// ***Verified:*** False
+    pub fn create_activation_info_with_handshake(
+        &self,
+        handshake_response: &Plist,
+    ) -> Result<Plist, MobileActivationError> {
+        let mut plist = unsafe { std::mem::zeroed() };
 
         let result = unsafe {
             unsafe_bindings::mobileactivation_create_activation_info_with_session(
                 self.pointer,
-                plist,
-                &mut session_plist,
+                handshake_response.get_pointer(),
+                &mut plist,
             )
         }
         .into();
@@ -154,7 +192,7 @@ impl MobileActivationClient<'_> {
             return Err(result);
         }
 
-        Ok((plist.into(), session_plist.into()))
+        Ok(plist.into())
     }
 
     /// Activates a device
@@ -190,14 +190,36 @@ impl MobileBackupClient<'_> {
         manifest: Plist,
         flags: MobileBackupRestoreFlags,
         backup_version: impl Into<String>,
+    ) -> Result<(), MobileBackupError> {
+        self.request_restore_with_flags(manifest, &[flags], backup_version)
+    }
+
+    /// Request the device restore a backup with several restore flags
+    /// # Arguments
+    /// * `manifest` - The backup manifest containing the backup version
+    /// * `flags` - The flags to choose for restoring, combined into one request
+    /// * `backup_version` - The backup version to use. The latest known version is 1.6.
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn request_restore_with_flags(
+        &self,
+        manifest: Plist,
+        flags: &[MobileBackupRestoreFlags],
+        backup_version: impl Into<String>,
     ) -> Result<(), MobileBackupError> {
         let backup_version_c_string = CString::new(backup_version.into()).unwrap();
+        let flags = flags
+            .iter()
+            .map(|flag| c_uint::from(*flag))
+            .fold(0, |a, b| a | b);
 
         let result = unsafe {
             unsafe_bindings::mobilebackup_request_restore(
                 self.pointer,
                 manifest.get_pointer(),
-                flags.into(),
+                flags,
                 backup_version_c_string.as_ptr(),
             )
         }
@@ -416,12 +438,17 @@ impl MobileBackup2Client<'_> {
             return Err(result);
         }
 
-        Ok((
-            unsafe { std::ffi::CStr::from_ptr(message) }
+        let message_string = if message.is_null() {
+            String::new()
+        } else {
+            let message_string = unsafe { std::ffi::CStr::from_ptr(message) }
                 .to_string_lossy()
-                .into_owned(),
-            options.into(),
-        ))
+                .into_owned();
+            unsafe { libc::free(message as *mut std::ffi::c_void) };
+            message_string
+        };
+
+        Ok((message_string, options.into()))
     }
 
     /// Sends raw data through the service connection
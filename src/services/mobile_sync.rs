@@ -2,7 +2,7 @@
 
 use std::{
     ffi::{c_uint, CString},
-    os::raw::c_char,
+    os::raw::{c_char, c_int},
 };
 
 use crate::{
@@ -24,7 +24,7 @@ pub struct MobileSyncAnchor {
     computer_anchor: CString,
 }
 
-impl MobileSyncClient<'_> {
+impl<'a> MobileSyncClient<'a> {
     /// Creates a new mobile sync service from a lockdown service
     /// # Arguments
     /// * `device` - The device to connect to
@@ -33,7 +33,7 @@ impl MobileSyncClient<'_> {
     /// A struct containing the handle to the connection
     ///
     /// ***Verified:*** False
-    pub fn new(device: Device, descriptor: LockdowndService) -> Result<Self, MobileSyncError> {
+    pub fn new(device: &'a Device, descriptor: LockdowndService) -> Result<Self, MobileSyncError> {
         let mut pointer: unsafe_bindings::mobilesync_client_t = std::ptr::null_mut();
         let result = unsafe {
             unsafe_bindings::mobilesync_client_new(device.pointer, descriptor.pointer, &mut pointer)
@@ -59,7 +59,7 @@ impl MobileSyncClient<'_> {
     ///
     /// ***Verified:*** False
     pub fn start_service(
-        device: Device,
+        device: &'a Device,
         label: impl Into<String>,
     ) -> Result<Self, MobileSyncError> {
         let label_c_string = CString::new(label.into()).unwrap();
@@ -134,16 +134,38 @@ impl MobileSyncClient<'_> {
     pub fn start(
         &self,
         data_class: impl Into<String>,
-        mut anchors: Vec<MobileSyncAnchor>,
+        anchors: Vec<MobileSyncAnchor>,
         computer_data_class_version: u64,
         sync_type: MobileSyncType,
     ) -> Result<(), (String, MobileSyncError)> {
+        self.start_sync(data_class, anchors, computer_data_class_version, sync_type)
+            .map(|_| ())
+    }
+
+    /// Starts the syncing of data, reporting what the device agreed to
+    /// # Arguments
+    /// * `data_class` - The identifiers to sync
+    /// * `anchors` - The sync anchors to base off of
+    /// * `computer_data_class_version` - The class version on the host
+    /// * `sync_type` - The type of sync to request
+    /// # Returns
+    /// The sync type the device chose and the device's class version
+    ///
+    /// ***Verified:*** False
+    pub fn start_sync(
+        &self,
+        data_class: impl Into<String>,
+        mut anchors: Vec<MobileSyncAnchor>,
+        computer_data_class_version: u64,
+        sync_type: MobileSyncType,
+    ) -> Result<(MobileSyncType, u64), (String, MobileSyncError)> {
         let data_class_c_string = CString::new(data_class.into()).unwrap();
 
         let mut anchor_ptrs: Vec<*mut unsafe_bindings::mobilesync_anchors> =
             anchors.iter_mut().map(|v| v.as_c_struct_ptr()).collect();
         anchor_ptrs.push(std::ptr::null_mut());
 
+        let mut sync_type: c_uint = sync_type.into();
         let mut device_data_class_version = 0;
 
         let mut error_description = std::ptr::null_mut();
@@ -154,7 +176,7 @@ impl MobileSyncClient<'_> {
                 data_class_c_string.as_ptr(),
                 anchor_ptrs[0],
                 computer_data_class_version,
-                &mut sync_type.into(),
+                &mut sync_type,
                 &mut device_data_class_version,
                 &mut error_description,
             )
@@ -162,15 +184,19 @@ impl MobileSyncClient<'_> {
         .into();
 
         if result != MobileSyncError::Success {
-            return Err((
-                unsafe { std::ffi::CStr::from_ptr(error_description) }
+            let description = if error_description.is_null() {
+                String::new()
+            } else {
+                let description = unsafe { std::ffi::CStr::from_ptr(error_description) }
                     .to_string_lossy()
-                    .into_owned(),
-                result,
-            ));
+                    .into_owned();
+                unsafe { libc::free(error_description as *mut std::ffi::c_void) };
+                description
+            };
+            return Err((description, result));
         }
 
-        Ok(())
+        Ok((sync_type.into(), device_data_class_version))
     }
 
     /// Cancels a sync request
@@ -389,6 +415,101 @@ impl MobileSyncClient<'_> {
 
         Ok(())
     }
+
+    /// Receives the identifiers the device assigned to records sent with `send_changes`
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// A dictionary mapping the host's identifiers to the device's, if any were remapped
+    ///
+    /// ***Verified:*** False
+    pub fn receive_remapped_identifiers(&self) -> Result<Option<Plist>, MobileSyncError> {
+        let mut mapping: unsafe_bindings::plist_t = std::ptr::null_mut();
+        let result =
+            unsafe { unsafe_bindings::mobilesync_remap_identifiers(self.pointer, &mut mapping) }
+                .into();
+
+        if result != MobileSyncError::Success {
+            return Err(result);
+        }
+
+        if mapping.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(mapping.into()))
+    }
+}
+
+/// Additional actions sent along with changes from the host
+pub struct MobileSyncActions {
+    plist: Plist,
+}
+
+impl MobileSyncActions {
+    /// Creates an empty set of actions
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The actions
+    ///
+    /// ***Verified:*** False
+    pub fn new() -> Self {
+        MobileSyncActions {
+            plist: unsafe { unsafe_bindings::mobilesync_actions_new() }.into(),
+        }
+    }
+
+    /// Sets the entity names the host wants records of
+    /// # Arguments
+    /// * `names` - The entity names, such as `com.apple.contacts.Contact`
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn set_entity_names(&mut self, names: &[&str]) {
+        let names: Vec<CString> = names.iter().map(|n| CString::new(*n).unwrap()).collect();
+        let name_ptrs: Vec<*const c_char> = names.iter().map(|n| n.as_ptr()).collect();
+        let key = CString::new("SyncDeviceLinkEntityNamesKey").unwrap();
+        unsafe {
+            unsafe_bindings::mobilesync_actions_add(
+                self.plist.get_pointer(),
+                key.as_ptr(),
+                name_ptrs.as_ptr(),
+                name_ptrs.len() as c_int,
+                std::ptr::null::<c_char>(),
+            )
+        };
+    }
+
+    /// Sets whether all records of the pulled entity types were sent
+    /// # Arguments
+    /// * `sent` - True if the host sent every record
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn set_all_records_of_pulled_entity_type_sent(&mut self, sent: bool) {
+        let key = CString::new("SyncDeviceLinkAllRecordsOfPulledEntityTypeSentKey").unwrap();
+        unsafe {
+            unsafe_bindings::mobilesync_actions_add(
+                self.plist.get_pointer(),
+                key.as_ptr(),
+                sent as c_int,
+                std::ptr::null::<c_char>(),
+            )
+        };
+    }
+
+    /// Gets the actions as a plist to pass to `send_changes`
+    pub fn into_plist(self) -> Plist {
+        self.plist
+    }
+}
+
+impl Default for MobileSyncActions {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MobileSyncAnchor {
@@ -436,6 +557,16 @@ impl From<MobileSyncType> for c_uint {
     }
 }
 
+impl From<c_uint> for MobileSyncType {
+    fn from(value: c_uint) -> Self {
+        match value {
+            0 => MobileSyncType::Fast,
+            2 => MobileSyncType::Reset,
+            _ => MobileSyncType::Slow,
+        }
+    }
+}
+
 impl Drop for MobileSyncClient<'_> {
     fn drop(&mut self) {
         unsafe {
@@ -1,14 +1,74 @@
 // Prepare to be boarded
 
-use std::ffi::CString;
+use std::{
+    ffi::CString,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    bindings as unsafe_bindings, error::PreboardError, idevice::Device,
+    bindings as unsafe_bindings,
+    error::PreboardError,
+    idevice::Device,
+    plist_helpers::{dict_bool, dict_string, dict_uint},
     services::lockdownd::LockdowndService,
 };
 
+use log::{info, warn};
 use plist_plus::Plist;
 
+/// How long a single receive waits before the deadline is checked again
+const RECEIVE_SLICE_MS: u32 = 1000;
+
+/// A progress message sent by preboard while a stashbag is created or committed
+#[derive(Debug, Clone)]
+pub enum PreboardStatus {
+    /// The device is showing the passcode dialog
+    ShowDialog,
+    /// The passcode dialog was dismissed, by entering the passcode or after a timeout or error
+    HideDialog,
+    /// The user didn't enter a passcode within two minutes
+    Timeout,
+    /// The user aborted the passcode entry, or the commit failed
+    Error {
+        code: u64,
+        description: Option<String>,
+    },
+    /// The stashbag was committed
+    CommitComplete,
+    /// A message this crate doesn't recognize
+    Other(Plist),
+}
+
+impl PreboardStatus {
+    /// Parses a message received from the preboard service
+    /// # Arguments
+    /// * `message` - The dictionary sent by the device
+    /// # Returns
+    /// The status it reports
+    pub fn from_plist(message: &Plist) -> Self {
+        // Failed commits also carry StashbagCommitComplete, so errors are checked first
+        if let Some(code) = dict_uint(message, "Error") {
+            return PreboardStatus::Error {
+                code,
+                description: dict_string(message, "ErrorString"),
+            };
+        }
+        if dict_bool(message, "ShowDialog") == Some(true) {
+            PreboardStatus::ShowDialog
+        } else if dict_bool(message, "HideDialog") == Some(true) {
+            PreboardStatus::HideDialog
+        } else if dict_bool(message, "Timeout") == Some(true) {
+            PreboardStatus::Timeout
+        } else if dict_bool(message, "StashbagCommitComplete") == Some(true)
+            || dict_uint(message, "StashbagCommitComplete").is_some_and(|c| c != 0)
+        {
+            PreboardStatus::CommitComplete
+        } else {
+            PreboardStatus::Other(message.clone())
+        }
+    }
+}
+
 /// A service that manages data at the first unlock screen after boot.
 /// Prepare to be boarded!
 pub struct PreboardClient<'a> {
@@ -167,6 +227,135 @@ impl PreboardClient<'_> {
 
         Ok(())
     }
+
+    /// Waits for the next progress message from the device
+    /// # Arguments
+    /// * `timeout` - How long to wait for the message
+    /// # Returns
+    /// The parsed status
+    ///
+    /// ***Verified:*** False
+    pub fn receive_status(&self, timeout: Duration) -> Result<PreboardStatus, PreboardError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(PreboardError::Timeout);
+            }
+            let slice = (remaining.as_millis() as u32).clamp(1, RECEIVE_SLICE_MS);
+            match self.receive(slice) {
+                Ok(message) => return Ok(PreboardStatus::from_plist(&message)),
+                Err(PreboardError::Timeout) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Creates a stashbag and waits for the user to enter their passcode on the device
+    /// # Arguments
+    /// * `manifest` - The options to use while creating the stashbag
+    /// * `timeout` - How long to wait for the passcode dialog to be dismissed
+    /// * `on_status` - Called with every progress message the device sends
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn create_stashbag_with_status(
+        &self,
+        manifest: Option<&Plist>,
+        timeout: Duration,
+        mut on_status: impl FnMut(PreboardStatus),
+    ) -> Result<(), PreboardError> {
+        self.send(stashbag_command("CreateStashbag", manifest))?;
+
+        let deadline = Instant::now() + timeout;
+        // The device reports timeouts and aborts first, then hides the dialog
+        let mut failure = None;
+        loop {
+            let status = self.receive_status(deadline.saturating_duration_since(Instant::now()))?;
+            match &status {
+                PreboardStatus::HideDialog => {
+                    on_status(status);
+                    return match failure {
+                        Some(e) => Err(e),
+                        None => Ok(()),
+                    };
+                }
+                PreboardStatus::Timeout => failure = Some(PreboardError::DialogTimedOut),
+                PreboardStatus::Error { code, description } => {
+                    warn!("Stashbag creation failed with {}: {:?}", code, description);
+                    failure = Some(PreboardError::StashbagFailed);
+                }
+                _ => {}
+            }
+            on_status(status);
+        }
+    }
+
+    /// Commits a created stashbag and waits for the device to confirm it
+    /// # Arguments
+    /// * `manifest` - The manifest used for creating the stashbag
+    /// * `timeout` - How long to wait for the confirmation
+    /// * `on_status` - Called with every progress message the device sends
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn commit_stashbag_with_status(
+        &self,
+        manifest: Option<&Plist>,
+        timeout: Duration,
+        mut on_status: impl FnMut(PreboardStatus),
+    ) -> Result<(), PreboardError> {
+        self.send(stashbag_command("CommitStashbag", manifest))?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let status = self.receive_status(deadline.saturating_duration_since(Instant::now()))?;
+            let result = match &status {
+                PreboardStatus::CommitComplete => Some(Ok(())),
+                PreboardStatus::Error { code, description } => {
+                    warn!("Stashbag commit failed with {}: {:?}", code, description);
+                    Some(Err(PreboardError::StashbagFailed))
+                }
+                _ => None,
+            };
+            on_status(status);
+            if let Some(result) = result {
+                return result;
+            }
+        }
+    }
+
+    /// Creates a stashbag, waits for the passcode to be entered, then commits it.
+    /// Progress can be forwarded to another thread by sending it down a channel from `on_status`.
+    /// # Arguments
+    /// * `manifest` - The options to use for the stashbag
+    /// * `timeout` - How long to wait for each step. Devices drop the passcode dialog after 2 min
+    /// * `on_status` - Called with every progress message the device sends
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn create_and_commit_stashbag(
+        &self,
+        manifest: Option<Plist>,
+        timeout: Duration,
+        mut on_status: impl FnMut(PreboardStatus),
+    ) -> Result<(), PreboardError> {
+        self.create_stashbag_with_status(manifest.as_ref(), timeout, &mut on_status)?;
+        info!("Stashbag created, committing it");
+        self.commit_stashbag_with_status(manifest.as_ref(), timeout, &mut on_status)
+    }
+}
+
+fn stashbag_command(command: &str, manifest: Option<&Plist>) -> Plist {
+    let mut request = Plist::new_dict();
+    let _ = request.dict_set_item("Command", Plist::new_string(command));
+    if let Some(manifest) = manifest {
+        let _ = request.dict_set_item("Manifest", manifest.clone());
+    }
+    request
 }
 
 impl Drop for PreboardClient<'_> {
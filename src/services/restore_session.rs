@@ -0,0 +1,643 @@
+// jkcoxson
+// Drives the message loop of a restore, answering restored's data requests and reporting progress
+
+use log::{info, warn};
+use plist_plus::{Plist, PlistType};
+
+use crate::{
+    error::RestoredError,
+    plist_helpers::{dict_item, dict_string, dict_uint},
+    services::restored::RestoredClient,
+};
+
+/// The status restored reports when the restore finished without errors
+const STATUS_SUCCESS: u64 = 0;
+/// The status restored reports when the restored image failed verification
+const STATUS_VERIFICATION_ERROR: u64 = 0xFFFFFFFFFFFFFFFF;
+
+/// Carries messages to and from restored.
+/// `RestoredClient` implements this; a fake endpoint can implement it to replay a restore.
+pub trait RestoredTransport {
+    /// Sends a message to restored
+    fn send(&self, message: Plist) -> Result<(), RestoredError>;
+    /// Receives the next message from restored
+    fn receive(&self) -> Result<Plist, RestoredError>;
+}
+
+impl RestoredTransport for RestoredClient<'_> {
+    fn send(&self, message: Plist) -> Result<(), RestoredError> {
+        RestoredClient::send(self, message)
+    }
+
+    fn receive(&self) -> Result<Plist, RestoredError> {
+        RestoredClient::receive(self)
+    }
+}
+
+/// The images sent in answer to a NORData request
+#[derive(Debug, Clone, Default)]
+pub struct NorData {
+    /// The personalized LLB
+    pub llb_image: Vec<u8>,
+    /// The remaining firmware images, in flashing order, by component name
+    pub nor_images: Vec<(String, Vec<u8>)>,
+    /// The personalized RestoreSEP image, if the device has a SEP
+    pub sep_image: Option<Vec<u8>>,
+}
+
+/// Supplies the files restored asks for during a restore.
+/// Every request defaults to `UnsupportedDataRequest`,
+/// so only the ones a restore needs have to be implemented.
+pub trait RestoreDataProvider {
+    /// Answers a SystemImageData request.
+    /// restored doesn't take the image as a reply. It connects to the host's ASR server on
+    /// port 12345, so this should stream the root filesystem over ASR and return once it's done.
+    /// # Arguments
+    /// * `request` - The request, for its arguments
+    fn system_image(&mut self, request: &Plist) -> Result<(), RestoredError> {
+        let _ = request;
+        Err(RestoredError::UnsupportedDataRequest)
+    }
+
+    /// Answers a RootTicket request
+    /// # Returns
+    /// The APTicket or IM4M for the restore
+    fn root_ticket(&mut self) -> Result<Vec<u8>, RestoredError> {
+        Err(RestoredError::UnsupportedDataRequest)
+    }
+
+    /// Answers a KernelCache request
+    /// # Returns
+    /// The personalized kernel cache
+    fn kernel_cache(&mut self) -> Result<Vec<u8>, RestoredError> {
+        Err(RestoredError::UnsupportedDataRequest)
+    }
+
+    /// Answers a NORData request
+    /// # Returns
+    /// The images to flash
+    fn nor_data(&mut self) -> Result<NorData, RestoredError> {
+        Err(RestoredError::UnsupportedDataRequest)
+    }
+
+    /// Answers a FUDData request for the list of FUD firmware images
+    /// # Returns
+    /// The names of the images the restore should update
+    fn fud_image_list(&mut self) -> Result<Vec<String>, RestoredError> {
+        Err(RestoredError::UnsupportedDataRequest)
+    }
+
+    /// Answers a FUDData request for a single image
+    /// # Arguments
+    /// * `name` - The component name of the image
+    /// # Returns
+    /// The personalized image
+    fn fud_image(&mut self, name: &str) -> Result<Vec<u8>, RestoredError> {
+        let _ = name;
+        Err(RestoredError::UnsupportedDataRequest)
+    }
+
+    /// Answers any other data request
+    /// # Arguments
+    /// * `data_type` - The DataType of the request
+    /// * `request` - The whole request
+    /// # Returns
+    /// The reply to send, or None to skip the request
+    fn other_data(
+        &mut self,
+        data_type: &str,
+        request: &Plist,
+    ) -> Result<Option<Plist>, RestoredError> {
+        let _ = request;
+        warn!("Skipping unhandled data request {}", data_type);
+        Ok(None)
+    }
+}
+
+/// A progress report from restored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RestoreProgress {
+    /// The code of the current operation
+    pub operation: u64,
+    /// The percentage of the operation that is done, if restored reported one
+    pub progress: Option<u8>,
+}
+
+impl RestoreProgress {
+    /// Gets the name idevicerestore uses for the operation
+    pub fn operation_name(&self) -> Option<&'static str> {
+        Some(match self.operation {
+            11 => "Creating partition map",
+            12 => "Creating filesystem",
+            13 => "Restoring image",
+            14 => "Verifying restore",
+            15 => "Checking filesystems",
+            16 => "Mounting filesystems",
+            17 => "Fixing up /var",
+            18 => "Flashing firmware",
+            19 => "Updating baseband",
+            20 => "Setting boot stage",
+            21 => "Rebooting device",
+            22 => "Shutting down device",
+            23 => "Turning on accessory power",
+            24 => "Clearing persistent boot-args",
+            25 => "Modifying persistent boot-args",
+            26 => "Installing root",
+            27 => "Installing kernelcache",
+            28 => "Waiting for NAND",
+            29 => "Unmounting filesystems",
+            30 => "Setting date and time on device",
+            31 => "Executing iBEC to bootstrap update",
+            32 => "Finalizing NAND epoch update",
+            33 => "Checking for inappropriate bootable partitions",
+            34 => "Creating factory restore marker",
+            35 => "Loading firmware data to flash",
+            36 => "Requesting FUD data",
+            37 => "Removing activation record",
+            38 => "Checking battery voltage",
+            39 => "Waiting for battery to charge",
+            40 => "Closing modem tickets",
+            41 => "Migrating data",
+            42 => "Wiping storage device",
+            43 => "Sending Apple logo to device",
+            44 => "Checking for uncollected logs",
+            _ => return None,
+        })
+    }
+}
+
+/// How a restore ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreStatus {
+    Success,
+    /// The restored image failed verification
+    VerificationError,
+    /// restored reported an error code
+    Failed(u64),
+}
+
+impl RestoreStatus {
+    fn from_code(code: u64) -> Self {
+        match code {
+            STATUS_SUCCESS => RestoreStatus::Success,
+            STATUS_VERIFICATION_ERROR => RestoreStatus::VerificationError,
+            code => RestoreStatus::Failed(code),
+        }
+    }
+
+    /// Gets a description of the status, for the codes idevicerestore knows
+    pub fn description(&self) -> Option<&'static str> {
+        Some(match self {
+            RestoreStatus::Success => "Restore finished",
+            RestoreStatus::VerificationError => "Verification error",
+            RestoreStatus::Failed(6) => "Disk failure",
+            RestoreStatus::Failed(14) => "Fail",
+            RestoreStatus::Failed(27) => "Failed to mount filesystems",
+            RestoreStatus::Failed(51) => "Failed to load SEP firmware",
+            RestoreStatus::Failed(53) => "Failed to recover FDR data",
+            RestoreStatus::Failed(1015) => "X-Gold baseband update failed",
+            RestoreStatus::Failed(_) => return None,
+        })
+    }
+}
+
+/// Runs a restore after restored has been told to start one
+pub struct RestoreSession<T: RestoredTransport> {
+    transport: T,
+}
+
+impl<T: RestoredTransport> RestoreSession<T> {
+    /// Wraps a connection to restored.
+    /// For a `RestoredClient`, call `start_restore` before running the session.
+    /// # Arguments
+    /// * `transport` - The connection to restored
+    /// # Returns
+    /// The session
+    pub fn new(transport: T) -> Self {
+        RestoreSession { transport }
+    }
+
+    /// Gets the connection back
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Handles restored's messages until it reports a final status
+    /// # Arguments
+    /// * `provider` - Supplies the data restored asks for
+    /// * `on_progress` - Called with every progress report
+    /// # Returns
+    /// How the restore ended
+    ///
+    /// ***Verified:*** False
+    pub fn run(
+        &mut self,
+        provider: &mut impl RestoreDataProvider,
+        mut on_progress: impl FnMut(RestoreProgress),
+    ) -> Result<RestoreStatus, RestoredError> {
+        loop {
+            let message = match self.transport.receive() {
+                Ok(message) => message,
+                // Some steps keep restored quiet for longer than a receive waits
+                Err(RestoredError::ReceiveTimeout) => continue,
+                Err(e) => return Err(e),
+            };
+            let msg_type = dict_string(&message, "MsgType").ok_or(RestoredError::InvalidMessage)?;
+
+            match msg_type.as_str() {
+                "DataRequestMsg" => self.handle_data_request(provider, &message)?,
+                "ProgressMsg" => {
+                    let operation =
+                        dict_uint(&message, "Operation").ok_or(RestoredError::InvalidMessage)?;
+                    // Operations without a percentage report -1
+                    let progress = dict_uint(&message, "Progress")
+                        .filter(|progress| *progress <= 100)
+                        .map(|progress| progress as u8);
+                    on_progress(RestoreProgress {
+                        operation,
+                        progress,
+                    });
+                }
+                "StatusMsg" => {
+                    let code =
+                        dict_uint(&message, "Status").ok_or(RestoredError::InvalidMessage)?;
+                    let status = RestoreStatus::from_code(code);
+                    match status {
+                        RestoreStatus::Success => info!("Restore finished"),
+                        _ => warn!(
+                            "Restore failed with status {}: {}",
+                            code,
+                            status.description().unwrap_or("Unknown error")
+                        ),
+                    }
+                    if let Some(log) = dict_string(&message, "Log") {
+                        info!("restored log: {}", log);
+                    }
+                    return Ok(status);
+                }
+                "CheckpointMsg" => {
+                    if let Some(checkpoint) = dict_uint(&message, "CHECKPOINT_ID") {
+                        info!("Reached checkpoint {}", checkpoint);
+                    }
+                }
+                "PreviousRestoreLogMsg" => {
+                    if let Some(log) = dict_string(&message, "PreviousRestoreLog") {
+                        info!("Previous restore log: {}", log);
+                    }
+                }
+                "BBUpdateStatusMsg" => {
+                    info!("Baseband update status: {}", message.to_string());
+                }
+                "RestoredCrash" => {
+                    warn!("restored crashed: {}", message.to_string());
+                    return Err(RestoredError::DeviceCrashed);
+                }
+                _ => warn!("Skipping unknown restored message {}", msg_type),
+            }
+        }
+    }
+
+    fn handle_data_request(
+        &self,
+        provider: &mut impl RestoreDataProvider,
+        message: &Plist,
+    ) -> Result<(), RestoredError> {
+        let data_type = dict_string(message, "DataType").ok_or(RestoredError::InvalidMessage)?;
+        info!("restored requested {}", data_type);
+
+        let reply = match data_type.as_str() {
+            "SystemImageData" => {
+                provider.system_image(message)?;
+                None
+            }
+            "RootTicket" => Some(data_reply("RootTicketData", &provider.root_ticket()?)),
+            "KernelCache" => Some(data_reply("KernelCacheFile", &provider.kernel_cache()?)),
+            "NORData" => {
+                // Older devices want the images keyed by name rather than in order
+                let by_name = dict_item(message, "Arguments")
+                    .and_then(|arguments| dict_item(&arguments, "FlashVersion1"))
+                    .is_some();
+                Some(nor_reply(provider.nor_data()?, by_name))
+            }
+            "FUDData" => {
+                let arguments = dict_item(message, "Arguments");
+                let wants_list = arguments
+                    .as_ref()
+                    .and_then(|arguments| dict_item(arguments, "FUDImageList"))
+                    .is_some();
+                let image_name = arguments
+                    .as_ref()
+                    .and_then(|arguments| dict_string(arguments, "ImageName"));
+                if wants_list {
+                    let mut list = Plist::new_array();
+                    for name in provider.fud_image_list()? {
+                        let _ = list.array_append_item(Plist::new_string(&name));
+                    }
+                    let mut reply = Plist::new_dict();
+                    let _ = reply.dict_set_item("FUDImageList", list);
+                    Some(reply)
+                } else if let Some(name) = image_name {
+                    Some(data_reply("FUDImageData", &provider.fud_image(&name)?))
+                } else {
+                    return Err(RestoredError::InvalidMessage);
+                }
+            }
+            _ => provider.other_data(&data_type, message)?,
+        };
+
+        match reply {
+            Some(reply) if reply.plist_type == PlistType::Dictionary => self.transport.send(reply),
+            Some(_) => Err(RestoredError::InvalidMessage),
+            None => Ok(()),
+        }
+    }
+}
+
+fn data_reply(key: &str, data: &[u8]) -> Plist {
+    let mut reply = Plist::new_dict();
+    let _ = reply.dict_set_item(key, Plist::new_data(data));
+    reply
+}
+
+fn nor_reply(nor: NorData, by_name: bool) -> Plist {
+    let mut reply = data_reply("LlbImageData", &nor.llb_image);
+    let images = if by_name {
+        let mut images = Plist::new_dict();
+        for (name, data) in &nor.nor_images {
+            let _ = images.dict_set_item(name, Plist::new_data(data));
+        }
+        images
+    } else {
+        let mut images = Plist::new_array();
+        for (_, data) in &nor.nor_images {
+            let _ = images.array_append_item(Plist::new_data(data));
+        }
+        images
+    };
+    let _ = reply.dict_set_item("NorImageData", images);
+    if let Some(sep) = &nor.sep_image {
+        let _ = reply.dict_set_item("RestoreSEPImageData", Plist::new_data(sep));
+    }
+    reply
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque};
+
+    use super::*;
+    use crate::plist_helpers::{array_items, data_bytes, dict_entries, string_array};
+
+    /// Replays recorded restored messages and keeps whatever the session sends back
+    struct FakeRestored {
+        incoming: RefCell<VecDeque<Result<Plist, RestoredError>>>,
+        sent: RefCell<Vec<Plist>>,
+    }
+
+    impl FakeRestored {
+        fn new(messages: &[&str]) -> Self {
+            let incoming = messages
+                .iter()
+                .map(|message| {
+                    Ok(Plist::from_xml(format!(
+                        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+                         <plist version=\"1.0\"><dict>{}</dict></plist>",
+                        message
+                    ))
+                    .unwrap())
+                })
+                .collect();
+            FakeRestored {
+                incoming: RefCell::new(incoming),
+                sent: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl RestoredTransport for FakeRestored {
+        fn send(&self, message: Plist) -> Result<(), RestoredError> {
+            self.sent.borrow_mut().push(message);
+            Ok(())
+        }
+
+        fn receive(&self) -> Result<Plist, RestoredError> {
+            // Running out of messages means the restore never reported a status
+            self.incoming
+                .borrow_mut()
+                .pop_front()
+                .unwrap_or(Err(RestoredError::MuxError))
+        }
+    }
+
+    #[derive(Default)]
+    struct TestProvider {
+        fud_requests: Vec<String>,
+    }
+
+    impl RestoreDataProvider for TestProvider {
+        fn root_ticket(&mut self) -> Result<Vec<u8>, RestoredError> {
+            Ok(b"IM4M".to_vec())
+        }
+
+        fn nor_data(&mut self) -> Result<NorData, RestoredError> {
+            Ok(NorData {
+                llb_image: b"LLB".to_vec(),
+                nor_images: vec![
+                    ("iBoot".into(), b"IBOOT".to_vec()),
+                    ("DeviceTree".into(), b"DTRE".to_vec()),
+                ],
+                sep_image: Some(b"SEP".to_vec()),
+            })
+        }
+
+        fn fud_image_list(&mut self) -> Result<Vec<String>, RestoredError> {
+            Ok(vec!["AOP".into(), "Multitouch".into()])
+        }
+
+        fn fud_image(&mut self, name: &str) -> Result<Vec<u8>, RestoredError> {
+            self.fud_requests.push(name.to_string());
+            Ok(format!("{} image", name).into_bytes())
+        }
+    }
+
+    fn data(reply: &Plist, key: &str) -> Vec<u8> {
+        dict_item(reply, key)
+            .and_then(|data| data_bytes(&data))
+            .unwrap()
+    }
+
+    const SUCCESS: &str = "<key>MsgType</key><string>StatusMsg</string>\
+                           <key>Status</key><integer>0</integer>";
+
+    fn run(messages: &[&str]) -> (Result<RestoreStatus, RestoredError>, Vec<Plist>) {
+        let mut session = RestoreSession::new(FakeRestored::new(messages));
+        let status = session.run(&mut TestProvider::default(), |_| {});
+        (status, session.into_inner().sent.into_inner())
+    }
+
+    #[test]
+    fn answers_root_ticket() {
+        let (status, sent) = run(&[
+            "<key>MsgType</key><string>DataRequestMsg</string>\
+             <key>DataType</key><string>RootTicket</string>",
+            SUCCESS,
+        ]);
+        assert_eq!(status, Ok(RestoreStatus::Success));
+        assert_eq!(sent.len(), 1);
+        assert_eq!(data(&sent[0], "RootTicketData"), b"IM4M");
+    }
+
+    #[test]
+    fn answers_nor_data_in_order() {
+        let (status, sent) = run(&[
+            "<key>MsgType</key><string>DataRequestMsg</string>\
+             <key>DataType</key><string>NORData</string>\
+             <key>Arguments</key><dict></dict>",
+            SUCCESS,
+        ]);
+        assert_eq!(status, Ok(RestoreStatus::Success));
+        let reply = &sent[0];
+        assert_eq!(data(reply, "LlbImageData"), b"LLB");
+        assert_eq!(data(reply, "RestoreSEPImageData"), b"SEP");
+        let images = dict_item(reply, "NorImageData").unwrap();
+        assert_eq!(images.plist_type, PlistType::Array);
+        let images: Vec<_> = array_items(&images)
+            .iter()
+            .map(|image| data_bytes(image).unwrap())
+            .collect();
+        assert_eq!(images, [b"IBOOT".to_vec(), b"DTRE".to_vec()]);
+    }
+
+    #[test]
+    fn answers_nor_data_by_name() {
+        let (status, sent) = run(&[
+            "<key>MsgType</key><string>DataRequestMsg</string>\
+             <key>DataType</key><string>NORData</string>\
+             <key>Arguments</key><dict><key>FlashVersion1</key><true/></dict>",
+            SUCCESS,
+        ]);
+        assert_eq!(status, Ok(RestoreStatus::Success));
+        let images = dict_item(&sent[0], "NorImageData").unwrap();
+        assert_eq!(images.plist_type, PlistType::Dictionary);
+        let mut images: Vec<_> = dict_entries(&images)
+            .into_iter()
+            .map(|(name, image)| (name, data_bytes(&image).unwrap()))
+            .collect();
+        images.sort();
+        assert_eq!(
+            images,
+            [
+                ("DeviceTree".to_string(), b"DTRE".to_vec()),
+                ("iBoot".to_string(), b"IBOOT".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn answers_fud_data() {
+        let mut session = RestoreSession::new(FakeRestored::new(&[
+            "<key>MsgType</key><string>DataRequestMsg</string>\
+             <key>DataType</key><string>FUDData</string>\
+             <key>Arguments</key><dict><key>FUDImageList</key><true/></dict>",
+            "<key>MsgType</key><string>DataRequestMsg</string>\
+             <key>DataType</key><string>FUDData</string>\
+             <key>Arguments</key><dict><key>ImageName</key><string>AOP</string></dict>",
+            SUCCESS,
+        ]));
+        let mut provider = TestProvider::default();
+        assert_eq!(
+            session.run(&mut provider, |_| {}),
+            Ok(RestoreStatus::Success)
+        );
+        assert_eq!(provider.fud_requests, ["AOP"]);
+
+        let sent = session.into_inner().sent.into_inner();
+        assert_eq!(sent.len(), 2);
+        let list = dict_item(&sent[0], "FUDImageList").unwrap();
+        assert_eq!(string_array(&list), ["AOP", "Multitouch"]);
+        assert_eq!(data(&sent[1], "FUDImageData"), b"AOP image");
+    }
+
+    #[test]
+    fn rejects_fud_data_without_arguments() {
+        let (status, sent) = run(&["<key>MsgType</key><string>DataRequestMsg</string>\
+             <key>DataType</key><string>FUDData</string>"]);
+        assert_eq!(status, Err(RestoredError::InvalidMessage));
+        assert!(sent.is_empty());
+    }
+
+    #[test]
+    fn unsupported_data_request() {
+        let (status, _) = run(&["<key>MsgType</key><string>DataRequestMsg</string>\
+             <key>DataType</key><string>KernelCache</string>"]);
+        assert_eq!(status, Err(RestoredError::UnsupportedDataRequest));
+    }
+
+    #[test]
+    fn reports_progress_until_status() {
+        let restored = FakeRestored::new(&[
+            "<key>MsgType</key><string>ProgressMsg</string>\
+             <key>Operation</key><integer>11</integer>\
+             <key>Progress</key><integer>-1</integer>",
+            "<key>MsgType</key><string>ProgressMsg</string>\
+             <key>Operation</key><integer>14</integer>\
+             <key>Progress</key><integer>42</integer>",
+            "<key>MsgType</key><string>CheckpointMsg</string>\
+             <key>CHECKPOINT_ID</key><integer>1102</integer>",
+            "<key>MsgType</key><string>StatusMsg</string>\
+             <key>Status</key><integer>51</integer>\
+             <key>Log</key><string>sep failed</string>",
+            // Never reached, the status ends the session
+            SUCCESS,
+        ]);
+        restored
+            .incoming
+            .borrow_mut()
+            .insert(1, Err(RestoredError::ReceiveTimeout));
+        let mut session = RestoreSession::new(restored);
+        let mut progress = Vec::new();
+        let status = session.run(&mut TestProvider::default(), |report| progress.push(report));
+
+        assert_eq!(status, Ok(RestoreStatus::Failed(51)));
+        assert_eq!(
+            status.unwrap().description(),
+            Some("Failed to load SEP firmware")
+        );
+        assert_eq!(
+            progress,
+            [
+                RestoreProgress {
+                    operation: 11,
+                    progress: None,
+                },
+                RestoreProgress {
+                    operation: 14,
+                    progress: Some(42),
+                },
+            ]
+        );
+        assert_eq!(progress[0].operation_name(), Some("Creating partition map"));
+        let restored = session.into_inner();
+        assert!(restored.sent.borrow().is_empty());
+        assert_eq!(restored.incoming.borrow().len(), 1);
+    }
+
+    #[test]
+    fn verification_error() {
+        let (status, _) = run(&["<key>MsgType</key><string>StatusMsg</string>\
+             <key>Status</key><integer>-1</integer>"]);
+        assert_eq!(status, Ok(RestoreStatus::VerificationError));
+    }
+
+    #[test]
+    fn crash_ends_the_session() {
+        let (status, _) = run(&["<key>MsgType</key><string>RestoredCrash</string>"]);
+        assert_eq!(status, Err(RestoredError::DeviceCrashed));
+    }
+
+    #[test]
+    fn transport_errors_end_the_session() {
+        let (status, _) = run(&[]);
+        assert_eq!(status, Err(RestoredError::MuxError));
+    }
+}
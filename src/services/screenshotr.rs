@@ -1,8 +1,20 @@
 // jkcoxson
 
-use std::ffi::CString;
+use std::{
+    ffi::CString,
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, TrySendError},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
+};
 
-use log::info;
+use log::{debug, info, warn};
 
 use crate::{
     bindings as unsafe_bindings, error::ScreenshotrError, idevice::Device,
@@ -14,6 +26,8 @@ pub struct ScreenshotrClient<'a> {
     phantom: std::marker::PhantomData<&'a Device>,
 }
 
+unsafe impl Send for ScreenshotrClient<'_> {}
+
 impl ScreenshotrClient<'_> {
     /// Creates a preboard client from a screenshotr service
     /// # Arguments
@@ -81,7 +95,8 @@ impl ScreenshotrClient<'_> {
     /// # Arguments
     /// *none*
     /// # Returns
-    /// A vector of bytes containing a .png
+    /// A vector of bytes containing the raw image.
+    /// Depending on the iOS version this is either a PNG or a TIFF, use `capture` to find out which.
     ///
     /// ***Verified:*** False
     pub fn take_screenshot(&self) -> Result<Vec<u8>, ScreenshotrError> {
@@ -96,12 +111,346 @@ impl ScreenshotrClient<'_> {
             return Err(result);
         }
 
+        if data.is_null() {
+            return Err(ScreenshotrError::EmptyScreenshot);
+        }
+        // libimobiledevice mallocs the image, so copy it out and free the original
+        let screenshot =
+            unsafe { std::slice::from_raw_parts(data as *const u8, size as usize).to_vec() };
+        unsafe { libc::free(data as *mut libc::c_void) };
+        if screenshot.is_empty() {
+            return Err(ScreenshotrError::EmptyScreenshot);
+        }
+
         info!("Screenshot size: {}", size);
+        Ok(screenshot)
+    }
+
+    /// Takes a screenshot on the device and detects its image format
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The screenshot
+    ///
+    /// ***Verified:*** False
+    pub fn capture(&self) -> Result<Screenshot, ScreenshotrError> {
+        Ok(Screenshot::new(self.take_screenshot()?))
+    }
+}
+
+/// The image formats that screenshotr is known to return
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Tiff,
+    Jpeg,
+    Unknown,
+}
+
+impl ImageFormat {
+    /// Detects the format of an image from its magic bytes
+    /// # Arguments
+    /// * `data` - The start of the image
+    /// # Returns
+    /// The detected format
+    ///
+    /// ***Verified:*** False
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            ImageFormat::Png
+        } else if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+            ImageFormat::Tiff
+        } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            ImageFormat::Jpeg
+        } else {
+            ImageFormat::Unknown
+        }
+    }
+
+    /// The file extension used for this format, without the dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Tiff => "tiff",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Unknown => "bin",
+        }
+    }
+}
+
+/// A screenshot taken from the device along with its detected format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screenshot {
+    data: Vec<u8>,
+    format: ImageFormat,
+}
+
+impl Screenshot {
+    /// Wraps raw image data, detecting its format
+    /// # Arguments
+    /// * `data` - The raw bytes of the image
+    /// # Returns
+    /// The screenshot
+    ///
+    /// ***Verified:*** False
+    pub fn new(data: Vec<u8>) -> Self {
+        let format = ImageFormat::detect(&data);
+        Screenshot { data, format }
+    }
+
+    /// The format of the image
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    /// The raw bytes of the image
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Consumes the screenshot and returns the raw bytes of the image
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Reads the width and height of the image from its header
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The width and height in pixels, or `None` if the header couldn't be read
+    ///
+    /// ***Verified:*** False
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        match self.format {
+            ImageFormat::Png => png_dimensions(&self.data),
+            ImageFormat::Tiff => tiff_dimensions(&self.data),
+            ImageFormat::Jpeg => jpeg_dimensions(&self.data),
+            ImageFormat::Unknown => None,
+        }
+    }
+
+    /// The width of the image in pixels
+    pub fn width(&self) -> Option<u32> {
+        self.dimensions().map(|(width, _)| width)
+    }
+
+    /// The height of the image in pixels
+    pub fn height(&self) -> Option<u32> {
+        self.dimensions().map(|(_, height)| height)
+    }
+
+    /// Converts the screenshot to a PNG.
+    /// PNG screenshots are returned as is, TIFF screenshots are decoded and re-encoded.
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// A screenshot in the PNG format
+    ///
+    /// ***Verified:*** False
+    #[cfg(feature = "screenshot-png")]
+    pub fn to_png(&self) -> Result<Screenshot, ScreenshotrError> {
+        match self.format {
+            ImageFormat::Png => Ok(self.clone()),
+            ImageFormat::Tiff => Ok(Screenshot::new(tiff_to_png(&self.data)?)),
+            _ => Err(ScreenshotrError::UnsupportedFormat),
+        }
+    }
+
+    /// Converts the screenshot to a JPEG.
+    /// JPEG screenshots are returned as is, any alpha channel is discarded.
+    /// # Arguments
+    /// * `quality` - The JPEG quality from 1 to 100
+    /// # Returns
+    /// A screenshot in the JPEG format
+    ///
+    /// ***Verified:*** False
+    #[cfg(feature = "screenshot-mjpeg")]
+    pub fn to_jpeg(&self, quality: u8) -> Result<Screenshot, ScreenshotrError> {
+        if self.format == ImageFormat::Jpeg {
+            return Ok(self.clone());
+        }
+        let (width, height, channels, pixels) = match self.format {
+            ImageFormat::Png => decode_png(&self.data)?,
+            ImageFormat::Tiff => decode_tiff_8bit(&self.data)?,
+            _ => return Err(ScreenshotrError::UnsupportedFormat),
+        };
+        if width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(ScreenshotrError::UnsupportedFormat);
+        }
+        let (color_type, pixels) = match channels {
+            1 => (jpeg_encoder::ColorType::Luma, pixels),
+            // JPEG has no grayscale alpha, so drop the alpha samples
+            2 => (
+                jpeg_encoder::ColorType::Luma,
+                pixels.chunks(2).map(|p| p[0]).collect(),
+            ),
+            3 => (jpeg_encoder::ColorType::Rgb, pixels),
+            4 => (jpeg_encoder::ColorType::Rgba, pixels),
+            _ => return Err(ScreenshotrError::UnsupportedFormat),
+        };
+
+        let mut output = Vec::new();
+        let encoder = jpeg_encoder::Encoder::new(&mut output, quality);
+        if let Err(e) = encoder.encode(&pixels, width as u16, height as u16, color_type) {
+            warn!("Unable to encode JPEG screenshot: {}", e);
+            return Err(ScreenshotrError::ConversionFailed);
+        }
+        Ok(Screenshot::new(output))
+    }
+
+    /// Saves the screenshot to disk.
+    /// The extension of the path is replaced with the one matching the image format.
+    /// # Arguments
+    /// * `path` - Where to save the screenshot
+    /// # Returns
+    /// The path the screenshot was written to
+    ///
+    /// ***Verified:*** False
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<PathBuf, ScreenshotrError> {
+        let path = path.as_ref().with_extension(self.format.extension());
+        if let Err(e) = std::fs::write(&path, &self.data) {
+            warn!("Unable to write screenshot to {}: {}", path.display(), e);
+            return Err(ScreenshotrError::IoError);
+        }
+        Ok(path)
+    }
+}
 
-        Ok(unsafe { std::slice::from_raw_parts(data as *mut u8, size as usize).to_vec() })
+impl From<Vec<u8>> for Screenshot {
+    fn from(data: Vec<u8>) -> Self {
+        Screenshot::new(data)
     }
 }
 
+fn png_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    // The IHDR chunk always comes first, right after the signature
+    if data.len() < 24 || &data[12..16] != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(data[16..20].try_into().ok()?);
+    let height = u32::from_be_bytes(data[20..24].try_into().ok()?);
+    Some((width, height))
+}
+
+fn tiff_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let little_endian = data.starts_with(b"II");
+    let read_u16 = |offset: usize| -> Option<u32> {
+        let bytes: [u8; 2] = data.get(offset..offset.checked_add(2)?)?.try_into().ok()?;
+        Some(if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        } as u32)
+    };
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let bytes: [u8; 4] = data.get(offset..offset.checked_add(4)?)?.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+
+    let ifd = read_u32(4)? as usize;
+    let entries = read_u16(ifd)? as usize;
+    let mut width = None;
+    let mut height = None;
+    for i in 0..entries {
+        // A corrupt IFD offset mustn't overflow on 32-bit targets
+        let entry = i.checked_mul(12)?.checked_add(ifd)?.checked_add(2)?;
+        let tag = read_u16(entry)?;
+        // SHORT values are stored in the first two bytes of the value field
+        let value = match read_u16(entry.checked_add(2)?)? {
+            3 => read_u16(entry.checked_add(8)?)?,
+            4 => read_u32(entry.checked_add(8)?)?,
+            _ => continue,
+        };
+        match tag {
+            256 => width = Some(value),
+            257 => height = Some(value),
+            _ => {}
+        }
+    }
+    Some((width?, height?))
+}
+
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let mut i = 2;
+    while i + 9 < data.len() {
+        if data[i] != 0xFF {
+            return None;
+        }
+        let marker = data[i + 1];
+        let length = u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+        // Start of frame markers, excluding DHT, JPG and DAC
+        if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+            let height = u16::from_be_bytes([data[i + 5], data[i + 6]]) as u32;
+            let width = u16::from_be_bytes([data[i + 7], data[i + 8]]) as u32;
+            return Some((width, height));
+        }
+        i += 2 + length;
+    }
+    None
+}
+
+#[cfg(feature = "screenshot-png")]
+fn tiff_to_png(data: &[u8]) -> Result<Vec<u8>, ScreenshotrError> {
+    use tiff::{
+        decoder::{Decoder, DecodingResult},
+        ColorType,
+    };
+
+    let mut decoder = match Decoder::new(std::io::Cursor::new(data)) {
+        Ok(decoder) => decoder,
+        Err(e) => {
+            warn!("Unable to decode TIFF screenshot: {}", e);
+            return Err(ScreenshotrError::ConversionFailed);
+        }
+    };
+    let (width, height) = decoder
+        .dimensions()
+        .map_err(|_| ScreenshotrError::ConversionFailed)?;
+    let color_type = match decoder
+        .colortype()
+        .map_err(|_| ScreenshotrError::ConversionFailed)?
+    {
+        ColorType::Gray(_) => png::ColorType::Grayscale,
+        ColorType::GrayA(_) => png::ColorType::GrayscaleAlpha,
+        ColorType::RGB(_) => png::ColorType::Rgb,
+        ColorType::RGBA(_) => png::ColorType::Rgba,
+        other => {
+            warn!("Unsupported TIFF color type: {:?}", other);
+            return Err(ScreenshotrError::UnsupportedFormat);
+        }
+    };
+    let (pixels, bit_depth) = match decoder.read_image() {
+        Ok(DecodingResult::U8(pixels)) => (pixels, png::BitDepth::Eight),
+        // PNG stores 16 bit samples in network byte order
+        Ok(DecodingResult::U16(pixels)) => (
+            pixels.iter().flat_map(|p| p.to_be_bytes()).collect(),
+            png::BitDepth::Sixteen,
+        ),
+        Ok(_) => return Err(ScreenshotrError::UnsupportedFormat),
+        Err(e) => {
+            warn!("Unable to decode TIFF screenshot: {}", e);
+            return Err(ScreenshotrError::ConversionFailed);
+        }
+    };
+
+    let mut output = Vec::new();
+    let mut encoder = png::Encoder::new(&mut output, width, height);
+    encoder.set_color(color_type);
+    encoder.set_depth(bit_depth);
+    let result = encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels));
+    if let Err(e) = result {
+        warn!("Unable to encode PNG screenshot: {}", e);
+        return Err(ScreenshotrError::ConversionFailed);
+    }
+    Ok(output)
+}
+
 impl Drop for ScreenshotrClient<'_> {
     fn drop(&mut self) {
         unsafe {
@@ -109,3 +458,540 @@ impl Drop for ScreenshotrClient<'_> {
         }
     }
 }
+
+#[cfg(feature = "screenshot-mjpeg")]
+fn decode_png(data: &[u8]) -> Result<(u32, u32, u8, Vec<u8>), ScreenshotrError> {
+    let mut decoder = png::Decoder::new(std::io::Cursor::new(data));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = match decoder.read_info() {
+        Ok(reader) => reader,
+        Err(e) => {
+            warn!("Unable to decode PNG screenshot: {}", e);
+            return Err(ScreenshotrError::ConversionFailed);
+        }
+    };
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = match reader.next_frame(&mut pixels) {
+        Ok(info) => info,
+        Err(e) => {
+            warn!("Unable to decode PNG screenshot: {}", e);
+            return Err(ScreenshotrError::ConversionFailed);
+        }
+    };
+    pixels.truncate(info.buffer_size());
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => return Err(ScreenshotrError::UnsupportedFormat),
+    };
+    Ok((info.width, info.height, channels, pixels))
+}
+
+#[cfg(feature = "screenshot-mjpeg")]
+fn decode_tiff_8bit(data: &[u8]) -> Result<(u32, u32, u8, Vec<u8>), ScreenshotrError> {
+    use tiff::{
+        decoder::{Decoder, DecodingResult},
+        ColorType,
+    };
+
+    let mut decoder = match Decoder::new(std::io::Cursor::new(data)) {
+        Ok(decoder) => decoder,
+        Err(e) => {
+            warn!("Unable to decode TIFF screenshot: {}", e);
+            return Err(ScreenshotrError::ConversionFailed);
+        }
+    };
+    let (width, height) = decoder
+        .dimensions()
+        .map_err(|_| ScreenshotrError::ConversionFailed)?;
+    let channels = match decoder
+        .colortype()
+        .map_err(|_| ScreenshotrError::ConversionFailed)?
+    {
+        ColorType::Gray(_) => 1,
+        ColorType::GrayA(_) => 2,
+        ColorType::RGB(_) => 3,
+        ColorType::RGBA(_) => 4,
+        other => {
+            warn!("Unsupported TIFF color type: {:?}", other);
+            return Err(ScreenshotrError::UnsupportedFormat);
+        }
+    };
+    let pixels = match decoder.read_image() {
+        Ok(DecodingResult::U8(pixels)) => pixels,
+        Ok(DecodingResult::U16(pixels)) => pixels.iter().map(|p| (p >> 8) as u8).collect(),
+        Ok(_) => return Err(ScreenshotrError::UnsupportedFormat),
+        Err(e) => {
+            warn!("Unable to decode TIFF screenshot: {}", e);
+            return Err(ScreenshotrError::ConversionFailed);
+        }
+    };
+    Ok((width, height, channels, pixels))
+}
+
+/// A single frame captured by a `ScreenshotStream`
+#[derive(Debug, Clone)]
+pub struct ScreenshotFrame {
+    /// The tick of the stream this frame was captured on.
+    /// Gaps mean the device was too slow to keep up with the interval.
+    pub index: u64,
+    /// The wall clock time the capture was started at
+    pub timestamp: SystemTime,
+    /// The time since the stream was started
+    pub elapsed: Duration,
+    pub screenshot: Screenshot,
+}
+
+/// Continuously takes screenshots on a background thread.
+/// Frames are buffered up to a limit, after which new frames are dropped until the consumer catches up.
+pub struct ScreenshotStream {
+    receiver: Receiver<Result<ScreenshotFrame, ScreenshotrError>>,
+    running: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ScreenshotStream {
+    /// Starts a screenshotr service on a background thread and begins capturing
+    /// # Arguments
+    /// * `device` - The device to capture
+    /// * `label` - The label for the connection
+    /// * `interval` - The target time between frames. Zero captures as fast as the device allows.
+    /// * `buffer` - How many frames to hold before dropping new ones
+    /// # Returns
+    /// The running stream
+    ///
+    /// ***Verified:*** False
+    pub fn start(
+        device: &Device,
+        label: impl Into<String>,
+        interval: Duration,
+        buffer: usize,
+    ) -> Result<Self, ScreenshotrError> {
+        let device = device.clone();
+        let label = label.into();
+        let (sender, receiver) = mpsc::sync_channel(buffer.max(1));
+        let (ready_sender, ready_receiver) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        let thread_running = running.clone();
+        let thread_dropped = dropped.clone();
+        let handle = std::thread::spawn(move || {
+            let client = match ScreenshotrClient::start_service(&device, label) {
+                Ok(client) => {
+                    let _ = ready_sender.send(Ok(()));
+                    client
+                }
+                Err(e) => {
+                    let _ = ready_sender.send(Err(e));
+                    return;
+                }
+            };
+
+            let start = Instant::now();
+            let mut index: u64 = 0;
+            while thread_running.load(Ordering::Relaxed) {
+                let deadline =
+                    start + Duration::from_nanos((interval.as_nanos() * index as u128) as u64);
+                let now = Instant::now();
+                if deadline > now {
+                    std::thread::sleep(deadline - now);
+                }
+
+                let timestamp = SystemTime::now();
+                let elapsed = start.elapsed();
+                let frame = match client.capture() {
+                    Ok(screenshot) => ScreenshotFrame {
+                        index,
+                        timestamp,
+                        elapsed,
+                        screenshot,
+                    },
+                    Err(e) => {
+                        warn!("Screenshot stream stopped: {}", e);
+                        // Don't block on a full channel, the consumer may never drain it
+                        let _ = sender.try_send(Err(e));
+                        break;
+                    }
+                };
+                match sender.try_send(Ok(frame)) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        debug!("Dropping frame {}, the consumer is behind", index);
+                        thread_dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(TrySendError::Disconnected(_)) => break,
+                }
+
+                // Skip the ticks that passed while capturing
+                index = if interval.is_zero() {
+                    index + 1
+                } else {
+                    let current = (start.elapsed().as_nanos() / interval.as_nanos()) as u64;
+                    (current + 1).max(index + 1)
+                };
+            }
+        });
+
+        match ready_receiver.recv() {
+            Ok(Ok(())) => Ok(ScreenshotStream {
+                receiver,
+                running,
+                dropped,
+                handle: Some(handle),
+            }),
+            Ok(Err(e)) => {
+                let _ = handle.join();
+                Err(e)
+            }
+            Err(_) => {
+                let _ = handle.join();
+                Err(ScreenshotrError::UnknownError)
+            }
+        }
+    }
+
+    /// Waits for the next frame
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The next frame, or `None` once the stream has ended
+    ///
+    /// ***Verified:*** False
+    pub fn next_frame(&self) -> Option<Result<ScreenshotFrame, ScreenshotrError>> {
+        self.receiver.recv().ok()
+    }
+
+    /// Waits for the next frame for a limited time
+    /// # Arguments
+    /// * `timeout` - How long to wait for a frame
+    /// # Returns
+    /// The next frame, or `None` if no frame arrived in time or the stream has ended
+    ///
+    /// ***Verified:*** False
+    pub fn next_frame_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Option<Result<ScreenshotFrame, ScreenshotrError>> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Takes the next frame if one is already buffered
+    pub fn try_next_frame(&self) -> Option<Result<ScreenshotFrame, ScreenshotrError>> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// The number of frames that were dropped because the buffer was full
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Records frames from the stream for a duration
+    /// # Arguments
+    /// * `recorder` - Where to write the frames
+    /// * `duration` - How long to record for
+    /// # Returns
+    /// The number of frames written
+    ///
+    /// ***Verified:*** False
+    pub fn record(
+        &self,
+        recorder: &mut ScreenshotRecorder,
+        duration: Duration,
+    ) -> Result<u64, ScreenshotrError> {
+        let end = Instant::now() + duration;
+        let mut written = 0;
+        loop {
+            let now = Instant::now();
+            if now >= end {
+                break;
+            }
+            match self.receiver.recv_timeout(end - now) {
+                Ok(frame) => {
+                    recorder.write_frame(&frame?)?;
+                    written += 1;
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        Ok(written)
+    }
+
+    /// Stops capturing and waits for the background thread to exit
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Iterator for ScreenshotStream {
+    type Item = Result<ScreenshotFrame, ScreenshotrError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame()
+    }
+}
+
+impl Drop for ScreenshotStream {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The on-disk layout of a screen recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordingFormat {
+    /// One image file per frame in a directory, named after the frame index and elapsed milliseconds
+    ImageSequence,
+    /// Every frame converted to JPEG and concatenated into a single motion JPEG file
+    #[cfg(feature = "screenshot-mjpeg")]
+    Mjpeg { quality: u8 },
+}
+
+/// Writes frames from a `ScreenshotStream` to disk
+pub struct ScreenshotRecorder {
+    path: PathBuf,
+    format: RecordingFormat,
+    file: Option<BufWriter<File>>,
+    frames: u64,
+}
+
+impl ScreenshotRecorder {
+    /// Creates a new recording
+    /// # Arguments
+    /// * `path` - The directory for an image sequence, or the file for a motion JPEG
+    /// * `format` - The format to record in
+    /// # Returns
+    /// The recorder
+    ///
+    /// ***Verified:*** False
+    pub fn new(path: impl AsRef<Path>, format: RecordingFormat) -> Result<Self, ScreenshotrError> {
+        let path = path.as_ref().to_path_buf();
+        let file = match format {
+            RecordingFormat::ImageSequence => {
+                if let Err(e) = std::fs::create_dir_all(&path) {
+                    warn!("Unable to create {}: {}", path.display(), e);
+                    return Err(ScreenshotrError::IoError);
+                }
+                None
+            }
+            #[cfg(feature = "screenshot-mjpeg")]
+            RecordingFormat::Mjpeg { .. } => match File::create(&path) {
+                Ok(file) => Some(BufWriter::new(file)),
+                Err(e) => {
+                    warn!("Unable to create {}: {}", path.display(), e);
+                    return Err(ScreenshotrError::IoError);
+                }
+            },
+        };
+        Ok(ScreenshotRecorder {
+            path,
+            format,
+            file,
+            frames: 0,
+        })
+    }
+
+    /// Writes a frame to the recording
+    /// # Arguments
+    /// * `frame` - The frame to write
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn write_frame(&mut self, frame: &ScreenshotFrame) -> Result<(), ScreenshotrError> {
+        match self.format {
+            RecordingFormat::ImageSequence => {
+                let name = format!("frame_{:06}_{}", frame.index, frame.elapsed.as_millis());
+                frame.screenshot.save(self.path.join(name))?;
+            }
+            #[cfg(feature = "screenshot-mjpeg")]
+            RecordingFormat::Mjpeg { quality } => {
+                let jpeg = frame.screenshot.to_jpeg(quality)?;
+                if let Some(file) = self.file.as_mut() {
+                    if let Err(e) = file.write_all(jpeg.data()) {
+                        warn!("Unable to write frame to {}: {}", self.path.display(), e);
+                        return Err(ScreenshotrError::IoError);
+                    }
+                }
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// The number of frames written so far
+    pub fn frames_written(&self) -> u64 {
+        self.frames
+    }
+
+    /// Flushes the recording to disk
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The number of frames written
+    ///
+    /// ***Verified:*** False
+    pub fn finish(mut self) -> Result<u64, ScreenshotrError> {
+        if let Some(file) = self.file.as_mut() {
+            if let Err(e) = file.flush() {
+                warn!("Unable to flush {}: {}", self.path.display(), e);
+                return Err(ScreenshotrError::IoError);
+            }
+        }
+        Ok(self.frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[8, 6, 0, 0, 0]);
+        data
+    }
+
+    /// A TIFF header and IFD with a SHORT width, a LONG height and an unrelated tag between them
+    fn tiff(little_endian: bool, width: u16, height: u32) -> Vec<u8> {
+        let u16_bytes = |value: u16| {
+            if little_endian {
+                value.to_le_bytes()
+            } else {
+                value.to_be_bytes()
+            }
+        };
+        let u32_bytes = |value: u32| {
+            if little_endian {
+                value.to_le_bytes()
+            } else {
+                value.to_be_bytes()
+            }
+        };
+        let mut data = if little_endian {
+            b"II*\0".to_vec()
+        } else {
+            b"MM\0*".to_vec()
+        };
+        data.extend_from_slice(&u32_bytes(8));
+        data.extend_from_slice(&u16_bytes(3));
+        for (tag, kind, value) in [(256, 3, None), (258, 3, Some(8)), (257, 4, Some(height))] {
+            data.extend_from_slice(&u16_bytes(tag));
+            data.extend_from_slice(&u16_bytes(kind));
+            data.extend_from_slice(&u32_bytes(1));
+            match value {
+                Some(value) if kind == 4 => data.extend_from_slice(&u32_bytes(value)),
+                Some(value) => {
+                    data.extend_from_slice(&u16_bytes(value as u16));
+                    data.extend_from_slice(&[0, 0]);
+                }
+                None => {
+                    data.extend_from_slice(&u16_bytes(width));
+                    data.extend_from_slice(&[0, 0]);
+                }
+            }
+        }
+        data.extend_from_slice(&[0; 4]);
+        data
+    }
+
+    fn jpeg(width: u16, height: u16) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        // An APP0 segment to skip over, then a baseline start of frame
+        data.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x10]);
+        data.extend_from_slice(b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        data.extend_from_slice(&[0xFF, 0xC0, 0x00, 0x11, 0x08]);
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&[3, 1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1]);
+        data
+    }
+
+    #[test]
+    fn detects_formats() {
+        assert_eq!(ImageFormat::detect(&png(1, 1)), ImageFormat::Png);
+        assert_eq!(ImageFormat::detect(&tiff(true, 1, 1)), ImageFormat::Tiff);
+        assert_eq!(ImageFormat::detect(&tiff(false, 1, 1)), ImageFormat::Tiff);
+        assert_eq!(ImageFormat::detect(&jpeg(1, 1)), ImageFormat::Jpeg);
+        assert_eq!(ImageFormat::detect(b"GIF89a"), ImageFormat::Unknown);
+        assert_eq!(ImageFormat::detect(b"\x89PN"), ImageFormat::Unknown);
+        assert_eq!(ImageFormat::detect(&[]), ImageFormat::Unknown);
+    }
+
+    #[test]
+    fn reads_png_dimensions() {
+        assert_eq!(png_dimensions(&png(1170, 2532)), Some((1170, 2532)));
+        assert_eq!(
+            Screenshot::new(png(640, 960)).dimensions(),
+            Some((640, 960))
+        );
+        assert_eq!(png_dimensions(&png(1170, 2532)[..23]), None);
+        let mut wrong_chunk = png(1, 1);
+        wrong_chunk[12..16].copy_from_slice(b"IDAT");
+        assert_eq!(png_dimensions(&wrong_chunk), None);
+    }
+
+    #[test]
+    fn reads_tiff_dimensions() {
+        assert_eq!(tiff_dimensions(&tiff(true, 1170, 2532)), Some((1170, 2532)));
+        assert_eq!(
+            tiff_dimensions(&tiff(false, 1170, 2532)),
+            Some((1170, 2532))
+        );
+        assert_eq!(
+            Screenshot::new(tiff(false, 750, 1334)).dimensions(),
+            Some((750, 1334))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_tiffs() {
+        let data = tiff(true, 1170, 2532);
+        // Cut off in the middle of the last entry
+        assert_eq!(tiff_dimensions(&data[..40]), None);
+        assert_eq!(tiff_dimensions(&data[..6]), None);
+
+        // An IFD offset at the very end of the address space
+        let mut data = tiff(true, 1, 1);
+        data[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(tiff_dimensions(&data), None);
+
+        // An entry count that runs past the end of the data
+        let mut data = tiff(false, 1, 1);
+        data[8..10].copy_from_slice(&u16::MAX.to_be_bytes());
+        assert_eq!(tiff_dimensions(&data), None);
+    }
+
+    #[test]
+    fn reads_jpeg_dimensions() {
+        assert_eq!(jpeg_dimensions(&jpeg(1170, 2532)), Some((1170, 2532)));
+        assert_eq!(
+            Screenshot::new(jpeg(320, 480)).dimensions(),
+            Some((320, 480))
+        );
+        let data = jpeg(1170, 2532);
+        assert_eq!(jpeg_dimensions(&data[..24]), None);
+        assert_eq!(jpeg_dimensions(&data[..2]), None);
+        // Garbage where a marker should be
+        let mut data = jpeg(1, 1);
+        data[2] = 0x00;
+        assert_eq!(jpeg_dimensions(&data), None);
+    }
+
+    #[test]
+    fn unknown_formats_have_no_dimensions() {
+        let screenshot = Screenshot::new(b"not an image".to_vec());
+        assert_eq!(screenshot.format(), ImageFormat::Unknown);
+        assert_eq!(screenshot.format().extension(), "bin");
+        assert_eq!(screenshot.dimensions(), None);
+    }
+}
@@ -1,16 +1,22 @@
 // jkcoxson
 
 use std::{
+    collections::HashMap,
     ffi::CString,
     os::raw::{c_char, c_uint},
+    path::Path,
 };
 
 use crate::{
-    bindings as unsafe_bindings, error::SbservicesError, idevice::Device,
-    services::lockdownd::LockdowndService,
+    bindings as unsafe_bindings,
+    error::SbservicesError,
+    idevice::Device,
+    plist_helpers::{array_items, dict_string},
+    services::{instproxy::InstProxyClient, lockdownd::LockdowndService},
 };
 
-use plist_plus::Plist;
+use log::warn;
+use plist_plus::{Plist, PlistType};
 
 /// A service to manage Springboard on iOS
 pub struct SpringboardServicesClient<'a> {
@@ -127,6 +133,28 @@ impl SpringboardServicesClient<'_> {
         Ok(())
     }
 
+    /// Gets the home screen layout in the typed format
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The layout of the dock and home screen pages
+    ///
+    /// ***Verified:*** False
+    pub fn get_home_screen_layout(&self) -> Result<HomeScreenLayout, SbservicesError> {
+        HomeScreenLayout::from_plist(&self.get_icon_state(Some("2".to_string()))?)
+    }
+
+    /// Applies a home screen layout to the device
+    /// # Arguments
+    /// * `layout` - The layout to apply
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn set_home_screen_layout(&self, layout: &HomeScreenLayout) -> Result<(), SbservicesError> {
+        self.set_icon_state(layout.to_plist())
+    }
+
     /// Get the icon of an app
     /// # Arguments
     /// * `bundle_id` - The bundle ID of the app to take the icon from
@@ -138,14 +166,14 @@ impl SpringboardServicesClient<'_> {
         &self,
         bundle_id: impl Into<String>,
     ) -> Result<Vec<u8>, SbservicesError> {
-        let data: *mut u8 = std::ptr::null_mut();
+        let mut data: *mut c_char = std::ptr::null_mut();
         let mut size = 0;
         let bundle_id_c_string = CString::new(bundle_id.into()).unwrap();
         let result = unsafe {
             unsafe_bindings::sbservices_get_icon_pngdata(
                 self.pointer,
                 bundle_id_c_string.as_ptr(),
-                &mut (data as *mut c_char),
+                &mut data,
                 &mut size,
             )
         }
@@ -155,11 +183,7 @@ impl SpringboardServicesClient<'_> {
             return Err(result);
         }
 
-        if data.is_null() {
-            Ok(Vec::new())
-        } else {
-            Ok(unsafe { std::slice::from_raw_parts(data, size as usize).to_vec() })
-        }
+        Ok(take_png_data(data, size))
     }
 
     /// Gets the orientation of the device
@@ -191,12 +215,12 @@ impl SpringboardServicesClient<'_> {
     ///
     /// ***Verified:*** False
     pub fn get_home_screen_wallpaper_pngdata(&self) -> Result<Vec<u8>, SbservicesError> {
-        let data: *mut u8 = std::ptr::null_mut();
+        let mut data: *mut c_char = std::ptr::null_mut();
         let mut size = 0;
         let result = unsafe {
             unsafe_bindings::sbservices_get_home_screen_wallpaper_pngdata(
                 self.pointer,
-                &mut (data as *mut c_char),
+                &mut data,
                 &mut size,
             )
         }
@@ -206,12 +230,583 @@ impl SpringboardServicesClient<'_> {
             return Err(result);
         }
 
-        let mut vec = Vec::with_capacity(size as usize);
-        unsafe {
-            std::ptr::copy_nonoverlapping(data, vec.as_mut_ptr(), size as usize);
+        Ok(take_png_data(data, size))
+    }
+
+    /// Fetches the icons of many apps at once
+    /// # Arguments
+    /// * `apps` - Which apps to fetch icons for
+    /// * `output_dir` - If set, each icon is also written to `<bundle_id>.png` in this directory
+    /// # Returns
+    /// A map of bundle ID to PNG data. Apps without an icon are left out.
+    ///
+    /// ***Verified:*** False
+    pub fn export_icons(
+        &self,
+        apps: IconSelection<'_, '_>,
+        output_dir: Option<&Path>,
+    ) -> Result<HashMap<String, Vec<u8>>, SbservicesError> {
+        let bundle_ids = match apps {
+            IconSelection::BundleIds(bundle_ids) => bundle_ids,
+            IconSelection::AllInstalled(instproxy) => {
+                let options = InstProxyClient::create_return_attributes(
+                    vec![("ApplicationType", Plist::new_string("Any"))],
+                    vec!["CFBundleIdentifier"],
+                );
+                let apps = match instproxy.browse_with_options(options) {
+                    Ok(apps) => apps,
+                    Err(e) => {
+                        warn!("Unable to list installed apps: {}", e);
+                        return Err(SbservicesError::LookupFailed);
+                    }
+                };
+                array_items(&apps)
+                    .iter()
+                    .filter_map(|app| dict_string(app, "CFBundleIdentifier"))
+                    .collect()
+            }
+        };
+
+        if let Some(output_dir) = output_dir {
+            if let Err(e) = std::fs::create_dir_all(output_dir) {
+                warn!("Unable to create {}: {}", output_dir.display(), e);
+                return Err(SbservicesError::IoError);
+            }
+        }
+
+        let mut icons = HashMap::with_capacity(bundle_ids.len());
+        for bundle_id in bundle_ids {
+            let png = match self.get_icon_png_data(bundle_id.as_str()) {
+                Ok(png) if !png.is_empty() => png,
+                Ok(_) => {
+                    warn!("{} has no icon", bundle_id);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Unable to get the icon for {}: {}", bundle_id, e);
+                    continue;
+                }
+            };
+            if let Some(output_dir) = output_dir {
+                let path = output_dir.join(format!("{}.png", bundle_id));
+                if let Err(e) = std::fs::write(&path, &png) {
+                    warn!("Unable to write {}: {}", path.display(), e);
+                    return Err(SbservicesError::IoError);
+                }
+            }
+            icons.insert(bundle_id, png);
+        }
+        Ok(icons)
+    }
+}
+
+/// Copies PNG data allocated by libimobiledevice and frees the original
+fn take_png_data(data: *mut c_char, size: u64) -> Vec<u8> {
+    if data.is_null() {
+        return Vec::new();
+    }
+    let png = unsafe { std::slice::from_raw_parts(data as *const u8, size as usize).to_vec() };
+    unsafe { libc::free(data as *mut libc::c_void) };
+    png
+}
+
+/// The apps to export icons for
+pub enum IconSelection<'a, 'b> {
+    BundleIds(Vec<String>),
+    /// Every installed app, listed through instproxy
+    AllInstalled(&'a InstProxyClient<'b>),
+}
+
+/// The layout of the home screen, as returned by the `2` icon state format.
+/// Every item keeps the dictionary it was parsed from, so unknown keys survive a round trip.
+#[derive(Debug, Clone)]
+pub struct HomeScreenLayout {
+    pub dock: Vec<HomeScreenItem>,
+    pub pages: Vec<Vec<HomeScreenItem>>,
+}
+
+/// A single entry on a home screen page, in the dock or in a folder
+#[derive(Debug, Clone)]
+pub enum HomeScreenItem {
+    App(AppIcon),
+    Folder(Folder),
+    Widget(Widget),
+    /// Anything the layout doesn't model, such as web clips
+    Other(Plist),
+}
+
+/// An app icon on the home screen
+#[derive(Debug, Clone)]
+pub struct AppIcon {
+    pub bundle_id: String,
+    pub display_name: Option<String>,
+    raw: Plist,
+}
+
+/// A folder of icons, which has its own pages
+#[derive(Debug, Clone)]
+pub struct Folder {
+    pub name: String,
+    pub pages: Vec<Vec<HomeScreenItem>>,
+    raw: Plist,
+}
+
+/// A home screen widget, introduced in iOS 14
+#[derive(Debug, Clone)]
+pub struct Widget {
+    /// The bundle ID of the app providing the widget
+    pub bundle_id: Option<String>,
+    /// The size of the widget, such as `small`, `medium` or `large`
+    pub grid_size: Option<String>,
+    raw: Plist,
+}
+
+/// Where an icon is placed in a layout
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum IconLocation {
+    Dock {
+        index: usize,
+    },
+    Page {
+        page: usize,
+        index: usize,
+    },
+    /// Inside a folder. `page` is `None` if the folder is in the dock.
+    Folder {
+        page: Option<usize>,
+        index: usize,
+        folder_page: usize,
+        folder_index: usize,
+    },
+}
+
+/// A difference between two layouts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayoutChange {
+    Added {
+        bundle_id: String,
+        location: IconLocation,
+    },
+    Removed {
+        bundle_id: String,
+        location: IconLocation,
+    },
+    Moved {
+        bundle_id: String,
+        from: IconLocation,
+        to: IconLocation,
+    },
+}
+
+impl AppIcon {
+    /// Creates a new app icon for a bundle ID
+    pub fn new(bundle_id: impl Into<String>) -> Self {
+        let bundle_id = bundle_id.into();
+        let mut raw = Plist::new_dict();
+        let _ = raw.dict_set_item("displayIdentifier", Plist::new_string(&bundle_id));
+        let _ = raw.dict_set_item("bundleIdentifier", Plist::new_string(&bundle_id));
+        AppIcon {
+            bundle_id,
+            display_name: None,
+            raw,
+        }
+    }
+
+    fn to_plist(&self) -> Plist {
+        let mut dict = self.raw.clone();
+        let _ = dict.dict_set_item("displayIdentifier", Plist::new_string(&self.bundle_id));
+        let _ = dict.dict_set_item("bundleIdentifier", Plist::new_string(&self.bundle_id));
+        if let Some(display_name) = &self.display_name {
+            let _ = dict.dict_set_item("displayName", Plist::new_string(display_name));
+        }
+        dict
+    }
+}
+
+impl Folder {
+    /// Creates a new folder with a single page of icons
+    pub fn new(name: impl Into<String>, icons: Vec<HomeScreenItem>) -> Self {
+        let mut raw = Plist::new_dict();
+        let _ = raw.dict_set_item("listType", Plist::new_string("folder"));
+        Folder {
+            name: name.into(),
+            pages: vec![icons],
+            raw,
+        }
+    }
+
+    fn to_plist(&self) -> Plist {
+        let mut dict = self.raw.clone();
+        let _ = dict.dict_set_item("displayName", Plist::new_string(&self.name));
+        let _ = dict.dict_set_item("iconLists", pages_to_plist(&self.pages));
+        dict
+    }
+}
+
+impl HomeScreenItem {
+    fn from_plist(item: Plist) -> Result<Self, SbservicesError> {
+        if item.plist_type != PlistType::Dictionary {
+            warn!("Home screen item is not a dictionary");
+            return Err(SbservicesError::InvalidLayout);
+        }
+
+        if dict_string(&item, "listType").as_deref() == Some("folder") {
+            let pages = match item.dict_get_item("iconLists") {
+                Ok(lists) if lists.plist_type == PlistType::Array => pages_from_plist(&lists)?,
+                _ => Vec::new(),
+            };
+            return Ok(HomeScreenItem::Folder(Folder {
+                name: dict_string(&item, "displayName").unwrap_or_default(),
+                pages,
+                raw: item,
+            }));
+        }
+
+        if dict_string(&item, "iconType").as_deref() == Some("custom")
+            || dict_string(&item, "elementType").as_deref() == Some("widget")
+        {
+            return Ok(HomeScreenItem::Widget(Widget {
+                bundle_id: dict_string(&item, "containerBundleIdentifier"),
+                grid_size: dict_string(&item, "gridSize"),
+                raw: item,
+            }));
+        }
+
+        match dict_string(&item, "bundleIdentifier") {
+            Some(bundle_id) => Ok(HomeScreenItem::App(AppIcon {
+                bundle_id,
+                display_name: dict_string(&item, "displayName"),
+                raw: item,
+            })),
+            None => Ok(HomeScreenItem::Other(item)),
+        }
+    }
+
+    fn to_plist(&self) -> Plist {
+        match self {
+            HomeScreenItem::App(app) => app.to_plist(),
+            HomeScreenItem::Folder(folder) => folder.to_plist(),
+            HomeScreenItem::Widget(widget) => widget.raw.clone(),
+            HomeScreenItem::Other(raw) => raw.clone(),
+        }
+    }
+
+    /// The bundle ID of the item if it is an app
+    pub fn bundle_id(&self) -> Option<&str> {
+        match self {
+            HomeScreenItem::App(app) => Some(&app.bundle_id),
+            _ => None,
+        }
+    }
+}
+
+fn items_from_plist(list: &Plist) -> Result<Vec<HomeScreenItem>, SbservicesError> {
+    if list.plist_type != PlistType::Array {
+        warn!("Home screen page is not an array");
+        return Err(SbservicesError::InvalidLayout);
+    }
+    array_items(list)
+        .into_iter()
+        .map(HomeScreenItem::from_plist)
+        .collect()
+}
+
+fn pages_from_plist(lists: &Plist) -> Result<Vec<Vec<HomeScreenItem>>, SbservicesError> {
+    array_items(lists).iter().map(items_from_plist).collect()
+}
+
+fn items_to_plist(items: &[HomeScreenItem]) -> Plist {
+    let mut array = Plist::new_array();
+    for item in items {
+        let _ = array.array_append_item(item.to_plist());
+    }
+    array
+}
+
+fn pages_to_plist(pages: &[Vec<HomeScreenItem>]) -> Plist {
+    let mut array = Plist::new_array();
+    for page in pages {
+        let _ = array.array_append_item(items_to_plist(page));
+    }
+    array
+}
+
+impl HomeScreenLayout {
+    /// Parses an icon state in the `2` format.
+    /// The first list is the dock, the rest are home screen pages.
+    /// # Arguments
+    /// * `state` - The icon state from `get_icon_state`
+    /// # Returns
+    /// The parsed layout
+    ///
+    /// ***Verified:*** False
+    pub fn from_plist(state: &Plist) -> Result<Self, SbservicesError> {
+        let mut lists = pages_from_plist(state)?.into_iter();
+        let dock = match lists.next() {
+            Some(dock) => dock,
+            None => {
+                warn!("Icon state has no dock");
+                return Err(SbservicesError::InvalidLayout);
+            }
+        };
+        Ok(HomeScreenLayout {
+            dock,
+            pages: lists.collect(),
+        })
+    }
+
+    /// Serializes the layout back into the `2` icon state format
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The icon state to pass to `set_icon_state`
+    ///
+    /// ***Verified:*** False
+    pub fn to_plist(&self) -> Plist {
+        let mut array = Plist::new_array();
+        let _ = array.array_append_item(items_to_plist(&self.dock));
+        for page in &self.pages {
+            let _ = array.array_append_item(items_to_plist(page));
+        }
+        array
+    }
+
+    /// Lists the location of every app in the layout, including those in folders
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// A map of bundle ID to location
+    ///
+    /// ***Verified:*** False
+    pub fn app_locations(&self) -> HashMap<String, IconLocation> {
+        let mut locations = HashMap::new();
+        let mut visit = |items: &[HomeScreenItem], page: Option<usize>| {
+            for (index, item) in items.iter().enumerate() {
+                match item {
+                    HomeScreenItem::App(app) => {
+                        let location = match page {
+                            Some(page) => IconLocation::Page { page, index },
+                            None => IconLocation::Dock { index },
+                        };
+                        locations.insert(app.bundle_id.clone(), location);
+                    }
+                    HomeScreenItem::Folder(folder) => {
+                        for (folder_page, icons) in folder.pages.iter().enumerate() {
+                            for (folder_index, icon) in icons.iter().enumerate() {
+                                if let Some(bundle_id) = icon.bundle_id() {
+                                    locations.insert(
+                                        bundle_id.to_string(),
+                                        IconLocation::Folder {
+                                            page,
+                                            index,
+                                            folder_page,
+                                            folder_index,
+                                        },
+                                    );
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        };
+        visit(&self.dock, None);
+        for (page, items) in self.pages.iter().enumerate() {
+            visit(items, Some(page));
+        }
+        locations
+    }
+
+    /// Finds where an app is in the layout
+    /// # Arguments
+    /// * `bundle_id` - The bundle ID of the app
+    /// # Returns
+    /// The location of the app if it is on the home screen
+    ///
+    /// ***Verified:*** False
+    pub fn find_app(&self, bundle_id: &str) -> Option<IconLocation> {
+        self.app_locations().remove(bundle_id)
+    }
+
+    /// Removes an app from the layout, wherever it is.
+    /// A folder page left empty is removed, and so is a folder left without pages.
+    /// # Arguments
+    /// * `bundle_id` - The bundle ID of the app
+    /// # Returns
+    /// The removed icon
+    ///
+    /// ***Verified:*** False
+    pub fn remove_app(&mut self, bundle_id: &str) -> Result<AppIcon, SbservicesError> {
+        let location = match self.find_app(bundle_id) {
+            Some(location) => location,
+            None => return Err(SbservicesError::IconNotFound),
+        };
+        let item = match location {
+            IconLocation::Dock { index } => self.dock.remove(index),
+            IconLocation::Page { page, index } => self.pages[page].remove(index),
+            IconLocation::Folder {
+                page,
+                index,
+                folder_page,
+                folder_index,
+            } => {
+                let items = match page {
+                    Some(page) => &mut self.pages[page],
+                    None => &mut self.dock,
+                };
+                let folder = match &mut items[index] {
+                    HomeScreenItem::Folder(folder) => folder,
+                    _ => return Err(SbservicesError::InvalidLayout),
+                };
+                let item = folder.pages[folder_page].remove(folder_index);
+                // SpringBoard doesn't keep empty folder pages or empty folders around
+                if folder.pages[folder_page].is_empty() {
+                    folder.pages.remove(folder_page);
+                }
+                if folder.pages.is_empty() {
+                    items.remove(index);
+                }
+                item
+            }
+        };
+        match item {
+            HomeScreenItem::App(app) => Ok(app),
+            _ => Err(SbservicesError::InvalidLayout),
+        }
+    }
+
+    /// Moves an app to a home screen page.
+    /// A page index one past the last page creates a new page.
+    /// # Arguments
+    /// * `bundle_id` - The bundle ID of the app
+    /// * `page` - The page to move the app to
+    /// * `position` - The position on the page, or `None` to append it
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn move_app_to_page(
+        &mut self,
+        bundle_id: &str,
+        page: usize,
+        position: Option<usize>,
+    ) -> Result<(), SbservicesError> {
+        if page > self.pages.len() {
+            return Err(SbservicesError::InvalidArg);
+        }
+        let app = self.remove_app(bundle_id)?;
+        if page == self.pages.len() {
+            self.pages.push(Vec::new());
+        }
+        insert_item(&mut self.pages[page], HomeScreenItem::App(app), position);
+        Ok(())
+    }
+
+    /// Moves an app into the dock
+    /// # Arguments
+    /// * `bundle_id` - The bundle ID of the app
+    /// * `position` - The position in the dock, or `None` to append it
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn move_app_to_dock(
+        &mut self,
+        bundle_id: &str,
+        position: Option<usize>,
+    ) -> Result<(), SbservicesError> {
+        let app = self.remove_app(bundle_id)?;
+        insert_item(&mut self.dock, HomeScreenItem::App(app), position);
+        Ok(())
+    }
+
+    /// Creates a folder containing the given apps and places it on a page
+    /// # Arguments
+    /// * `name` - The name of the folder
+    /// * `bundle_ids` - The apps to move into the folder
+    /// * `page` - The page to place the folder on. One past the last page creates a new page.
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn create_folder(
+        &mut self,
+        name: impl Into<String>,
+        bundle_ids: &[&str],
+        page: usize,
+    ) -> Result<(), SbservicesError> {
+        if page > self.pages.len() {
+            return Err(SbservicesError::InvalidArg);
+        }
+        if let Some(missing) = bundle_ids.iter().find(|id| self.find_app(id).is_none()) {
+            warn!(
+                "Cannot add {} to folder, it is not on the home screen",
+                missing
+            );
+            return Err(SbservicesError::IconNotFound);
+        }
+        let mut icons = Vec::with_capacity(bundle_ids.len());
+        for bundle_id in bundle_ids {
+            icons.push(HomeScreenItem::App(self.remove_app(bundle_id)?));
+        }
+        if page == self.pages.len() {
+            self.pages.push(Vec::new());
         }
+        self.pages[page].push(HomeScreenItem::Folder(Folder::new(name, icons)));
+        Ok(())
+    }
 
-        Ok(vec)
+    /// Compares the app placement of two layouts
+    /// # Arguments
+    /// * `other` - The layout to compare against
+    /// # Returns
+    /// The changes needed to go from this layout to `other`
+    ///
+    /// ***Verified:*** False
+    pub fn diff(&self, other: &HomeScreenLayout) -> Vec<LayoutChange> {
+        let before = self.app_locations();
+        let after = other.app_locations();
+        let mut changes = Vec::new();
+        for (bundle_id, from) in &before {
+            match after.get(bundle_id) {
+                Some(to) if to != from => changes.push(LayoutChange::Moved {
+                    bundle_id: bundle_id.clone(),
+                    from: from.clone(),
+                    to: to.clone(),
+                }),
+                Some(_) => {}
+                None => changes.push(LayoutChange::Removed {
+                    bundle_id: bundle_id.clone(),
+                    location: from.clone(),
+                }),
+            }
+        }
+        for (bundle_id, to) in &after {
+            if !before.contains_key(bundle_id) {
+                changes.push(LayoutChange::Added {
+                    bundle_id: bundle_id.clone(),
+                    location: to.clone(),
+                });
+            }
+        }
+        changes.sort_by(|a, b| change_bundle_id(a).cmp(change_bundle_id(b)));
+        changes
+    }
+}
+
+fn insert_item(items: &mut Vec<HomeScreenItem>, item: HomeScreenItem, position: Option<usize>) {
+    match position {
+        Some(position) if position < items.len() => items.insert(position, item),
+        _ => items.push(item),
+    }
+}
+
+fn change_bundle_id(change: &LayoutChange) -> &str {
+    match change {
+        LayoutChange::Added { bundle_id, .. }
+        | LayoutChange::Removed { bundle_id, .. }
+        | LayoutChange::Moved { bundle_id, .. } => bundle_id,
     }
 }
 
@@ -257,3 +852,319 @@ impl Drop for SpringboardServicesClient<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An icon state in the `2` format, as returned by an iOS 15 device: a dock, a page with a
+    /// folder and a widget, and a page with a web clip the layout doesn't model
+    const ICON_STATE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<array>
+	<array>
+		<dict>
+			<key>bundleIdentifier</key>
+			<string>com.apple.mobilephone</string>
+			<key>bundleVersion</key>
+			<string>1</string>
+			<key>displayIdentifier</key>
+			<string>com.apple.mobilephone</string>
+			<key>displayName</key>
+			<string>Phone</string>
+		</dict>
+		<dict>
+			<key>bundleIdentifier</key>
+			<string>com.apple.mobilesafari</string>
+			<key>bundleVersion</key>
+			<string>8614.1.25.9.10</string>
+			<key>displayIdentifier</key>
+			<string>com.apple.mobilesafari</string>
+			<key>displayName</key>
+			<string>Safari</string>
+		</dict>
+	</array>
+	<array>
+		<dict>
+			<key>bundleIdentifier</key>
+			<string>com.apple.mobilecal</string>
+			<key>displayIdentifier</key>
+			<string>com.apple.mobilecal</string>
+			<key>displayName</key>
+			<string>Calendar</string>
+		</dict>
+		<dict>
+			<key>displayName</key>
+			<string>Utilities</string>
+			<key>iconLists</key>
+			<array>
+				<array>
+					<dict>
+						<key>bundleIdentifier</key>
+						<string>com.apple.calculator</string>
+						<key>displayIdentifier</key>
+						<string>com.apple.calculator</string>
+						<key>displayName</key>
+						<string>Calculator</string>
+					</dict>
+				</array>
+				<array>
+					<dict>
+						<key>bundleIdentifier</key>
+						<string>com.apple.compass</string>
+						<key>displayIdentifier</key>
+						<string>com.apple.compass</string>
+						<key>displayName</key>
+						<string>Compass</string>
+					</dict>
+				</array>
+			</array>
+			<key>listType</key>
+			<string>folder</string>
+		</dict>
+		<dict>
+			<key>containerBundleIdentifier</key>
+			<string>com.apple.weather</string>
+			<key>elementType</key>
+			<string>widget</string>
+			<key>elements</key>
+			<array>
+				<dict>
+					<key>containerBundleIdentifier</key>
+					<string>com.apple.weather</string>
+					<key>widgetIdentifier</key>
+					<string>com.apple.weather.widget</string>
+				</dict>
+			</array>
+			<key>gridSize</key>
+			<string>medium</string>
+			<key>iconType</key>
+			<string>custom</string>
+		</dict>
+	</array>
+	<array>
+		<dict>
+			<key>displayIdentifier</key>
+			<string>com.apple.webapp-6D1D1E5C</string>
+			<key>displayName</key>
+			<string>Docs</string>
+			<key>iconType</key>
+			<string>webclip</string>
+		</dict>
+		<dict>
+			<key>bundleIdentifier</key>
+			<string>com.apple.Maps</string>
+			<key>displayIdentifier</key>
+			<string>com.apple.Maps</string>
+			<key>displayName</key>
+			<string>Maps</string>
+		</dict>
+	</array>
+</array>
+</plist>
+"#;
+
+    fn layout() -> HomeScreenLayout {
+        let state = Plist::from_xml(ICON_STATE.to_string()).unwrap();
+        HomeScreenLayout::from_plist(&state).unwrap()
+    }
+
+    fn folder(layout: &HomeScreenLayout) -> &Folder {
+        match &layout.pages[0][1] {
+            HomeScreenItem::Folder(folder) => folder,
+            item => panic!("expected a folder, found {:?}", item),
+        }
+    }
+
+    #[test]
+    fn parses_icon_state() {
+        let layout = layout();
+        assert_eq!(layout.dock.len(), 2);
+        assert_eq!(layout.dock[1].bundle_id(), Some("com.apple.mobilesafari"));
+        assert_eq!(layout.pages.len(), 2);
+
+        let folder = folder(&layout);
+        assert_eq!(folder.name, "Utilities");
+        assert_eq!(folder.pages.len(), 2);
+        match &layout.pages[0][2] {
+            HomeScreenItem::Widget(widget) => {
+                assert_eq!(widget.bundle_id.as_deref(), Some("com.apple.weather"));
+                assert_eq!(widget.grid_size.as_deref(), Some("medium"));
+            }
+            item => panic!("expected a widget, found {:?}", item),
+        }
+        assert!(matches!(layout.pages[1][0], HomeScreenItem::Other(_)));
+        match &layout.pages[1][1] {
+            HomeScreenItem::App(app) => assert_eq!(app.display_name.as_deref(), Some("Maps")),
+            item => panic!("expected an app, found {:?}", item),
+        }
+    }
+
+    #[test]
+    fn round_trips_icon_state() {
+        let state = Plist::from_xml(ICON_STATE.to_string()).unwrap();
+        let layout = HomeScreenLayout::from_plist(&state).unwrap();
+        assert_eq!(layout.to_plist().to_string(), state.to_string());
+    }
+
+    #[test]
+    fn rejects_invalid_icon_state() {
+        let mut state = Plist::new_array();
+        assert!(HomeScreenLayout::from_plist(&state).is_err());
+        let _ = state.array_append_item(Plist::new_string("dock"));
+        assert!(HomeScreenLayout::from_plist(&state).is_err());
+    }
+
+    #[test]
+    fn finds_apps() {
+        let layout = layout();
+        assert_eq!(
+            layout.find_app("com.apple.mobilesafari"),
+            Some(IconLocation::Dock { index: 1 })
+        );
+        assert_eq!(
+            layout.find_app("com.apple.Maps"),
+            Some(IconLocation::Page { page: 1, index: 1 })
+        );
+        assert_eq!(
+            layout.find_app("com.apple.compass"),
+            Some(IconLocation::Folder {
+                page: Some(0),
+                index: 1,
+                folder_page: 1,
+                folder_index: 0,
+            })
+        );
+        assert_eq!(layout.find_app("com.apple.weather"), None);
+        assert_eq!(layout.app_locations().len(), 6);
+    }
+
+    #[test]
+    fn removing_apps_drops_empty_folder_pages_and_folders() {
+        let mut layout = layout();
+        let compass = layout.remove_app("com.apple.compass").unwrap();
+        assert_eq!(compass.bundle_id, "com.apple.compass");
+        assert_eq!(folder(&layout).pages.len(), 1);
+
+        layout.remove_app("com.apple.calculator").unwrap();
+        assert_eq!(layout.pages[0].len(), 2);
+        assert!(matches!(layout.pages[0][1], HomeScreenItem::Widget(_)));
+        assert_eq!(
+            layout.remove_app("com.apple.calculator").err(),
+            Some(SbservicesError::IconNotFound)
+        );
+    }
+
+    #[test]
+    fn moves_apps_to_pages() {
+        let mut layout = layout();
+        layout
+            .move_app_to_page("com.apple.mobilesafari", 1, Some(0))
+            .unwrap();
+        assert_eq!(layout.dock.len(), 1);
+        assert_eq!(
+            layout.find_app("com.apple.mobilesafari"),
+            Some(IconLocation::Page { page: 1, index: 0 })
+        );
+
+        // One past the last page makes a new one
+        layout
+            .move_app_to_page("com.apple.compass", 2, None)
+            .unwrap();
+        assert_eq!(layout.pages.len(), 3);
+        assert_eq!(
+            layout.find_app("com.apple.compass"),
+            Some(IconLocation::Page { page: 2, index: 0 })
+        );
+        assert_eq!(folder(&layout).pages.len(), 1);
+
+        assert_eq!(
+            layout.move_app_to_page("com.apple.Maps", 5, None).err(),
+            Some(SbservicesError::InvalidArg)
+        );
+        layout.move_app_to_dock("com.apple.Maps", Some(0)).unwrap();
+        assert_eq!(layout.dock[0].bundle_id(), Some("com.apple.Maps"));
+    }
+
+    #[test]
+    fn creates_folders() {
+        let mut layout = layout();
+        layout
+            .create_folder("Travel", &["com.apple.Maps", "com.apple.compass"], 1)
+            .unwrap();
+        let travel = match layout.pages[1].last() {
+            Some(HomeScreenItem::Folder(folder)) => folder,
+            item => panic!("expected a folder, found {:?}", item),
+        };
+        assert_eq!(travel.name, "Travel");
+        let icons: Vec<_> = travel.pages[0]
+            .iter()
+            .map(|icon| icon.bundle_id())
+            .collect();
+        assert_eq!(icons, [Some("com.apple.Maps"), Some("com.apple.compass")]);
+        assert_eq!(
+            dict_string(&travel.to_plist(), "listType").as_deref(),
+            Some("folder")
+        );
+
+        // Nothing moves if one of the apps isn't there
+        assert_eq!(
+            layout
+                .create_folder("Missing", &["com.apple.mobilecal", "com.example.none"], 0)
+                .err(),
+            Some(SbservicesError::IconNotFound)
+        );
+        assert_eq!(
+            layout.find_app("com.apple.mobilecal"),
+            Some(IconLocation::Page { page: 0, index: 0 })
+        );
+    }
+
+    #[test]
+    fn diffs_layouts() {
+        let before = layout();
+        let mut after = layout();
+        after.move_app_to_dock("com.apple.Maps", None).unwrap();
+        after.remove_app("com.apple.calculator").unwrap();
+        after.pages[1].push(HomeScreenItem::App(AppIcon::new("com.example.new")));
+        assert_eq!(
+            before.diff(&after),
+            [
+                LayoutChange::Moved {
+                    bundle_id: "com.apple.Maps".to_string(),
+                    from: IconLocation::Page { page: 1, index: 1 },
+                    to: IconLocation::Dock { index: 2 },
+                },
+                LayoutChange::Removed {
+                    bundle_id: "com.apple.calculator".to_string(),
+                    location: IconLocation::Folder {
+                        page: Some(0),
+                        index: 1,
+                        folder_page: 0,
+                        folder_index: 0,
+                    },
+                },
+                LayoutChange::Moved {
+                    bundle_id: "com.apple.compass".to_string(),
+                    from: IconLocation::Folder {
+                        page: Some(0),
+                        index: 1,
+                        folder_page: 1,
+                        folder_index: 0,
+                    },
+                    to: IconLocation::Folder {
+                        page: Some(0),
+                        index: 1,
+                        folder_page: 0,
+                        folder_index: 0,
+                    },
+                },
+                LayoutChange::Added {
+                    bundle_id: "com.example.new".to_string(),
+                    location: IconLocation::Page { page: 1, index: 1 },
+                },
+            ]
+        );
+        assert!(before.diff(&layout()).is_empty());
+    }
+}
@@ -0,0 +1,785 @@
+// jkcoxson
+// Runs MobileSync sessions and maps the records of the built in data classes to typed entities
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::SystemTime,
+};
+
+use log::{info, warn};
+use plist_plus::{Plist, PlistType};
+
+use crate::{
+    error::MobileSyncError,
+    idevice::Device,
+    plist_helpers::{dict_bool, dict_date, dict_entries, dict_string, new_date, string_array},
+    services::mobile_sync::{
+        MobileSyncActions, MobileSyncAnchor, MobileSyncClient, MobileSyncType,
+    },
+};
+
+/// The key every record uses to name its entity type
+const ENTITY_NAME_KEY: &str = "com.apple.syncservices.RecordEntityName";
+
+/// The kinds of data that can be synced
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SyncDataClass {
+    Contacts,
+    Calendars,
+    Bookmarks,
+    Other(String),
+}
+
+impl SyncDataClass {
+    /// Gets the identifier MobileSync uses for the data class
+    pub fn as_str(&self) -> &str {
+        match self {
+            SyncDataClass::Contacts => "com.apple.Contacts",
+            SyncDataClass::Calendars => "com.apple.Calendars",
+            SyncDataClass::Bookmarks => "com.apple.Bookmarks",
+            SyncDataClass::Other(name) => name,
+        }
+    }
+
+    /// The data class version the host claims to understand
+    fn computer_version(&self) -> u64 {
+        match self {
+            SyncDataClass::Calendars => 107,
+            SyncDataClass::Bookmarks => 102,
+            _ => 106,
+        }
+    }
+}
+
+/// A set of records keyed by their record identifier
+#[derive(Debug, Clone, Default)]
+pub struct SyncRecords {
+    /// The records, each a dictionary naming its entity type
+    pub records: BTreeMap<String, Plist>,
+    /// Identifiers of records the device reported as deleted
+    pub deleted: Vec<String>,
+}
+
+impl SyncRecords {
+    /// Creates an empty record set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a record, replacing any record with the same identifier
+    pub fn insert(&mut self, id: impl Into<String>, record: Plist) {
+        self.records.insert(id.into(), record);
+    }
+
+    /// Gets the entity type of a record, such as `com.apple.contacts.Contact`
+    pub fn entity_name(record: &Plist) -> Option<String> {
+        dict_string(record, ENTITY_NAME_KEY)
+    }
+
+    /// Iterates over the records of a single entity type
+    pub fn records_of<'a>(
+        &'a self,
+        entity_name: &'a str,
+    ) -> impl Iterator<Item = (&'a String, &'a Plist)> + 'a {
+        self.records
+            .iter()
+            .filter(move |(_, record)| Self::entity_name(record).as_deref() == Some(entity_name))
+    }
+
+    /// Collects the contacts, along with their phone numbers, emails, URLs and addresses
+    pub fn contacts(&self) -> Vec<Contact> {
+        let mut contacts: BTreeMap<String, Contact> = self
+            .records_of(Contact::ENTITY)
+            .map(|(id, record)| (id.clone(), Contact::from_record(id, record)))
+            .collect();
+
+        for (id, record) in &self.records {
+            let entity = match Self::entity_name(record) {
+                Some(entity) => entity,
+                None => continue,
+            };
+            for owner in string_array_field(record, "contact") {
+                let contact = match contacts.get_mut(&owner) {
+                    Some(contact) => contact,
+                    None => continue,
+                };
+                match entity.as_str() {
+                    ContactField::PHONE_ENTITY => contact
+                        .phone_numbers
+                        .push(ContactField::from_record(id, record)),
+                    ContactField::EMAIL_ENTITY => {
+                        contact.emails.push(ContactField::from_record(id, record))
+                    }
+                    ContactField::URL_ENTITY => {
+                        contact.urls.push(ContactField::from_record(id, record))
+                    }
+                    ContactAddress::ENTITY => contact
+                        .addresses
+                        .push(ContactAddress::from_record(id, record)),
+                    _ => {}
+                }
+            }
+        }
+        contacts.into_values().collect()
+    }
+
+    /// Collects the calendars
+    pub fn calendars(&self) -> Vec<Calendar> {
+        self.records_of(Calendar::ENTITY)
+            .map(|(id, record)| Calendar::from_record(id, record))
+            .collect()
+    }
+
+    /// Collects the calendar events
+    pub fn events(&self) -> Vec<CalendarEvent> {
+        self.records_of(CalendarEvent::ENTITY)
+            .map(|(id, record)| CalendarEvent::from_record(id, record))
+            .collect()
+    }
+
+    /// Collects the bookmarks
+    pub fn bookmarks(&self) -> Vec<Bookmark> {
+        self.records_of(Bookmark::ENTITY)
+            .map(|(id, record)| Bookmark::from_record(id, record))
+            .collect()
+    }
+
+    /// Collects the bookmark folders
+    pub fn bookmark_folders(&self) -> Vec<BookmarkFolder> {
+        self.records_of(BookmarkFolder::ENTITY)
+            .map(|(id, record)| BookmarkFolder::from_record(id, record))
+            .collect()
+    }
+
+    /// Adds a contact and its child records
+    pub fn add_contact(&mut self, contact: &Contact) {
+        for (id, record) in contact.to_records() {
+            self.insert(id, record);
+        }
+    }
+
+    /// Adds a calendar
+    pub fn add_calendar(&mut self, calendar: &Calendar) {
+        self.insert(calendar.id.clone(), calendar.to_record());
+    }
+
+    /// Adds a calendar event
+    pub fn add_event(&mut self, event: &CalendarEvent) {
+        self.insert(event.id.clone(), event.to_record());
+    }
+
+    /// Adds a bookmark
+    pub fn add_bookmark(&mut self, bookmark: &Bookmark) {
+        self.insert(bookmark.id.clone(), bookmark.to_record());
+    }
+
+    /// Adds a bookmark folder
+    pub fn add_bookmark_folder(&mut self, folder: &BookmarkFolder) {
+        self.insert(folder.id.clone(), folder.to_record());
+    }
+
+    /// Merges a batch of entities received from the device.
+    /// Entries that aren't dictionaries mark deleted records.
+    fn merge(&mut self, entities: &Plist) {
+        for (id, record) in dict_entries(entities) {
+            if record.plist_type == PlistType::Dictionary {
+                self.records.insert(id, record);
+            } else {
+                self.records.remove(&id);
+                self.deleted.push(id);
+            }
+        }
+    }
+
+    fn to_plist(&self) -> Plist {
+        let mut entities = Plist::new_dict();
+        for (id, record) in &self.records {
+            let _ = entities.dict_set_item(id, record.clone());
+        }
+        entities
+    }
+
+    fn entity_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .records
+            .values()
+            .filter_map(Self::entity_name)
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+/// A person in the address book
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Contact {
+    pub id: String,
+    pub first_name: Option<String>,
+    pub middle_name: Option<String>,
+    pub last_name: Option<String>,
+    pub nickname: Option<String>,
+    /// Such as "Dr."
+    pub prefix: Option<String>,
+    /// Such as "Jr."
+    pub suffix: Option<String>,
+    pub organization: Option<String>,
+    pub department: Option<String>,
+    pub job_title: Option<String>,
+    pub notes: Option<String>,
+    pub birthday: Option<SystemTime>,
+    pub phone_numbers: Vec<ContactField>,
+    pub emails: Vec<ContactField>,
+    pub urls: Vec<ContactField>,
+    pub addresses: Vec<ContactAddress>,
+}
+
+impl Contact {
+    pub const ENTITY: &'static str = "com.apple.contacts.Contact";
+
+    const FIELDS: [&'static str; 10] = [
+        "first name",
+        "middle name",
+        "last name",
+        "nickname",
+        "title",
+        "suffix",
+        "company name",
+        "department",
+        "job title",
+        "notes",
+    ];
+
+    /// Reads a contact record. Child records are attached by `SyncRecords::contacts`.
+    pub fn from_record(id: &str, record: &Plist) -> Self {
+        let [first_name, middle_name, last_name, nickname, prefix, suffix, organization, department, job_title, notes] =
+            Self::FIELDS.map(|key| dict_string(record, key));
+        Contact {
+            id: id.to_string(),
+            first_name,
+            middle_name,
+            last_name,
+            nickname,
+            prefix,
+            suffix,
+            organization,
+            department,
+            job_title,
+            notes,
+            birthday: dict_date(record, "birthday"),
+            ..Default::default()
+        }
+    }
+
+    /// Builds the contact record and one record per phone number, email, URL and address.
+    /// Children without an identifier are given one derived from the contact's.
+    pub fn to_records(&self) -> Vec<(String, Plist)> {
+        let mut record = new_record(Self::ENTITY);
+        let values = [
+            &self.first_name,
+            &self.middle_name,
+            &self.last_name,
+            &self.nickname,
+            &self.prefix,
+            &self.suffix,
+            &self.organization,
+            &self.department,
+            &self.job_title,
+            &self.notes,
+        ];
+        for (key, value) in Self::FIELDS.iter().zip(values) {
+            set_string(&mut record, key, value);
+        }
+        set_date(&mut record, "birthday", self.birthday);
+
+        let mut records = vec![(self.id.clone(), record)];
+        let children = [
+            (ContactField::PHONE_ENTITY, "phone", &self.phone_numbers),
+            (ContactField::EMAIL_ENTITY, "email", &self.emails),
+            (ContactField::URL_ENTITY, "url", &self.urls),
+        ];
+        for (entity, name, fields) in children {
+            for (index, field) in fields.iter().enumerate() {
+                let id = field
+                    .id
+                    .clone()
+                    .unwrap_or_else(|| format!("{}/{}/{}", self.id, name, index));
+                records.push((id, field.to_record(entity, &self.id)));
+            }
+        }
+        for (index, address) in self.addresses.iter().enumerate() {
+            let id = address
+                .id
+                .clone()
+                .unwrap_or_else(|| format!("{}/address/{}", self.id, index));
+            records.push((id, address.to_record(&self.id)));
+        }
+        records
+    }
+
+    /// Gets the name to show for the contact
+    pub fn display_name(&self) -> String {
+        let name = [&self.first_name, &self.middle_name, &self.last_name]
+            .iter()
+            .filter_map(|part| part.as_deref())
+            .collect::<Vec<_>>()
+            .join(" ");
+        if name.is_empty() {
+            self.organization.clone().unwrap_or_default()
+        } else {
+            name
+        }
+    }
+}
+
+/// A phone number, email address or URL of a contact
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContactField {
+    pub id: Option<String>,
+    /// Such as "home", "work" or "mobile"
+    pub kind: Option<String>,
+    /// A custom label shown instead of the kind
+    pub label: Option<String>,
+    pub value: String,
+}
+
+impl ContactField {
+    pub const PHONE_ENTITY: &'static str = "com.apple.contacts.Phone Number";
+    pub const EMAIL_ENTITY: &'static str = "com.apple.contacts.Email Address";
+    pub const URL_ENTITY: &'static str = "com.apple.contacts.URL";
+
+    fn from_record(id: &str, record: &Plist) -> Self {
+        ContactField {
+            id: Some(id.to_string()),
+            kind: dict_string(record, "type"),
+            label: dict_string(record, "label"),
+            value: dict_string(record, "value").unwrap_or_default(),
+        }
+    }
+
+    fn to_record(&self, entity: &str, contact_id: &str) -> Plist {
+        let mut record = new_record(entity);
+        let _ = record.dict_set_item("value", Plist::new_string(&self.value));
+        set_string(&mut record, "type", &self.kind);
+        set_string(&mut record, "label", &self.label);
+        set_owner(&mut record, "contact", contact_id);
+        record
+    }
+}
+
+/// A postal address of a contact
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContactAddress {
+    pub id: Option<String>,
+    pub kind: Option<String>,
+    pub label: Option<String>,
+    pub street: Option<String>,
+    pub city: Option<String>,
+    pub state: Option<String>,
+    pub postal_code: Option<String>,
+    pub country: Option<String>,
+    pub country_code: Option<String>,
+}
+
+impl ContactAddress {
+    pub const ENTITY: &'static str = "com.apple.contacts.Street Address";
+
+    const FIELDS: [&'static str; 8] = [
+        "type",
+        "label",
+        "street",
+        "city",
+        "state",
+        "postal code",
+        "country",
+        "country code",
+    ];
+
+    fn from_record(id: &str, record: &Plist) -> Self {
+        let [kind, label, street, city, state, postal_code, country, country_code] =
+            Self::FIELDS.map(|key| dict_string(record, key));
+        ContactAddress {
+            id: Some(id.to_string()),
+            kind,
+            label,
+            street,
+            city,
+            state,
+            postal_code,
+            country,
+            country_code,
+        }
+    }
+
+    fn to_record(&self, contact_id: &str) -> Plist {
+        let mut record = new_record(Self::ENTITY);
+        let values = [
+            &self.kind,
+            &self.label,
+            &self.street,
+            &self.city,
+            &self.state,
+            &self.postal_code,
+            &self.country,
+            &self.country_code,
+        ];
+        for (key, value) in Self::FIELDS.iter().zip(values) {
+            set_string(&mut record, key, value);
+        }
+        set_owner(&mut record, "contact", contact_id);
+        record
+    }
+}
+
+/// A calendar events belong to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Calendar {
+    pub id: String,
+    pub title: Option<String>,
+}
+
+impl Calendar {
+    pub const ENTITY: &'static str = "com.apple.calendars.Calendar";
+
+    pub fn from_record(id: &str, record: &Plist) -> Self {
+        Calendar {
+            id: id.to_string(),
+            title: dict_string(record, "title"),
+        }
+    }
+
+    pub fn to_record(&self) -> Plist {
+        let mut record = new_record(Self::ENTITY);
+        set_string(&mut record, "title", &self.title);
+        record
+    }
+}
+
+/// An event in a calendar
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CalendarEvent {
+    pub id: String,
+    /// The identifier of the calendar the event belongs to
+    pub calendar_id: Option<String>,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub url: Option<String>,
+    pub start: Option<SystemTime>,
+    pub end: Option<SystemTime>,
+    pub all_day: bool,
+}
+
+impl CalendarEvent {
+    pub const ENTITY: &'static str = "com.apple.calendars.Event";
+
+    pub fn from_record(id: &str, record: &Plist) -> Self {
+        CalendarEvent {
+            id: id.to_string(),
+            calendar_id: string_array_field(record, "calendar").into_iter().next(),
+            summary: dict_string(record, "summary"),
+            description: dict_string(record, "description"),
+            location: dict_string(record, "location"),
+            url: dict_string(record, "url"),
+            start: dict_date(record, "start date"),
+            end: dict_date(record, "end date"),
+            all_day: dict_bool(record, "all day").unwrap_or(false),
+        }
+    }
+
+    pub fn to_record(&self) -> Plist {
+        let mut record = new_record(Self::ENTITY);
+        set_string(&mut record, "summary", &self.summary);
+        set_string(&mut record, "description", &self.description);
+        set_string(&mut record, "location", &self.location);
+        set_string(&mut record, "url", &self.url);
+        set_date(&mut record, "start date", self.start);
+        set_date(&mut record, "end date", self.end);
+        let _ = record.dict_set_item("all day", Plist::new_bool(self.all_day));
+        if let Some(calendar_id) = &self.calendar_id {
+            set_owner(&mut record, "calendar", calendar_id);
+        }
+        record
+    }
+}
+
+/// A Safari bookmark
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bookmark {
+    pub id: String,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    /// The identifier of the folder containing the bookmark
+    pub parent_id: Option<String>,
+}
+
+impl Bookmark {
+    pub const ENTITY: &'static str = "com.apple.bookmarks.Bookmark";
+
+    pub fn from_record(id: &str, record: &Plist) -> Self {
+        Bookmark {
+            id: id.to_string(),
+            title: dict_string(record, "name"),
+            url: dict_string(record, "url"),
+            parent_id: string_array_field(record, "parent").into_iter().next(),
+        }
+    }
+
+    pub fn to_record(&self) -> Plist {
+        let mut record = new_record(Self::ENTITY);
+        set_string(&mut record, "name", &self.title);
+        set_string(&mut record, "url", &self.url);
+        if let Some(parent_id) = &self.parent_id {
+            set_owner(&mut record, "parent", parent_id);
+        }
+        record
+    }
+}
+
+/// A folder of Safari bookmarks
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BookmarkFolder {
+    pub id: String,
+    pub title: Option<String>,
+    pub parent_id: Option<String>,
+}
+
+impl BookmarkFolder {
+    pub const ENTITY: &'static str = "com.apple.bookmarks.Folder";
+
+    pub fn from_record(id: &str, record: &Plist) -> Self {
+        BookmarkFolder {
+            id: id.to_string(),
+            title: dict_string(record, "name"),
+            parent_id: string_array_field(record, "parent").into_iter().next(),
+        }
+    }
+
+    pub fn to_record(&self) -> Plist {
+        let mut record = new_record(Self::ENTITY);
+        set_string(&mut record, "name", &self.title);
+        if let Some(parent_id) = &self.parent_id {
+            set_owner(&mut record, "parent", parent_id);
+        }
+        record
+    }
+}
+
+/// Drives a MobileSync session for a single data class
+pub struct SyncSession<'a> {
+    client: MobileSyncClient<'a>,
+    data_class: SyncDataClass,
+    sync_type: Option<MobileSyncType>,
+}
+
+impl<'a> SyncSession<'a> {
+    /// Starts the MobileSync service on the device
+    /// # Arguments
+    /// * `device` - The device to sync with
+    /// * `data_class` - The data class to sync
+    /// # Returns
+    /// A session that hasn't started syncing yet
+    ///
+    /// ***Verified:*** False
+    pub fn new(device: &'a Device, data_class: SyncDataClass) -> Result<Self, MobileSyncError> {
+        Ok(SyncSession {
+            client: MobileSyncClient::start_service(device, "sync_session")?,
+            data_class,
+            sync_type: None,
+        })
+    }
+
+    /// Asks the device to start syncing. The device may pick a different sync type than requested.
+    /// # Arguments
+    /// * `sync_type` - The type of sync to request
+    /// * `anchor` - The device anchor from the last sync and a new anchor for the host
+    /// # Returns
+    /// The sync type the device chose
+    ///
+    /// ***Verified:*** False
+    pub fn start(
+        &mut self,
+        sync_type: MobileSyncType,
+        anchor: MobileSyncAnchor,
+    ) -> Result<MobileSyncType, MobileSyncError> {
+        let (sync_type, device_version) = self
+            .client
+            .start_sync(
+                self.data_class.as_str(),
+                vec![anchor],
+                self.data_class.computer_version(),
+                sync_type,
+            )
+            .map_err(|(description, e)| {
+                warn!(
+                    "Device refused to sync {}: {}",
+                    self.data_class.as_str(),
+                    description
+                );
+                e
+            })?;
+        info!(
+            "Syncing {} with {:?} sync, device class version {}",
+            self.data_class.as_str(),
+            sync_type,
+            device_version
+        );
+        self.sync_type = Some(sync_type);
+        Ok(sync_type)
+    }
+
+    /// Gets the sync type the device chose, if the session has started
+    pub fn sync_type(&self) -> Option<MobileSyncType> {
+        self.sync_type
+    }
+
+    /// Receives the device's records. Slow and reset syncs send every record, fast syncs only changes.
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// The records sent by the device
+    ///
+    /// ***Verified:*** False
+    pub fn pull(&mut self) -> Result<SyncRecords, MobileSyncError> {
+        let (entities, mut more, _) = match self.sync_type {
+            Some(MobileSyncType::Fast) => self.client.get_changes_from_device()?,
+            Some(_) => self.client.get_all_records_from_device()?,
+            None => return Err(MobileSyncError::NotReady),
+        };
+
+        let mut records = SyncRecords::new();
+        records.merge(&entities);
+        self.client.acknowledge_changes_from_device()?;
+        while more {
+            let (entities, has_more, _) = self.client.receive_changes()?;
+            records.merge(&entities);
+            self.client.acknowledge_changes_from_device()?;
+            more = has_more;
+        }
+        Ok(records)
+    }
+
+    /// Sends records from the host to the device
+    /// # Arguments
+    /// * `changes` - The records to create or update
+    /// # Returns
+    /// The identifiers the device assigned, keyed by the identifiers the host used
+    ///
+    /// ***Verified:*** False
+    pub fn push(
+        &mut self,
+        changes: &SyncRecords,
+    ) -> Result<HashMap<String, String>, MobileSyncError> {
+        if self.sync_type.is_none() {
+            return Err(MobileSyncError::NotReady);
+        }
+        self.client.ready_to_send_changes_from_computer()?;
+
+        let names = changes.entity_names();
+        let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+        let mut actions = MobileSyncActions::new();
+        actions.set_entity_names(&names);
+        actions.set_all_records_of_pulled_entity_type_sent(false);
+        self.client
+            .send_changes(changes.to_plist(), true, Some(actions.into_plist()))?;
+
+        let mut remapped = HashMap::new();
+        if let Some(mapping) = self.client.receive_remapped_identifiers()? {
+            for (host_id, device_id) in dict_entries(&mapping) {
+                if let Ok(device_id) = device_id.get_string_val() {
+                    remapped.insert(host_id, device_id);
+                }
+            }
+        }
+        Ok(remapped)
+    }
+
+    /// Ends the sync, committing it on the device
+    /// # Arguments
+    /// *none*
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn finish(self) -> Result<(), MobileSyncError> {
+        self.client.finish()
+    }
+
+    /// Aborts the sync
+    /// # Arguments
+    /// * `reason` - Why the sync was cancelled
+    /// # Returns
+    /// *none*
+    ///
+    /// ***Verified:*** False
+    pub fn cancel(self, reason: impl Into<String>) -> Result<(), MobileSyncError> {
+        self.client.cancel(reason)
+    }
+}
+
+fn new_record(entity: &str) -> Plist {
+    let mut record = Plist::new_dict();
+    let _ = record.dict_set_item(ENTITY_NAME_KEY, Plist::new_string(entity));
+    record
+}
+
+fn set_string(record: &mut Plist, key: &str, value: &Option<String>) {
+    if let Some(value) = value {
+        let _ = record.dict_set_item(key, Plist::new_string(value));
+    }
+}
+
+fn set_date(record: &mut Plist, key: &str, value: Option<SystemTime>) {
+    if let Some(value) = value {
+        let _ = record.dict_set_item(key, new_date(value));
+    }
+}
+
+/// Links a child record to its owner, which MobileSync stores as an array of identifiers
+fn set_owner(record: &mut Plist, key: &str, owner: &str) {
+    let mut owners = Plist::new_array();
+    let _ = owners.array_append_item(Plist::new_string(owner));
+    let _ = record.dict_set_item(key, owners);
+}
+
+fn string_array_field(record: &Plist, key: &str) -> Vec<String> {
+    match record.dict_get_item(key) {
+        Ok(item) if item.plist_type == PlistType::Array => string_array(&item),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn contact_birthday_before_2001_round_trips() {
+        let contact = Contact {
+            id: "contact-1".to_string(),
+            first_name: Some("Ada".to_string()),
+            // 15/06/1985
+            birthday: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(487_641_600)),
+            ..Default::default()
+        };
+        let records = contact.to_records();
+        let (id, record) = &records[0];
+        assert_eq!(Contact::from_record(id, record), contact);
+    }
+
+    #[test]
+    fn event_dates_before_1970_round_trip() {
+        // 01/03/1965 12:30 to 13:30
+        let start = SystemTime::UNIX_EPOCH - Duration::from_secs(152_623_800);
+        let event = CalendarEvent {
+            id: "event-1".to_string(),
+            summary: Some("Launch".to_string()),
+            start: Some(start),
+            end: Some(start + Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let record = event.to_record();
+        assert_eq!(CalendarEvent::from_record("event-1", &record), event);
+    }
+}
@@ -0,0 +1,490 @@
+// jkcoxson
+// A minimal read-only SQLite reader, enough to walk the tables of a backup's Manifest.db
+
+use std::{collections::HashSet, fmt, path::Path};
+
+/// A value stored in a column
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl Value {
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_blob(&self) -> Option<&[u8]> {
+        match self {
+            Value::Blob(blob) => Some(blob),
+            _ => None,
+        }
+    }
+}
+
+/// Why a database couldn't be read
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SqliteError {
+    Io(String),
+    NotADatabase,
+    Corrupt,
+    TableNotFound,
+}
+
+impl fmt::Display for SqliteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SqliteError::Io(e) => write!(f, "IO error: {}", e),
+            SqliteError::NotADatabase => f.write_str("Not a SQLite database"),
+            SqliteError::Corrupt => f.write_str("Database is corrupt"),
+            SqliteError::TableNotFound => f.write_str("Table not found"),
+        }
+    }
+}
+
+/// The rows of a table along with its column names
+pub(crate) struct Table {
+    pub(crate) columns: Vec<String>,
+    pub(crate) rows: Vec<Vec<Value>>,
+}
+
+impl Table {
+    /// Gets the position of a column by name
+    pub(crate) fn column(&self, name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case(name))
+    }
+}
+
+/// An SQLite database loaded into memory
+pub(crate) struct Database {
+    data: Vec<u8>,
+    page_size: usize,
+    usable_size: usize,
+}
+
+const HEADER_MAGIC: &[u8] = b"SQLite format 3\0";
+
+/// How deep a table's b-tree may go before it's treated as corrupt, the same limit SQLite uses
+const MAX_DEPTH: usize = 20;
+
+impl Database {
+    pub(crate) fn open(path: &Path) -> Result<Self, SqliteError> {
+        let data = std::fs::read(path).map_err(|e| SqliteError::Io(e.to_string()))?;
+        Self::from_bytes(data)
+    }
+
+    pub(crate) fn from_bytes(data: Vec<u8>) -> Result<Self, SqliteError> {
+        if data.len() < 100 || !data.starts_with(HEADER_MAGIC) {
+            return Err(SqliteError::NotADatabase);
+        }
+        let page_size = match u16::from_be_bytes([data[16], data[17]]) {
+            1 => 65536,
+            size => size as usize,
+        };
+        if page_size < 512 || !page_size.is_power_of_two() {
+            return Err(SqliteError::NotADatabase);
+        }
+        let usable_size = page_size - data[20] as usize;
+        Ok(Database {
+            data,
+            page_size,
+            usable_size,
+        })
+    }
+
+    /// Reads every row of a table
+    pub(crate) fn table(&self, name: &str) -> Result<Table, SqliteError> {
+        let mut schema = Vec::new();
+        self.walk_table(1, &mut schema)?;
+        for row in schema {
+            let is_table = row.first().and_then(Value::as_str) == Some("table");
+            let matches = row
+                .get(1)
+                .and_then(Value::as_str)
+                .is_some_and(|table| table.eq_ignore_ascii_case(name));
+            if !is_table || !matches {
+                continue;
+            }
+            let root = row
+                .get(3)
+                .and_then(Value::as_integer)
+                .ok_or(SqliteError::Corrupt)?;
+            let columns = row
+                .get(4)
+                .and_then(Value::as_str)
+                .map(column_names)
+                .unwrap_or_default();
+            let mut rows = Vec::new();
+            self.walk_table(root as usize, &mut rows)?;
+            return Ok(Table { columns, rows });
+        }
+        Err(SqliteError::TableNotFound)
+    }
+
+    fn page(&self, number: usize) -> Result<&[u8], SqliteError> {
+        let start = number
+            .checked_sub(1)
+            .ok_or(SqliteError::Corrupt)?
+            .checked_mul(self.page_size)
+            .ok_or(SqliteError::Corrupt)?;
+        slice(&self.data, start, self.page_size)
+    }
+
+    fn walk_table(&self, root: usize, rows: &mut Vec<Vec<Value>>) -> Result<(), SqliteError> {
+        self.walk_page(root, 0, &mut HashSet::new(), rows)
+    }
+
+    /// Reads the rows under a b-tree page, refusing to visit a page twice so a corrupt
+    /// database can't send it around in circles
+    fn walk_page(
+        &self,
+        number: usize,
+        depth: usize,
+        visited: &mut HashSet<usize>,
+        rows: &mut Vec<Vec<Value>>,
+    ) -> Result<(), SqliteError> {
+        if depth > MAX_DEPTH || !visited.insert(number) {
+            return Err(SqliteError::Corrupt);
+        }
+        let page = self.page(number)?;
+        // The first page starts with the database header
+        let header = if number == 1 { 100 } else { 0 };
+        let kind = *page.get(header).ok_or(SqliteError::Corrupt)?;
+        let cells = read_u16(page, header + 3)? as usize;
+        let (pointers, interior) = match kind {
+            0x0d => (header + 8, false),
+            0x05 => (header + 12, true),
+            _ => return Err(SqliteError::Corrupt),
+        };
+
+        for i in 0..cells {
+            let offset = read_u16(page, pointers + i * 2)? as usize;
+            if interior {
+                let child = read_u32(page, offset)? as usize;
+                self.walk_page(child, depth + 1, visited, rows)?;
+            } else {
+                let (payload_size, used) = read_varint(page, offset)?;
+                let (_rowid, rowid_used) = read_varint(page, offset + used)?;
+                let payload_size =
+                    usize::try_from(payload_size).map_err(|_| SqliteError::Corrupt)?;
+                let payload = self.payload(page, offset + used + rowid_used, payload_size)?;
+                rows.push(parse_record(&payload)?);
+            }
+        }
+
+        if interior {
+            let right = read_u32(page, header + 8)? as usize;
+            self.walk_page(right, depth + 1, visited, rows)?;
+        }
+        Ok(())
+    }
+
+    /// Gathers a cell's payload, following overflow pages if it doesn't fit in the page
+    fn payload(&self, page: &[u8], start: usize, size: usize) -> Result<Vec<u8>, SqliteError> {
+        let max_local = self.usable_size - 35;
+        if size <= max_local {
+            return slice(page, start, size).map(|payload| payload.to_vec());
+        }
+        // Nothing can hold more than the whole file, however long its overflow chain claims to be
+        if size > self.data.len() {
+            return Err(SqliteError::Corrupt);
+        }
+
+        let min_local = (self.usable_size - 12) * 32 / 255 - 23;
+        let mut local = min_local + (size - min_local) % (self.usable_size - 4);
+        if local > max_local {
+            local = min_local;
+        }
+        let mut payload = slice(page, start, local)?.to_vec();
+        let mut next = read_u32(page, start + local)? as usize;
+        let mut visited = HashSet::new();
+        while payload.len() < size {
+            if !visited.insert(next) {
+                return Err(SqliteError::Corrupt);
+            }
+            let overflow = self.page(next)?;
+            next = read_u32(overflow, 0)? as usize;
+            let take = (size - payload.len()).min(self.usable_size - 4);
+            payload.extend_from_slice(slice(overflow, 4, take)?);
+        }
+        Ok(payload)
+    }
+}
+
+fn parse_record(payload: &[u8]) -> Result<Vec<Value>, SqliteError> {
+    let (header_size, mut header_offset) = read_varint(payload, 0)?;
+    let mut body_offset = usize::try_from(header_size).map_err(|_| SqliteError::Corrupt)?;
+    let mut values = Vec::new();
+    while header_offset < header_size as usize {
+        let (serial, used) = read_varint(payload, header_offset)?;
+        header_offset += used;
+        let (value, len) = match serial {
+            0 => (Value::Null, 0),
+            1..=6 => {
+                let len = [1, 2, 3, 4, 6, 8][serial as usize - 1];
+                let bytes = slice(payload, body_offset, len)?;
+                // Sign extend from the first byte
+                let mut value: i64 = if bytes[0] & 0x80 != 0 { -1 } else { 0 };
+                for byte in bytes {
+                    value = (value << 8) | *byte as i64;
+                }
+                (Value::Integer(value), len)
+            }
+            7 => {
+                let bytes = slice(payload, body_offset, 8)?;
+                let mut buffer = [0; 8];
+                buffer.copy_from_slice(bytes);
+                (Value::Real(f64::from_be_bytes(buffer)), 8)
+            }
+            8 => (Value::Integer(0), 0),
+            9 => (Value::Integer(1), 0),
+            serial if serial >= 12 => {
+                let len = usize::try_from((serial - 12) / 2).map_err(|_| SqliteError::Corrupt)?;
+                let bytes = slice(payload, body_offset, len)?;
+                if serial % 2 == 0 {
+                    (Value::Blob(bytes.to_vec()), len)
+                } else {
+                    (
+                        Value::Text(String::from_utf8_lossy(bytes).into_owned()),
+                        len,
+                    )
+                }
+            }
+            _ => return Err(SqliteError::Corrupt),
+        };
+        body_offset += len;
+        values.push(value);
+    }
+    Ok(values)
+}
+
+/// Pulls the column names out of a CREATE TABLE statement
+fn column_names(sql: &str) -> Vec<String> {
+    let (start, end) = match (sql.find('('), sql.rfind(')')) {
+        (Some(start), Some(end)) if start < end => (start, end),
+        _ => return Vec::new(),
+    };
+
+    let mut definitions = Vec::new();
+    let mut depth = 0;
+    let mut current = String::new();
+    for c in sql[start + 1..end].chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                definitions.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    definitions.push(current);
+
+    definitions
+        .iter()
+        .filter_map(|definition| definition.split_whitespace().next())
+        .filter(|name| {
+            !["PRIMARY", "UNIQUE", "CHECK", "FOREIGN", "CONSTRAINT"]
+                .iter()
+                .any(|keyword| name.eq_ignore_ascii_case(keyword))
+        })
+        .map(|name| {
+            name.trim_matches(|c| matches!(c, '"' | '`' | '[' | ']'))
+                .to_string()
+        })
+        .collect()
+}
+
+/// Gets `len` bytes at `start`, without overflowing on lengths read from a corrupt file
+fn slice(data: &[u8], start: usize, len: usize) -> Result<&[u8], SqliteError> {
+    let end = start.checked_add(len).ok_or(SqliteError::Corrupt)?;
+    data.get(start..end).ok_or(SqliteError::Corrupt)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, SqliteError> {
+    let bytes = slice(data, offset, 2)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, SqliteError> {
+    let bytes = slice(data, offset, 4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Reads a big endian variable length integer, returning it and how many bytes it used
+fn read_varint(data: &[u8], offset: usize) -> Result<(u64, usize), SqliteError> {
+    let mut value: u64 = 0;
+    for i in 0..9 {
+        let byte = *data.get(offset + i).ok_or(SqliteError::Corrupt)?;
+        if i == 8 {
+            return Ok(((value << 8) | byte as u64, 9));
+        }
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &[u8] = include_bytes!("../tests/fixtures/Manifest.db");
+    const PAGE: usize = 512;
+
+    fn large_blob() -> Vec<u8> {
+        (0..=255).cycle().take(2048).collect()
+    }
+
+    #[test]
+    fn reads_manifest_files() {
+        let database = Database::from_bytes(MANIFEST.to_vec()).unwrap();
+        let files = database.table("Files").unwrap();
+        assert_eq!(
+            files.columns,
+            ["fileID", "domain", "relativePath", "flags", "file"]
+        );
+        assert_eq!(files.rows.len(), 41);
+
+        let path = files.column("relativePath").unwrap();
+        let first = &files.rows[0];
+        assert_eq!(first[0].as_str(), Some("0".repeat(40).as_str()));
+        assert_eq!(first[path].as_str(), Some("Library/File0"));
+        assert_eq!(first[files.column("flags").unwrap()].as_integer(), Some(1));
+        assert_eq!(first[files.column("file").unwrap()], Value::Null);
+    }
+
+    #[test]
+    fn follows_overflow_pages() {
+        let database = Database::from_bytes(MANIFEST.to_vec()).unwrap();
+        let files = database.table("Files").unwrap();
+        let large = files
+            .rows
+            .iter()
+            .find(|row| row[2].as_str() == Some("Library/Large"))
+            .unwrap();
+        assert_eq!(large[4].as_blob(), Some(large_blob().as_slice()));
+    }
+
+    #[test]
+    fn missing_table() {
+        let database = Database::from_bytes(MANIFEST.to_vec()).unwrap();
+        assert_eq!(
+            database.table("Properties").err(),
+            Some(SqliteError::TableNotFound)
+        );
+    }
+
+    #[test]
+    fn rejects_non_databases() {
+        assert_eq!(
+            Database::from_bytes(vec![0; 1024]).err(),
+            Some(SqliteError::NotADatabase)
+        );
+    }
+
+    #[test]
+    fn rejects_page_cycles() {
+        // Point the Files root's right child back at the root itself
+        let mut data = MANIFEST.to_vec();
+        let root = PAGE;
+        assert_eq!(data[root], 0x05);
+        data[root + 8..root + 12].copy_from_slice(&2u32.to_be_bytes());
+        let database = Database::from_bytes(data).unwrap();
+        assert_eq!(database.table("Files").err(), Some(SqliteError::Corrupt));
+    }
+
+    #[test]
+    fn rejects_overflow_cycles() {
+        // Make every overflow page link back to itself
+        let mut data = MANIFEST.to_vec();
+        let mut overflow_pages = 0;
+        for number in 2..=data.len() / PAGE {
+            let start = (number - 1) * PAGE;
+            if data[start] == 0x00 && data[start + 1] == 0x00 && number > 2 {
+                let next = u32::from_be_bytes(data[start..start + 4].try_into().unwrap());
+                if next != 0 {
+                    data[start..start + 4].copy_from_slice(&(number as u32).to_be_bytes());
+                    overflow_pages += 1;
+                }
+            }
+        }
+        assert!(overflow_pages > 0);
+        let database = Database::from_bytes(data).unwrap();
+        assert_eq!(database.table("Files").err(), Some(SqliteError::Corrupt));
+    }
+
+    #[test]
+    fn rejects_oversized_payloads() {
+        let database = Database::from_bytes(MANIFEST.to_vec()).unwrap();
+        let page = database.page(1).unwrap();
+        assert_eq!(
+            database.payload(page, 100, usize::MAX).err(),
+            Some(SqliteError::Corrupt)
+        );
+        assert_eq!(
+            database.payload(page, usize::MAX, 10).err(),
+            Some(SqliteError::Corrupt)
+        );
+    }
+
+    #[test]
+    fn parses_record_types() {
+        // Header: size 6, then a null, an int8, a float, a 1 and a 3 byte text
+        let record = [
+            6, 0, 1, 7, 9, 19, 0xfe, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0, b'a', b'b', b'c',
+        ];
+        assert_eq!(
+            parse_record(&record).unwrap(),
+            [
+                Value::Null,
+                Value::Integer(-2),
+                Value::Real(1.5),
+                Value::Integer(1),
+                Value::Text("abc".into()),
+            ]
+        );
+        assert_eq!(
+            parse_record(&record[..10]).err(),
+            Some(SqliteError::Corrupt)
+        );
+    }
+
+    #[test]
+    fn column_names_skip_constraints() {
+        assert_eq!(
+            column_names(
+                "CREATE TABLE t (\"a\" TEXT, b DECIMAL(10, 2), [c] BLOB, PRIMARY KEY (a, b))"
+            ),
+            ["a", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn varints() {
+        assert_eq!(read_varint(&[0x7f], 0).unwrap(), (0x7f, 1));
+        assert_eq!(read_varint(&[0x81, 0x00], 0).unwrap(), (0x80, 2));
+        assert_eq!(read_varint(&[0xff; 9], 0).unwrap(), (u64::MAX, 9));
+        assert_eq!(read_varint(&[0x81], 0).err(), Some(SqliteError::Corrupt));
+    }
+}
@@ -0,0 +1,1263 @@
+// jkcoxson
+// Converts MobileSync contacts to and from vCard, and calendars to and from iCalendar
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    error::SyncFormatError,
+    services::sync_session::{
+        Calendar, CalendarEvent, Contact, ContactAddress, ContactField, SyncRecords,
+    },
+};
+
+const PRODID: &str = "-//rusty_libimobiledevice//MobileSync//EN";
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// The vCard version to write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VCardVersion {
+    V3,
+    V4,
+}
+
+impl VCardVersion {
+    fn as_str(&self) -> &'static str {
+        match self {
+            VCardVersion::V3 => "3.0",
+            VCardVersion::V4 => "4.0",
+        }
+    }
+
+    /// 3.0 cards conventionally use upper case parameters, 4.0 cards lower case
+    fn case(&self, value: &str) -> String {
+        match self {
+            VCardVersion::V3 => value.to_uppercase(),
+            VCardVersion::V4 => value.to_lowercase(),
+        }
+    }
+}
+
+/// Writes a contact as a vCard
+/// # Arguments
+/// * `contact` - The contact to convert
+/// * `version` - The vCard version to write
+/// # Returns
+/// The vCard, with CRLF line endings
+pub fn contact_to_vcard(contact: &Contact, version: VCardVersion) -> String {
+    let mut out = String::new();
+    push_line(&mut out, "BEGIN:VCARD");
+    push_line(&mut out, &format!("VERSION:{}", version.as_str()));
+    push_line(&mut out, &format!("UID:{}", escape(&contact.id)));
+    push_line(
+        &mut out,
+        &format!(
+            "N:{}",
+            join_components(&[
+                &contact.last_name,
+                &contact.first_name,
+                &contact.middle_name,
+                &contact.prefix,
+                &contact.suffix,
+            ])
+        ),
+    );
+    push_line(&mut out, &format!("FN:{}", escape(&contact.display_name())));
+    push_property(&mut out, "NICKNAME", &contact.nickname);
+    if contact.organization.is_some() || contact.department.is_some() {
+        push_line(
+            &mut out,
+            &format!(
+                "ORG:{}",
+                join_components(&[&contact.organization, &contact.department])
+            ),
+        );
+    }
+    push_property(&mut out, "TITLE", &contact.job_title);
+    push_property(&mut out, "NOTE", &contact.notes);
+    if let Some(birthday) = contact.birthday {
+        let separated = version == VCardVersion::V3;
+        push_line(
+            &mut out,
+            &format!("BDAY:{}", format_date(birthday, separated)),
+        );
+    }
+
+    // Labels and country codes are tied to their property with a group, as Apple's exporter does
+    let mut groups = 0;
+    let mut next_group = || {
+        groups += 1;
+        format!("item{}.", groups)
+    };
+    let fields = [
+        ("TEL", &contact.phone_numbers),
+        ("EMAIL", &contact.emails),
+        ("URL", &contact.urls),
+    ];
+    for (name, fields) in fields {
+        for field in fields {
+            let (mut types, label) = vcard_types(field.kind.as_deref(), field.label.as_deref());
+            if name == "EMAIL" && version == VCardVersion::V3 {
+                types.insert(0, "internet");
+            }
+            let group = label.map(|_| next_group()).unwrap_or_default();
+            push_line(
+                &mut out,
+                &format!(
+                    "{}{}{}:{}",
+                    group,
+                    name,
+                    type_param(&types, version),
+                    escape(&field.value)
+                ),
+            );
+            if let Some(label) = label {
+                push_line(&mut out, &format!("{}X-ABLabel:{}", group, escape(label)));
+            }
+        }
+    }
+    for address in &contact.addresses {
+        let (types, label) = vcard_types(address.kind.as_deref(), address.label.as_deref());
+        let group = if label.is_some() || address.country_code.is_some() {
+            next_group()
+        } else {
+            String::new()
+        };
+        push_line(
+            &mut out,
+            &format!(
+                "{}ADR{}:;;{}",
+                group,
+                type_param(&types, version),
+                join_components(&[
+                    &address.street,
+                    &address.city,
+                    &address.state,
+                    &address.postal_code,
+                    &address.country,
+                ])
+            ),
+        );
+        if let Some(label) = label {
+            push_line(&mut out, &format!("{}X-ABLabel:{}", group, escape(label)));
+        }
+        if let Some(country_code) = &address.country_code {
+            push_line(
+                &mut out,
+                &format!("{}X-ABADR:{}", group, escape(country_code)),
+            );
+        }
+    }
+    push_line(&mut out, "END:VCARD");
+    out
+}
+
+/// Writes several contacts into one vCard file
+/// # Arguments
+/// * `contacts` - The contacts to convert
+/// * `version` - The vCard version to write
+/// # Returns
+/// The cards, one after another
+pub fn contacts_to_vcard(contacts: &[Contact], version: VCardVersion) -> String {
+    contacts
+        .iter()
+        .map(|contact| contact_to_vcard(contact, version))
+        .collect()
+}
+
+/// Reads the contacts in a vCard file. Both 3.0 and 4.0 cards are accepted.
+/// Cards without a UID are given an identifier from their position in the file.
+/// # Arguments
+/// * `text` - The contents of the vCard file
+/// # Returns
+/// The contacts in the file
+pub fn contacts_from_vcard(text: &str) -> Result<Vec<Contact>, SyncFormatError> {
+    let mut contacts = Vec::new();
+    let mut card: Option<Vec<ContentLine>> = None;
+    for line in unfold(text) {
+        let line = parse_line(&line).ok_or(SyncFormatError::InvalidVCard)?;
+        match (line.name.as_str(), &mut card) {
+            ("BEGIN", None) if line.value.eq_ignore_ascii_case("VCARD") => card = Some(Vec::new()),
+            ("END", Some(_)) if line.value.eq_ignore_ascii_case("VCARD") => {
+                let lines = card.take().unwrap_or_default();
+                contacts.push(contact_from_lines(&lines, contacts.len()));
+            }
+            (_, Some(lines)) if line.name != "BEGIN" && line.name != "END" => lines.push(line),
+            _ => return Err(SyncFormatError::InvalidVCard),
+        }
+    }
+    if card.is_some() {
+        return Err(SyncFormatError::InvalidVCard);
+    }
+    Ok(contacts)
+}
+
+/// Writes a calendar and its events as an iCalendar file. Event times are written in UTC.
+/// # Arguments
+/// * `calendar` - The calendar to convert
+/// * `events` - The events to include
+/// # Returns
+/// The iCalendar file, with CRLF line endings
+pub fn calendar_to_icalendar(calendar: &Calendar, events: &[CalendarEvent]) -> String {
+    let mut out = String::new();
+    push_line(&mut out, "BEGIN:VCALENDAR");
+    push_line(&mut out, "VERSION:2.0");
+    push_line(&mut out, &format!("PRODID:{}", PRODID));
+    push_line(&mut out, "CALSCALE:GREGORIAN");
+    push_property(&mut out, "X-WR-CALNAME", &calendar.title);
+    push_line(&mut out, &format!("X-WR-RELCALID:{}", escape(&calendar.id)));
+    for event in events {
+        push_line(&mut out, "BEGIN:VEVENT");
+        push_line(&mut out, &format!("UID:{}", escape(&event.id)));
+        // DTSTAMP is required, use the start so the output only depends on the event
+        let stamp = event.start.unwrap_or(SystemTime::UNIX_EPOCH);
+        push_line(&mut out, &format!("DTSTAMP:{}", format_date_time(stamp)));
+        for (name, time) in [("DTSTART", event.start), ("DTEND", event.end)] {
+            let time = match time {
+                Some(time) => time,
+                None => continue,
+            };
+            if event.all_day {
+                push_line(
+                    &mut out,
+                    &format!("{};VALUE=DATE:{}", name, format_date(time, false)),
+                );
+            } else {
+                push_line(&mut out, &format!("{}:{}", name, format_date_time(time)));
+            }
+        }
+        push_property(&mut out, "SUMMARY", &event.summary);
+        push_property(&mut out, "DESCRIPTION", &event.description);
+        push_property(&mut out, "LOCATION", &event.location);
+        if let Some(url) = &event.url {
+            // URL is a URI value, which isn't escaped
+            push_line(&mut out, &format!("URL:{}", url));
+        }
+        push_line(&mut out, "END:VEVENT");
+    }
+    push_line(&mut out, "END:VCALENDAR");
+    out
+}
+
+/// Reads the first calendar in an iCalendar file.
+/// Times with a TZID are read as UTC, since no time zone database is available.
+/// Components other than events, such as alarms and time zones, are skipped.
+/// # Arguments
+/// * `text` - The contents of the iCalendar file
+/// # Returns
+/// The calendar and its events, which refer to the calendar by its identifier
+pub fn calendar_from_icalendar(
+    text: &str,
+) -> Result<(Calendar, Vec<CalendarEvent>), SyncFormatError> {
+    let mut calendar: Option<Calendar> = None;
+    let mut events = Vec::new();
+    let mut event: Option<(CalendarEvent, Option<Duration>)> = None;
+    let mut components: Vec<String> = Vec::new();
+
+    for line in unfold(text) {
+        let line = parse_line(&line).ok_or(SyncFormatError::InvalidICalendar)?;
+        match line.name.as_str() {
+            "BEGIN" => {
+                let component = line.value.to_uppercase();
+                match (component.as_str(), components.last().map(|c| c.as_str())) {
+                    ("VCALENDAR", None) => {
+                        calendar = Some(Calendar {
+                            id: "icalendar".to_string(),
+                            title: None,
+                        })
+                    }
+                    ("VEVENT", Some("VCALENDAR")) => {
+                        event = Some((
+                            CalendarEvent {
+                                id: format!("icalendar-event-{}", events.len()),
+                                ..Default::default()
+                            },
+                            None,
+                        ))
+                    }
+                    (_, None) => return Err(SyncFormatError::InvalidICalendar),
+                    _ => {}
+                }
+                components.push(component);
+            }
+            "END" => {
+                let component = components.pop().ok_or(SyncFormatError::InvalidICalendar)?;
+                if !component.eq_ignore_ascii_case(&line.value) {
+                    return Err(SyncFormatError::InvalidICalendar);
+                }
+                match component.as_str() {
+                    "VEVENT" if components.last().map(|c| c.as_str()) == Some("VCALENDAR") => {
+                        if let Some((mut finished, duration)) = event.take() {
+                            if let (None, Some(start), Some(duration)) =
+                                (finished.end, finished.start, duration)
+                            {
+                                finished.end = Some(start + duration);
+                            }
+                            events.push(finished);
+                        }
+                    }
+                    "VCALENDAR" => break,
+                    _ => {}
+                }
+            }
+            _ => match (
+                components.last().map(|c| c.as_str()),
+                &mut event,
+                &mut calendar,
+            ) {
+                (Some("VEVENT"), Some((event, duration)), _) => {
+                    apply_event_property(event, duration, &line)?
+                }
+                (Some("VCALENDAR"), _, Some(calendar)) => match line.name.as_str() {
+                    "X-WR-CALNAME" => calendar.title = Some(unescape(&line.value)),
+                    "X-WR-RELCALID" => calendar.id = unescape(&line.value),
+                    _ => {}
+                },
+                _ => {}
+            },
+        }
+    }
+
+    let calendar = calendar.ok_or(SyncFormatError::InvalidICalendar)?;
+    if !components.is_empty() {
+        return Err(SyncFormatError::InvalidICalendar);
+    }
+    for event in &mut events {
+        event.calendar_id = Some(calendar.id.clone());
+    }
+    Ok((calendar, events))
+}
+
+/// Writes every contact in a set of records into one vCard file
+/// # Arguments
+/// * `records` - Records received from the device
+/// * `version` - The vCard version to write
+/// # Returns
+/// The cards, one after another
+pub fn records_to_vcard(records: &SyncRecords, version: VCardVersion) -> String {
+    contacts_to_vcard(&records.contacts(), version)
+}
+
+/// Writes each calendar in a set of records, with its events, as an iCalendar file.
+/// Events whose calendar isn't in the records are left out.
+/// # Arguments
+/// * `records` - Records received from the device
+/// # Returns
+/// One iCalendar file per calendar
+pub fn records_to_icalendar(records: &SyncRecords) -> Vec<String> {
+    let events = records.events();
+    records
+        .calendars()
+        .iter()
+        .map(|calendar| {
+            let events: Vec<CalendarEvent> = events
+                .iter()
+                .filter(|event| event.calendar_id.as_deref() == Some(calendar.id.as_str()))
+                .cloned()
+                .collect();
+            calendar_to_icalendar(calendar, &events)
+        })
+        .collect()
+}
+
+/// Reads a vCard file into records that can be pushed to the device
+/// # Arguments
+/// * `text` - The contents of the vCard file
+/// # Returns
+/// The contact records and their child records
+pub fn records_from_vcard(text: &str) -> Result<SyncRecords, SyncFormatError> {
+    let mut records = SyncRecords::new();
+    for contact in contacts_from_vcard(text)? {
+        records.add_contact(&contact);
+    }
+    Ok(records)
+}
+
+/// Reads an iCalendar file into records that can be pushed to the device
+/// # Arguments
+/// * `text` - The contents of the iCalendar file
+/// # Returns
+/// The calendar record and its event records
+pub fn records_from_icalendar(text: &str) -> Result<SyncRecords, SyncFormatError> {
+    let (calendar, events) = calendar_from_icalendar(text)?;
+    let mut records = SyncRecords::new();
+    records.add_calendar(&calendar);
+    for event in &events {
+        records.add_event(event);
+    }
+    Ok(records)
+}
+
+fn contact_from_lines(lines: &[ContentLine], index: usize) -> Contact {
+    let mut labels = HashMap::new();
+    let mut country_codes = HashMap::new();
+    for line in lines {
+        if let Some(group) = &line.group {
+            match line.name.as_str() {
+                "X-ABLABEL" => {
+                    labels.insert(group.clone(), unescape(&line.value));
+                }
+                "X-ABADR" => {
+                    country_codes.insert(group.clone(), unescape(&line.value));
+                }
+                _ => {}
+            }
+        }
+    }
+    let label_of = |line: &ContentLine| {
+        line.group
+            .as_ref()
+            .and_then(|group| labels.get(group))
+            .map(|label| strip_label(label))
+    };
+
+    let mut contact = Contact {
+        id: format!("vcard-{}", index),
+        ..Default::default()
+    };
+    let mut formatted_name = None;
+    for line in lines {
+        match line.name.as_str() {
+            "UID" => contact.id = unescape(&line.value),
+            "N" => {
+                let mut parts = split_components(&line.value).into_iter();
+                contact.last_name = parts.next().flatten();
+                contact.first_name = parts.next().flatten();
+                contact.middle_name = parts.next().flatten();
+                contact.prefix = parts.next().flatten();
+                contact.suffix = parts.next().flatten();
+            }
+            "FN" => formatted_name = non_empty(unescape(&line.value)),
+            "NICKNAME" => contact.nickname = non_empty(unescape(&line.value)),
+            "ORG" => {
+                let mut parts = split_components(&line.value).into_iter();
+                contact.organization = parts.next().flatten();
+                contact.department = parts.next().flatten();
+            }
+            "TITLE" => contact.job_title = non_empty(unescape(&line.value)),
+            "NOTE" => contact.notes = non_empty(unescape(&line.value)),
+            "BDAY" => contact.birthday = parse_date_time(&line.value).map(|(time, _)| time),
+            "TEL" | "EMAIL" | "URL" => {
+                let mut value = unescape(&line.value);
+                if line.name == "TEL" {
+                    if let Some(number) = value.strip_prefix("tel:") {
+                        value = number.to_string();
+                    }
+                }
+                let (kind, label) = sync_kind(&line.types(), label_of(line));
+                let field = ContactField {
+                    id: None,
+                    kind,
+                    label,
+                    value,
+                };
+                match line.name.as_str() {
+                    "TEL" => contact.phone_numbers.push(field),
+                    "EMAIL" => contact.emails.push(field),
+                    _ => contact.urls.push(field),
+                }
+            }
+            "ADR" => {
+                // The post office box and extended address aren't synced
+                let parts = split_components(&line.value);
+                let part = |i: usize| parts.get(i).cloned().flatten();
+                let (kind, label) = sync_kind(&line.types(), label_of(line));
+                contact.addresses.push(ContactAddress {
+                    id: None,
+                    kind,
+                    label,
+                    street: part(2),
+                    city: part(3),
+                    state: part(4),
+                    postal_code: part(5),
+                    country: part(6),
+                    country_code: line
+                        .group
+                        .as_ref()
+                        .and_then(|group| country_codes.get(group))
+                        .cloned(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    // Cards without a structured name still need something to show
+    if contact.first_name.is_none() && contact.last_name.is_none() && contact.organization.is_none()
+    {
+        contact.first_name = formatted_name;
+    }
+    contact
+}
+
+fn apply_event_property(
+    event: &mut CalendarEvent,
+    duration: &mut Option<Duration>,
+    line: &ContentLine,
+) -> Result<(), SyncFormatError> {
+    match line.name.as_str() {
+        "UID" => event.id = unescape(&line.value),
+        "SUMMARY" => event.summary = non_empty(unescape(&line.value)),
+        "DESCRIPTION" => event.description = non_empty(unescape(&line.value)),
+        "LOCATION" => event.location = non_empty(unescape(&line.value)),
+        "URL" => event.url = non_empty(line.value.clone()),
+        "DTSTART" | "DTEND" => {
+            let (time, date_only) =
+                parse_date_time(&line.value).ok_or(SyncFormatError::InvalidDate)?;
+            let date_only = date_only
+                || line
+                    .param("VALUE")
+                    .is_some_and(|value| value.eq_ignore_ascii_case("DATE"));
+            if line.name == "DTSTART" {
+                event.start = Some(time);
+                event.all_day = date_only;
+            } else {
+                event.end = Some(time);
+            }
+        }
+        "DURATION" => {
+            *duration = Some(parse_duration(&line.value).ok_or(SyncFormatError::InvalidDate)?)
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Maps a MobileSync field type to vCard TYPE values.
+/// Types vCard has no name for are kept as the label when there isn't one already.
+fn vcard_types<'a>(
+    kind: Option<&'a str>,
+    label: Option<&'a str>,
+) -> (Vec<&'static str>, Option<&'a str>) {
+    let types = match kind.map(|kind| kind.to_lowercase()).as_deref() {
+        None => return (Vec::new(), label),
+        Some("mobile") => vec!["cell"],
+        Some("home") => vec!["home"],
+        Some("work") => vec!["work"],
+        Some("main") => vec!["main"],
+        Some("home fax") => vec!["home", "fax"],
+        Some("work fax") => vec!["work", "fax"],
+        Some("pager") => vec!["pager"],
+        Some("other") => vec!["other"],
+        Some(_) => return (Vec::new(), label.or(kind)),
+    };
+    (types, label)
+}
+
+/// Maps vCard TYPE values back to a MobileSync field type
+fn sync_kind(types: &[String], label: Option<String>) -> (Option<String>, Option<String>) {
+    let has = |name: &str| types.iter().any(|t| t == name);
+    let kind = if has("fax") {
+        Some(if has("work") { "work fax" } else { "home fax" })
+    } else if has("cell") || has("mobile") {
+        Some("mobile")
+    } else if has("home") {
+        Some("home")
+    } else if has("work") {
+        Some("work")
+    } else if has("main") {
+        Some("main")
+    } else if has("pager") {
+        Some("pager")
+    } else if has("other") {
+        Some("other")
+    } else {
+        None
+    };
+    (kind.map(|kind| kind.to_string()), label)
+}
+
+/// Apple writes its built in labels as `_$!<Label>!$_`
+fn strip_label(label: &str) -> String {
+    label
+        .strip_prefix("_$!<")
+        .and_then(|label| label.strip_suffix(">!$_"))
+        .unwrap_or(label)
+        .to_string()
+}
+
+fn type_param(types: &[&str], version: VCardVersion) -> String {
+    if types.is_empty() {
+        return String::new();
+    }
+    format!(";TYPE={}", version.case(&types.join(",")))
+}
+
+/// A property line, with the group, name and parameter names upper cased where case doesn't matter
+struct ContentLine {
+    group: Option<String>,
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl ContentLine {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Gets the lower cased TYPE values, including the bare values vCard 2.1 allowed
+    fn types(&self) -> Vec<String> {
+        self.params
+            .iter()
+            .filter(|(key, _)| key == "TYPE")
+            .flat_map(|(_, value)| value.split(','))
+            .map(|value| value.trim().to_lowercase())
+            .collect()
+    }
+}
+
+fn parse_line(line: &str) -> Option<ContentLine> {
+    let mut quoted = false;
+    let mut colon = None;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ':' if !quoted => {
+                colon = Some(i);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let colon = colon?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+
+    let mut parts = split_unquoted(head, ';').into_iter();
+    let name = parts.next()?.to_uppercase();
+    let (group, name) = match name.split_once('.') {
+        Some((group, name)) => (Some(group.to_string()), name.to_string()),
+        None => (None, name),
+    };
+    if name.is_empty() {
+        return None;
+    }
+    let params = parts
+        .map(|param| match param.split_once('=') {
+            Some((key, value)) => (key.to_uppercase(), value.replace('"', "")),
+            None => ("TYPE".to_string(), param),
+        })
+        .collect();
+    Some(ContentLine {
+        group,
+        name,
+        params,
+        value: value.to_string(),
+    })
+}
+
+fn split_unquoted(value: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in value.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    parts.push(current);
+    parts
+}
+
+/// Joins folded lines back together and drops blank lines
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ if line.trim().is_empty() => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Appends a line, folding it so no line is longer than 75 bytes
+fn push_line(out: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+fn push_property(out: &mut String, name: &str, value: &Option<String>) {
+    if let Some(value) = value {
+        push_line(out, &format!("{}:{}", name, escape(value)));
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+fn join_components(parts: &[&Option<String>]) -> String {
+    parts
+        .iter()
+        .map(|part| part.as_deref().map(escape).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Splits a structured value on unescaped semicolons, mapping empty components to `None`
+fn split_components(value: &str) -> Vec<Option<String>> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                current.push(c);
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            ';' => parts.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    parts.push(current);
+    parts.iter().map(|part| non_empty(unescape(part))).collect()
+}
+
+fn non_empty(value: String) -> Option<String> {
+    if value.trim().is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Formats a date as `YYYYMMDD`, or `YYYY-MM-DD` if `separated`
+fn format_date(time: SystemTime, separated: bool) -> String {
+    let (year, month, day) = civil_from_days(unix_seconds(time).div_euclid(SECONDS_PER_DAY));
+    if separated {
+        format!("{:04}-{:02}-{:02}", year, month, day)
+    } else {
+        format!("{:04}{:02}{:02}", year, month, day)
+    }
+}
+
+/// Formats a time as a UTC iCalendar date-time, `YYYYMMDDTHHMMSSZ`
+fn format_date_time(time: SystemTime) -> String {
+    let seconds = unix_seconds(time);
+    let of_day = seconds.rem_euclid(SECONDS_PER_DAY);
+    format!(
+        "{}T{:02}{:02}{:02}Z",
+        format_date(time, false),
+        of_day / 3600,
+        of_day % 3600 / 60,
+        of_day % 60
+    )
+}
+
+/// Parses a date or a date-time in the basic or extended format.
+/// Returns the time and whether the value was a date alone.
+fn parse_date_time(value: &str) -> Option<(SystemTime, bool)> {
+    let value = value.trim();
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    let date: String = date.chars().filter(|c| *c != '-').collect();
+    if date.len() != 8 || !date.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let year: i64 = date[..4].parse().ok()?;
+    let month: u32 = date[4..6].parse().ok()?;
+    let day: u32 = date[6..].parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let mut seconds = days_from_civil(year, month, day) * SECONDS_PER_DAY;
+
+    if let Some(time) = time {
+        // Drop the UTC designator or offset, offsets aren't applied
+        let time: String = time
+            .chars()
+            .take_while(|c| c.is_ascii_digit() || *c == ':')
+            .filter(|c| *c != ':')
+            .collect();
+        if time.len() < 4 {
+            return None;
+        }
+        let hour: i64 = time[..2].parse().ok()?;
+        let minute: i64 = time[2..4].parse().ok()?;
+        let second: i64 = time.get(4..6).map_or(Some(0), |s| s.parse().ok())?;
+        seconds += hour * 3600 + minute * 60 + second;
+    }
+    Some((from_unix_seconds(seconds), time.is_none()))
+}
+
+/// Parses an iCalendar duration such as `PT1H30M` or `P1D`. Negative durations aren't supported.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim().strip_prefix('+').unwrap_or(value.trim());
+    let value = value.strip_prefix('P')?;
+    let mut seconds = 0;
+    let mut number = String::new();
+    let mut in_time = false;
+    for c in value.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => in_time = true,
+            unit => {
+                let amount: u64 = number.parse().ok()?;
+                number.clear();
+                seconds += amount
+                    * match (unit, in_time) {
+                        ('W', false) => 7 * 86400,
+                        ('D', false) => 86400,
+                        ('H', true) => 3600,
+                        ('M', true) => 60,
+                        ('S', true) => 1,
+                        _ => return None,
+                    };
+            }
+        }
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Some(Duration::from_secs(seconds))
+}
+
+fn unix_seconds(time: SystemTime) -> i64 {
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+fn from_unix_seconds(seconds: i64) -> SystemTime {
+    if seconds >= 0 {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds as u64)
+    } else {
+        SystemTime::UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs())
+    }
+}
+
+/// Counts the days from 1970-01-01 to a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The inverse of `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 15/06/1985, midnight UTC
+    const BIRTHDAY_1985: u64 = 487_641_600;
+
+    fn birthday() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(BIRTHDAY_1985)
+    }
+
+    fn utc(year: i64, month: u32, day: u32, hour: i64, minute: i64) -> SystemTime {
+        from_unix_seconds(
+            days_from_civil(year, month, day) * SECONDS_PER_DAY + hour * 3600 + minute * 60,
+        )
+    }
+
+    fn contact() -> Contact {
+        Contact {
+            id: "contact-1".to_string(),
+            first_name: Some("Ada".to_string()),
+            middle_name: Some("King".to_string()),
+            last_name: Some("Lovelace".to_string()),
+            nickname: Some("Countess".to_string()),
+            prefix: Some("Dr.".to_string()),
+            suffix: Some("Jr.".to_string()),
+            organization: Some("Analytical Engines, Ltd.".to_string()),
+            department: Some("R&D".to_string()),
+            job_title: Some("Programmer".to_string()),
+            notes: Some("First line; second\\line\nThird, and last".to_string()),
+            birthday: Some(birthday()),
+            phone_numbers: vec![
+                ContactField {
+                    kind: Some("mobile".to_string()),
+                    value: "+44 20 7946 0000".to_string(),
+                    ..Default::default()
+                },
+                ContactField {
+                    kind: Some("work fax".to_string()),
+                    value: "+44 20 7946 0001".to_string(),
+                    ..Default::default()
+                },
+                ContactField {
+                    label: Some("Lake house".to_string()),
+                    value: "+44 20 7946 0002".to_string(),
+                    ..Default::default()
+                },
+            ],
+            emails: vec![ContactField {
+                kind: Some("home".to_string()),
+                value: "ada@example.com".to_string(),
+                ..Default::default()
+            }],
+            urls: vec![ContactField {
+                value: "https://example.com/notes,1".to_string(),
+                ..Default::default()
+            }],
+            addresses: vec![ContactAddress {
+                kind: Some("home".to_string()),
+                street: Some("12 St James's Square".to_string()),
+                city: Some("London".to_string()),
+                postal_code: Some("SW1Y 4JH".to_string()),
+                country: Some("United Kingdom".to_string()),
+                country_code: Some("gb".to_string()),
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn calendar() -> Calendar {
+        Calendar {
+            id: "calendar-1".to_string(),
+            title: Some("Work, mostly".to_string()),
+        }
+    }
+
+    fn events() -> Vec<CalendarEvent> {
+        vec![
+            CalendarEvent {
+                id: "event-1".to_string(),
+                calendar_id: Some("calendar-1".to_string()),
+                summary: Some("Planning; round two".to_string()),
+                description: Some("Agenda:\nBudget, hiring".to_string()),
+                location: Some("Room 4".to_string()),
+                url: Some("https://example.com/meet?id=1,2".to_string()),
+                start: Some(utc(2024, 3, 5, 9, 30)),
+                end: Some(utc(2024, 3, 5, 10, 45)),
+                all_day: false,
+            },
+            CalendarEvent {
+                id: "event-2".to_string(),
+                calendar_id: Some("calendar-1".to_string()),
+                summary: Some("Offsite".to_string()),
+                start: Some(utc(2024, 2, 29, 0, 0)),
+                end: Some(utc(2024, 3, 1, 0, 0)),
+                all_day: true,
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn vcard_3_round_trips() {
+        let text = contact_to_vcard(&contact(), VCardVersion::V3);
+        assert!(text.contains("VERSION:3.0\r\n"));
+        assert!(text.contains("BDAY:1985-06-15\r\n"));
+        assert!(text.contains("EMAIL;TYPE=INTERNET,HOME:ada@example.com\r\n"));
+        assert_eq!(contacts_from_vcard(&text), Ok(vec![contact()]));
+    }
+
+    #[test]
+    fn vcard_4_round_trips() {
+        let text = contact_to_vcard(&contact(), VCardVersion::V4);
+        assert!(text.contains("VERSION:4.0\r\n"));
+        assert!(text.contains("BDAY:19850615\r\n"));
+        assert!(text.contains("TEL;TYPE=cell:+44 20 7946 0000\r\n"));
+        assert_eq!(contacts_from_vcard(&text), Ok(vec![contact()]));
+    }
+
+    #[test]
+    fn several_cards_round_trip() {
+        let mut second = contact();
+        second.id = "contact-2".to_string();
+        second.birthday = None;
+        let contacts = vec![contact(), second];
+        let text = contacts_to_vcard(&contacts, VCardVersion::V3);
+        assert_eq!(contacts_from_vcard(&text), Ok(contacts));
+    }
+
+    #[test]
+    fn apple_vcards_are_read() {
+        let text = "BEGIN:VCARD\r\n\
+                    VERSION:3.0\r\n\
+                    N:Appleseed;Johnny;;;\r\n\
+                    FN:Johnny Appleseed\r\n\
+                    item1.TEL;type=CELL;type=VOICE;type=pref:(408) 555-0100\r\n\
+                    item1.X-ABLabel:_$!<Mobile>!$_\r\n\
+                    item2.EMAIL;type=INTERNET:johnny@example.com\r\n\
+                    item2.X-ABLabel:School\r\n\
+                    TEL;TYPE=HOME,FAX:(408) 555-0101\r\n\
+                    BDAY;VALUE=date:1965-03-01\r\n\
+                    END:VCARD\r\n";
+        let contacts = contacts_from_vcard(text).unwrap();
+        assert_eq!(contacts.len(), 1);
+        let contact = &contacts[0];
+        assert_eq!(contact.id, "vcard-0");
+        assert_eq!(contact.first_name.as_deref(), Some("Johnny"));
+        assert_eq!(contact.last_name.as_deref(), Some("Appleseed"));
+        assert_eq!(contact.phone_numbers[0].kind.as_deref(), Some("mobile"));
+        assert_eq!(contact.phone_numbers[0].label.as_deref(), Some("Mobile"));
+        assert_eq!(contact.phone_numbers[1].kind.as_deref(), Some("home fax"));
+        assert_eq!(contact.emails[0].label.as_deref(), Some("School"));
+        assert_eq!(contact.birthday, Some(utc(1965, 3, 1, 0, 0)));
+    }
+
+    #[test]
+    fn cards_with_only_a_formatted_name_keep_it() {
+        let text = "BEGIN:VCARD\nVERSION:4.0\nFN:Cher\nEND:VCARD\n";
+        let contacts = contacts_from_vcard(text).unwrap();
+        assert_eq!(contacts[0].first_name.as_deref(), Some("Cher"));
+    }
+
+    #[test]
+    fn folded_lines_are_joined_and_unescaped() {
+        let text = "BEGIN:VCARD\r\n\
+                    VERSION:3.0\r\n\
+                    UID:folded\r\n\
+                    NOTE:One\\, two\\; three\\\\four\\nfive and a line that goes on lo\r\n \
+                    nger than seventy-five\r\n\
+                    \tbytes\r\n\
+                    END:VCARD\r\n";
+        let contacts = contacts_from_vcard(text).unwrap();
+        assert_eq!(
+            contacts[0].notes.as_deref(),
+            Some(
+                "One, two; three\\four\nfive and a line that goes on longer than seventy-five\
+                 bytes"
+            )
+        );
+    }
+
+    #[test]
+    fn long_lines_are_folded() {
+        let mut out = String::new();
+        let value = "é".repeat(60);
+        push_line(&mut out, &format!("NOTE:{}", value));
+        for line in out.split("\r\n") {
+            assert!(line.len() <= 75, "{} bytes: {:?}", line.len(), line);
+        }
+        assert_eq!(unfold(&out), vec![format!("NOTE:{}", value)]);
+    }
+
+    #[test]
+    fn escaping_round_trips() {
+        let value = "a\\b;c,d\ne";
+        assert_eq!(escape(value), "a\\\\b\\;c\\,d\\ne");
+        assert_eq!(unescape(&escape(value)), value);
+        assert_eq!(unescape("trailing\\"), "trailing\\");
+        assert_eq!(unescape("upper\\Ncase"), "upper\ncase");
+    }
+
+    #[test]
+    fn structured_values_split_on_unescaped_semicolons() {
+        assert_eq!(
+            split_components("Doe\\;Smith;Jane;;"),
+            vec![
+                Some("Doe;Smith".to_string()),
+                Some("Jane".to_string()),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_cards_are_rejected() {
+        let text = "BEGIN:VCARD\nVERSION:3.0\nFN:Nobody\n";
+        assert_eq!(
+            contacts_from_vcard(text),
+            Err(SyncFormatError::InvalidVCard)
+        );
+        assert_eq!(
+            contacts_from_vcard("FN:Nobody\n"),
+            Err(SyncFormatError::InvalidVCard)
+        );
+    }
+
+    #[test]
+    fn icalendar_round_trips() {
+        let text = calendar_to_icalendar(&calendar(), &events());
+        assert!(text.contains("DTSTART:20240305T093000Z\r\n"));
+        assert!(text.contains("DTSTART;VALUE=DATE:20240229\r\n"));
+        assert_eq!(calendar_from_icalendar(&text), Ok((calendar(), events())));
+    }
+
+    #[test]
+    fn durations_set_the_end() {
+        let text = "BEGIN:VCALENDAR\r\n\
+                    VERSION:2.0\r\n\
+                    BEGIN:VEVENT\r\n\
+                    UID:with-duration\r\n\
+                    DTSTART:20240102T100000Z\r\n\
+                    DURATION:PT1H30M\r\n\
+                    BEGIN:VALARM\r\n\
+                    TRIGGER:-PT15M\r\n\
+                    END:VALARM\r\n\
+                    END:VEVENT\r\n\
+                    END:VCALENDAR\r\n";
+        let (calendar, events) = calendar_from_icalendar(text).unwrap();
+        assert_eq!(calendar.id, "icalendar");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, "with-duration");
+        assert_eq!(events[0].start, Some(utc(2024, 1, 2, 10, 0)));
+        assert_eq!(events[0].end, Some(utc(2024, 1, 2, 11, 30)));
+        assert!(!events[0].all_day);
+        assert_eq!(events[0].calendar_id.as_deref(), Some("icalendar"));
+    }
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("PT1H30M"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("P1DT2H"), Some(Duration::from_secs(93600)));
+        assert_eq!(parse_duration("+P2W"), Some(Duration::from_secs(1_209_600)));
+        assert_eq!(parse_duration("PT45S"), Some(Duration::from_secs(45)));
+        assert_eq!(parse_duration("P1H"), None);
+        assert_eq!(parse_duration("PT5"), None);
+        assert_eq!(parse_duration("-PT5M"), None);
+        assert_eq!(parse_duration("1H"), None);
+    }
+
+    #[test]
+    fn all_day_events_are_read() {
+        let text = "BEGIN:VCALENDAR\n\
+                    BEGIN:VEVENT\n\
+                    UID:holiday\n\
+                    DTSTART;VALUE=DATE:20241225\n\
+                    DTEND;VALUE=DATE:20241226\n\
+                    END:VEVENT\n\
+                    BEGIN:VEVENT\n\
+                    UID:bare-date\n\
+                    DTSTART:19650301\n\
+                    END:VEVENT\n\
+                    END:VCALENDAR\n";
+        let (_, events) = calendar_from_icalendar(text).unwrap();
+        assert!(events[0].all_day);
+        assert_eq!(events[0].start, Some(utc(2024, 12, 25, 0, 0)));
+        assert_eq!(events[0].end, Some(utc(2024, 12, 26, 0, 0)));
+        assert!(events[1].all_day);
+        assert_eq!(events[1].start, Some(utc(1965, 3, 1, 0, 0)));
+    }
+
+    #[test]
+    fn mismatched_components_are_rejected() {
+        let text = "BEGIN:VCALENDAR\nBEGIN:VEVENT\nEND:VCALENDAR\n";
+        assert_eq!(
+            calendar_from_icalendar(text),
+            Err(SyncFormatError::InvalidICalendar)
+        );
+        let text = "BEGIN:VCALENDAR\nBEGIN:VEVENT\nDTSTART:yesterday\nEND:VEVENT\nEND:VCALENDAR\n";
+        assert_eq!(
+            calendar_from_icalendar(text),
+            Err(SyncFormatError::InvalidDate)
+        );
+    }
+
+    #[test]
+    fn dates_before_1970_are_formatted() {
+        let date = utc(1965, 3, 1, 12, 30);
+        assert_eq!(format_date(date, true), "1965-03-01");
+        assert_eq!(format_date_time(date), "19650301T123000Z");
+        assert_eq!(parse_date_time("19650301T123000Z"), Some((date, false)));
+        assert_eq!(parse_date_time("1965-03-01T12:30:00"), Some((date, false)));
+    }
+
+    #[test]
+    fn civil_days_convert_both_ways() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(days_from_civil(2000, 2, 29)), (2000, 2, 29));
+        assert_eq!(civil_from_days(days_from_civil(1900, 3, 1)), (1900, 3, 1));
+    }
+
+    #[test]
+    fn records_keep_birthdays_before_2001() {
+        let text = "BEGIN:VCARD\r\n\
+                    VERSION:3.0\r\n\
+                    UID:born-1985\r\n\
+                    N:Lovelace;Ada;;;\r\n\
+                    BDAY:1985-06-15\r\n\
+                    END:VCARD\r\n";
+        let records = records_from_vcard(text).unwrap();
+        let contacts = records.contacts();
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].birthday, Some(birthday()));
+        assert!(records_to_vcard(&records, VCardVersion::V3).contains("BDAY:1985-06-15\r\n"));
+    }
+
+    #[test]
+    fn records_round_trip_through_icalendar() {
+        let records =
+            records_from_icalendar(&calendar_to_icalendar(&calendar(), &events())).unwrap();
+        let files = records_to_icalendar(&records);
+        assert_eq!(files.len(), 1);
+        let (calendar_back, mut events_back) = calendar_from_icalendar(&files[0]).unwrap();
+        events_back.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(calendar_back, calendar());
+        assert_eq!(events_back, events());
+    }
+}
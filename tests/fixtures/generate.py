@@ -0,0 +1,174 @@
+#!/usr/bin/env python3
+# Regenerates the fixtures the unit tests read. Run from this directory.
+
+import datetime
+import hashlib
+import os
+import plistlib
+import shutil
+import sqlite3
+
+from cryptography.hazmat.primitives import hashes, padding
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+from cryptography.hazmat.primitives.kdf.pbkdf2 import PBKDF2HMAC
+from cryptography.hazmat.primitives.keywrap import aes_key_wrap
+
+PASSWORD = b"password"
+# Fixed keys and salts, so regenerating gives the same bytes
+SALT = bytes(range(20))
+DOUBLE_PROTECTION_SALT = bytes(range(20, 40))
+CLASS_KEYS = {3: bytes([3]) * 32, 4: bytes([4]) * 32}
+MANIFEST_DB_KEY = bytes([0xdb]) * 32
+
+
+def manifest_db(path, rows):
+    """Writes a Manifest.db with 512 byte pages, so a few rows already need interior and
+    overflow pages"""
+    if os.path.exists(path):
+        os.remove(path)
+    db = sqlite3.connect(path)
+    db.execute("PRAGMA page_size = 512")
+    db.execute(
+        "CREATE TABLE Files (fileID TEXT PRIMARY KEY, domain TEXT, relativePath TEXT, "
+        "flags INTEGER, file BLOB)"
+    )
+    db.executemany("INSERT INTO Files VALUES (?, ?, ?, ?, ?)", rows)
+    db.commit()
+    db.execute("VACUUM")
+    db.close()
+
+
+def sqlite_fixture():
+    rows = [
+        ("%040x" % i, "HomeDomain", "Library/File%d" % i, 1, None)
+        for i in range(40)
+    ]
+    # Too big for one 512 byte page, so it spills onto overflow pages
+    rows.append(("f" * 40, "HomeDomain", "Library/Large", 1, bytes(range(256)) * 8))
+    manifest_db("Manifest.db", rows)
+
+
+def encrypt(key, data):
+    """AES-256-CBC with a zero IV and PKCS#7 padding, as backups store files"""
+    padder = padding.PKCS7(128).padder()
+    data = padder.update(data) + padder.finalize()
+    encryptor = Cipher(algorithms.AES(key), modes.CBC(bytes(16))).encryptor()
+    return encryptor.update(data) + encryptor.finalize()
+
+
+def tlv(tag, value):
+    if isinstance(value, int):
+        value = value.to_bytes(4, "big")
+    return tag + len(value).to_bytes(4, "big") + value
+
+
+def keybag():
+    password = PBKDF2HMAC(hashes.SHA256(), 32, DOUBLE_PROTECTION_SALT, 10).derive(PASSWORD)
+    passcode_key = PBKDF2HMAC(hashes.SHA1(), 32, SALT, 10).derive(password)
+    data = tlv(b"VERS", 3) + tlv(b"TYPE", 1) + tlv(b"UUID", bytes(16)) + tlv(b"WRAP", 0)
+    data += tlv(b"SALT", SALT) + tlv(b"ITER", 10)
+    data += tlv(b"DPWT", 1) + tlv(b"DPIC", 10) + tlv(b"DPSL", DOUBLE_PROTECTION_SALT)
+    for protection_class, key in CLASS_KEYS.items():
+        data += tlv(b"UUID", bytes([protection_class]) * 16) + tlv(b"CLAS", protection_class)
+        data += tlv(b"WRAP", 2) + tlv(b"KTYP", 0)
+        data += tlv(b"WPKY", aes_key_wrap(passcode_key, key))
+    return data
+
+
+def wrapped_key(protection_class, key):
+    return protection_class.to_bytes(4, "little") + aes_key_wrap(
+        CLASS_KEYS[protection_class], key
+    )
+
+
+def metadata(size, mode, protection_class=None, key=None, target=None):
+    """An NSKeyedArchiver encoded MBFile"""
+    objects = ["$null"]
+    root = {"$class": plistlib.UID(0), "Size": size, "Mode": mode, "LastModified": 1600000000}
+    objects.append(root)
+    if protection_class is not None:
+        root["ProtectionClass"] = protection_class
+        root["EncryptionKey"] = plistlib.UID(len(objects))
+        objects.append({"NS.data": wrapped_key(protection_class, key)})
+    if target is not None:
+        root["Target"] = plistlib.UID(len(objects))
+        objects.append(target)
+    archive = {
+        "$version": 100000,
+        "$archiver": "NSKeyedArchiver",
+        "$top": {"root": plistlib.UID(1)},
+        "$objects": objects,
+    }
+    return plistlib.dumps(archive, fmt=plistlib.FMT_BINARY)
+
+
+def backup_fixture(path, encrypted):
+    shutil.rmtree(path, ignore_errors=True)
+    os.makedirs(path)
+    date = datetime.datetime(2022, 5, 1, 12, 0, 0)
+    entries = [
+        ("HomeDomain", "Library", 2, None),
+        ("HomeDomain", "Library/Notes.txt", 1, b"Hello from the backup\n"),
+        # Fills its last block exactly, so the padding is a whole block
+        ("HomeDomain", "Library/Block.bin", 1, bytes(range(32))),
+        ("HomeDomain", "Library/Link", 4, None),
+        ("CameraRollDomain", "Media/DCIM/IMG_0001.JPG", 1, b"\xff\xd8\xff\xe0 not a photo"),
+    ]
+    rows = []
+    for number, (domain, relative_path, flags, contents) in enumerate(entries):
+        file_id = hashlib.sha1(("%s-%s" % (domain, relative_path)).encode()).hexdigest()
+        mode = {1: 0o100644, 2: 0o40755, 4: 0o120755}[flags]
+        size = len(contents) if contents is not None else 0
+        target = "Notes.txt" if flags == 4 else None
+        if contents is not None and encrypted:
+            key = bytes([number]) * 32
+            blob = metadata(size, mode, 3, key, target)
+            contents = encrypt(key, contents)
+        else:
+            blob = metadata(size, mode, target=target)
+        rows.append((file_id, domain, relative_path, flags, blob))
+        if contents is not None:
+            os.makedirs(os.path.join(path, file_id[:2]), exist_ok=True)
+            with open(os.path.join(path, file_id[:2], file_id), "wb") as f:
+                f.write(contents)
+
+    database = os.path.join(path, "Manifest.db")
+    manifest_db(database, rows)
+    manifest = {
+        "IsEncrypted": encrypted,
+        "Version": "10.0",
+        "Date": date,
+        "Lockdown": {
+            "DeviceName": "Fixture iPhone",
+            "UniqueDeviceID": "00008030-0000000000000000",
+            "ProductVersion": "15.4",
+        },
+    }
+    if encrypted:
+        with open(database, "rb") as f:
+            plain = f.read()
+        with open(database, "wb") as f:
+            f.write(encrypt(MANIFEST_DB_KEY, plain))
+        manifest["BackupKeyBag"] = keybag()
+        manifest["ManifestKey"] = wrapped_key(4, MANIFEST_DB_KEY)
+    with open(os.path.join(path, "Manifest.plist"), "wb") as f:
+        plistlib.dump(manifest, f, fmt=plistlib.FMT_BINARY)
+    with open(os.path.join(path, "Status.plist"), "wb") as f:
+        plistlib.dump({"IsFullBackup": True, "Date": date, "Version": "3.3"}, f)
+    # Info.plist is left out of the encrypted backup, the reader falls back to Lockdown
+    if not encrypted:
+        with open(os.path.join(path, "Info.plist"), "wb") as f:
+            plistlib.dump(
+                {
+                    "Device Name": "Fixture iPhone",
+                    "Target Identifier": "00008030-0000000000000000",
+                    "Product Version": "15.4",
+                },
+                f,
+            )
+
+
+if __name__ == "__main__":
+    sqlite_fixture()
+    backup_fixture("backups/plain", False)
+    backup_fixture("backups/encrypted", True)
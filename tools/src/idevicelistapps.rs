@@ -1,9 +1,8 @@
 // jkcoxson
 // This one isn't an official tool, but something I think is necessary
 
-use plist_plus::Plist;
 use rusty_libimobiledevice::idevice;
-use rusty_libimobiledevice::services::instproxy::InstProxyClient;
+use rusty_libimobiledevice::services::instproxy::{ApplicationType, InstallOptions};
 
 fn main() {
     const VERSION: &str = "0.1.0";
@@ -77,14 +76,10 @@ fn main() {
         }
     };
 
-    let client_opts = InstProxyClient::create_return_attributes(
-        vec![("ApplicationType".to_string(), Plist::new_string("Any"))],
-        vec![
-            "CFBundleIdentifier".to_string(),
-            "CFBundleDisplayName".to_string(),
-        ],
-    );
-    let lookup_results = match instproxy_client.lookup(vec![], Some(client_opts)) {
+    let options = InstallOptions::new()
+        .application_type(ApplicationType::Any)
+        .return_attributes(["CFBundleIdentifier", "CFBundleDisplayName"]);
+    let apps = match instproxy_client.lookup_apps(vec![], &options) {
         Ok(apps) => {
             println!("Successfully looked up apps");
             apps
@@ -95,24 +90,14 @@ fn main() {
         }
     };
 
-    for app in lookup_results {
-        let id = app
-            .plist
-            .dict_get_item("CFBundleIdentifier")
-            .unwrap()
-            .get_string_val()
-            .unwrap();
-        if id.contains("com.apple") && !all {
+    for app in apps {
+        if app.bundle_id.contains("com.apple") && !all {
             continue;
         }
         println!(
             "{}: {}",
-            app.plist
-                .dict_get_item("CFBundleDisplayName")
-                .unwrap()
-                .get_string_val()
-                .unwrap(),
-            id
+            app.display_name.unwrap_or_default(),
+            app.bundle_id
         );
     }
 }